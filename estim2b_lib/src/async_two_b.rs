@@ -0,0 +1,222 @@
+use async_trait::async_trait;
+use std::panic;
+use std::sync::{Arc, Mutex};
+
+use crate::*;
+
+/// Async counterpart of `TwoB` for use inside an async runtime.
+#[async_trait]
+pub trait AsyncTwoB: Send + Sync {
+    async fn execute(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        use TwoBCommand::*;
+        match command {
+            RefreshState => self.refresh_state().await,
+            Reset => self.reset().await,
+            Kill => self.kill().await,
+            SetJoinedChannels(enable) => self.set_joined_channels(enable).await,
+            SetMode(mode) => self.set_mode(mode).await,
+            SetPower(power) => self.set_power(power).await,
+            SetMap(map) => self.set_map(map).await,
+            SetBias(bias) => self.set_bias(bias).await,
+            SetRamp(ramp) => self.set_ramp(ramp).await,
+            SetWarp(warp) => self.set_warp(warp).await,
+            IncrementChannel(channel) => self.increment_channel(channel).await,
+            DecrementChannel(channel) => self.decrement_channel(channel).await,
+            SetChannel(channel, value) => self.set_channel(channel, value).await,
+        }
+    }
+
+    async fn refresh_state(&mut self) -> Result<(), TwoBError>;
+
+    async fn reset(&mut self) -> Result<(), TwoBError>;
+
+    async fn kill(&mut self) -> Result<(), TwoBError>;
+
+    async fn set_joined_channels(&mut self, enable: bool) -> Result<(), TwoBError>;
+
+    async fn set_mode(&mut self, mode: TwoBMode) -> Result<(), TwoBError>;
+
+    async fn set_power(&mut self, power: TwoBPower) -> Result<(), TwoBError>;
+
+    async fn set_map(&mut self, map: TwoBMap) -> Result<(), TwoBError>;
+
+    async fn set_bias(&mut self, bias: TwoBBias) -> Result<(), TwoBError>;
+
+    async fn set_ramp(&mut self, ramp: TwoBRamp) -> Result<(), TwoBError>;
+
+    async fn set_warp(&mut self, warp: TwoBWarp) -> Result<(), TwoBError>;
+
+    async fn increment_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError>;
+
+    async fn decrement_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError>;
+
+    async fn set_channel(&mut self, channel: TwoBChannel, value: u8) -> Result<(), TwoBError>;
+
+    async fn set_state(&mut self, state: TwoBState) -> Result<(), TwoBError>;
+
+    async fn get_state(&self) -> TwoBState;
+
+    async fn get_mode(&self) -> TwoBMode {
+        self.get_state().await.mode
+    }
+
+    async fn get_power(&self) -> TwoBPower {
+        self.get_state().await.power
+    }
+
+    async fn get_bias(&self) -> TwoBBias {
+        self.get_state().await.bias
+    }
+
+    async fn get_joined_channels(&self) -> bool {
+        self.get_state().await.joined_channels
+    }
+
+    async fn get_map(&self) -> TwoBMap {
+        self.get_state().await.map
+    }
+
+    async fn get_ramp(&self) -> TwoBRamp {
+        self.get_state().await.ramp
+    }
+
+    async fn get_warp(&self) -> TwoBWarp {
+        self.get_state().await.warp
+    }
+
+    async fn get_battery(&self) -> u16 {
+        self.get_state().await.battery
+    }
+
+    async fn get_channel(&self, channel: TwoBChannel) -> u8 {
+        let state = self.get_state().await;
+        match channel {
+            TwoBChannel::A => state.channel_a,
+            TwoBChannel::B => state.channel_b,
+            TwoBChannel::C => state.channel_c,
+            TwoBChannel::D => state.channel_d,
+        }
+    }
+
+    async fn get_version(&self) -> String;
+
+    async fn get_firmware_version(&self) -> Result<FirmwareVersion, TwoBError> {
+        self.get_version().await.parse()
+    }
+
+    async fn get_capabilities(&self) -> Capabilities {
+        self.get_firmware_version()
+            .await
+            .map(|version| Capabilities::for_version(&version))
+            .unwrap_or_default()
+    }
+}
+
+/// Makes a blocking `TwoB` usable as `AsyncTwoB`. Every call runs on tokio's blocking
+/// thread pool, so slow serial I/O never stalls the async executor.
+#[derive(Clone)]
+pub struct BlockingAdapter {
+    device: Arc<Mutex<Box<dyn TwoB>>>,
+}
+
+impl BlockingAdapter {
+    pub fn new<T: TwoB + 'static>(device: T) -> Self {
+        Self::from_box(Box::new(device))
+    }
+
+    pub fn from_box(device: Box<dyn TwoB>) -> Self {
+        BlockingAdapter {
+            device: Arc::new(Mutex::new(device)),
+        }
+    }
+
+    async fn call<R, F>(&self, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut dyn TwoB) -> R + Send + 'static,
+    {
+        let device = self.device.clone();
+        tokio::task::spawn_blocking(move || f(device.lock().unwrap().as_mut()))
+            .await
+            .unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
+    }
+}
+
+#[async_trait]
+impl AsyncTwoB for BlockingAdapter {
+    async fn execute(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.execute(command)).await
+    }
+
+    async fn refresh_state(&mut self) -> Result<(), TwoBError> {
+        self.call(|two_b| two_b.refresh_state()).await
+    }
+
+    async fn reset(&mut self) -> Result<(), TwoBError> {
+        self.call(|two_b| two_b.reset()).await
+    }
+
+    async fn kill(&mut self) -> Result<(), TwoBError> {
+        self.call(|two_b| two_b.kill()).await
+    }
+
+    async fn set_joined_channels(&mut self, enable: bool) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.set_joined_channels(enable))
+            .await
+    }
+
+    async fn set_mode(&mut self, mode: TwoBMode) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.set_mode(mode)).await
+    }
+
+    async fn set_power(&mut self, power: TwoBPower) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.set_power(power)).await
+    }
+
+    async fn set_map(&mut self, map: TwoBMap) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.set_map(map)).await
+    }
+
+    async fn set_bias(&mut self, bias: TwoBBias) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.set_bias(bias)).await
+    }
+
+    async fn set_ramp(&mut self, ramp: TwoBRamp) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.set_ramp(ramp)).await
+    }
+
+    async fn set_warp(&mut self, warp: TwoBWarp) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.set_warp(warp)).await
+    }
+
+    async fn increment_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.increment_channel(channel))
+            .await
+    }
+
+    async fn decrement_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.decrement_channel(channel))
+            .await
+    }
+
+    async fn set_channel(&mut self, channel: TwoBChannel, value: u8) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.set_channel(channel, value))
+            .await
+    }
+
+    async fn set_state(&mut self, state: TwoBState) -> Result<(), TwoBError> {
+        self.call(move |two_b| two_b.set_state(state)).await
+    }
+
+    async fn get_state(&self) -> TwoBState {
+        self.call(|two_b| two_b.get_state()).await
+    }
+
+    async fn get_version(&self) -> String {
+        self.call(|two_b| two_b.get_version()).await
+    }
+
+    async fn get_capabilities(&self) -> Capabilities {
+        self.call(|two_b| two_b.get_capabilities()).await
+    }
+}
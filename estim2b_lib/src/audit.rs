@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::*;
+
+/// `prev_hash` of the first record.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Front-end a command came from.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Origin {
+    #[default]
+    Library,
+    Http {
+        address: Option<String>,
+        /// `token_fingerprint` of the token the client sent, never the token itself.
+        token: Option<String>,
+    },
+    Python,
+    Cli,
+    /// A signal sent to the process, such as SIGUSR1.
+    Signal,
+    Script(String),
+    /// Sent by the handle itself, e.g. "watchdog", "battery", "session", "slew" or
+    /// "poller".
+    Automatic(String),
+    /// A command merged from the commands of several origins.
+    Coalesced(Vec<Origin>),
+}
+
+/// Short, irreversible identifier of `token` to tell clients apart in the log.
+pub fn token_fingerprint(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))[..16].into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum AuditedAction {
+    Commands(Vec<TwoBCommand>),
+    SetState(TwoBState),
+    /// A `call` on the device, which can do anything.
+    Call,
+    EmergencyStop(StopSource),
+    ResetEmergencyStop {
+        reason: String,
+    },
+}
+
+/// A line of the audit log. `hash` covers every other field, including the hash of
+/// the previous record, so editing, inserting or removing a record breaks the chain.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub timestamp: SystemTime,
+    pub origin: Origin,
+    pub action: AuditedAction,
+    /// State of the device once the action was carried out.
+    pub state: TwoBState,
+    pub error: Option<TwoBError>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditRecord {
+    fn digest(&self) -> String {
+        let content = serde_json::to_string(&(
+            self.seq,
+            self.timestamp,
+            &self.origin,
+            &self.action,
+            &self.state,
+            &self.error,
+            &self.prev_hash,
+        ))
+        .expect("Audit records serialize");
+        hex(&Sha256::digest(content.as_bytes()))
+    }
+}
+
+/// Append-only, hash-chained log of the actions carried out on a device, one JSON
+/// record per line.
+///
+/// Only complete lines count: a line cut off by a crash is left out when reading. The
+/// chain can't tell that records were cut off at the end, so keep a copy of the
+/// latest hash elsewhere to detect that.
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    next_seq: u64,
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Opens the log at `path`, creating it if needed. Fails if the existing records
+    /// don't verify, so a tampered log is never extended.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TwoBError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let content = fs::read_to_string(&path)?;
+        let complete = complete_lines(&content);
+        let records = verify_chain(parse(complete)?)?;
+        // A line cut off by a crash would run into the next record.
+        if complete.len() < content.len() {
+            file.set_len(complete.len() as u64)?;
+        }
+        let (next_seq, last_hash) = match records.last() {
+            Some(last) => (last.seq + 1, last.hash.clone()),
+            None => (0, GENESIS.into()),
+        };
+        Ok(AuditLog {
+            path,
+            file,
+            next_seq,
+            last_hash,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(
+        &mut self,
+        origin: Origin,
+        action: AuditedAction,
+        state: TwoBState,
+        error: Option<TwoBError>,
+    ) -> Result<AuditRecord, TwoBError> {
+        let mut record = AuditRecord {
+            seq: self.next_seq,
+            timestamp: SystemTime::now(),
+            origin,
+            action,
+            state,
+            error,
+            prev_hash: self.last_hash.clone(),
+            hash: String::new(),
+        };
+        record.hash = record.digest();
+        let mut line =
+            serde_json::to_string(&record).map_err(|e| TwoBError::ParserError(e.to_string()))?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.next_seq += 1;
+        self.last_hash = record.hash.clone();
+        Ok(record)
+    }
+
+    /// Reads the complete records without verifying them.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<AuditRecord>, TwoBError> {
+        parse(complete_lines(&fs::read_to_string(path)?))
+    }
+
+    /// Reads the records and checks the hash chain, failing at the first record that
+    /// was altered, inserted or removed.
+    pub fn verify<P: AsRef<Path>>(path: P) -> Result<Vec<AuditRecord>, TwoBError> {
+        verify_chain(Self::read(path)?)
+    }
+}
+
+/// `content` up to and including its last line break.
+fn complete_lines(content: &str) -> &str {
+    content.rfind('\n').map_or("", |end| &content[..=end])
+}
+
+fn parse(content: &str) -> Result<Vec<AuditRecord>, TwoBError> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| TwoBError::ParserError(format!("Audit log line {}: {}", i + 1, e)))
+        })
+        .collect()
+}
+
+fn verify_chain(records: Vec<AuditRecord>) -> Result<Vec<AuditRecord>, TwoBError> {
+    let mut prev_hash = GENESIS;
+    for (seq, record) in records.iter().enumerate() {
+        if record.seq != seq as u64 || record.prev_hash != prev_hash {
+            return Err(TwoBError::ParserError(format!(
+                "Audit record {} doesn't follow the one before",
+                seq
+            )));
+        }
+        if record.digest() != record.hash {
+            return Err(TwoBError::ParserError(format!(
+                "Audit record {} was altered",
+                seq
+            )));
+        }
+        prev_hash = &record.hash;
+    }
+    Ok(records)
+}
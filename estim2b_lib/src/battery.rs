@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+/// What powers the 2B.
+#[derive(Clone, Copy, Debug, Default, Display, Eq, PartialEq, Serialize, Deserialize)]
+pub enum PowerSupply {
+    #[default]
+    BatteryPack,
+    /// A mains adapter doesn't run down, so no percentage is estimated.
+    Mains,
+}
+
+/// Turns the raw battery value of the status line into volts and a charge estimate.
+///
+/// The 2B doesn't document what its battery value means, so there is no default
+/// calibration: measure the supply of your box against the value it reports.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatteryModel {
+    #[serde(default)]
+    pub supply: PowerSupply,
+    pub volts_per_count: f64,
+    /// Voltage the box browns out at.
+    pub empty_volts: f64,
+    pub full_volts: f64,
+}
+
+impl BatteryModel {
+    pub fn volts(&self, raw: u16) -> f64 {
+        f64::from(raw) * self.volts_per_count
+    }
+
+    /// Estimated charge in percent, `None` on mains.
+    pub fn percentage(&self, raw: u16) -> Option<f64> {
+        if self.supply == PowerSupply::Mains {
+            return None;
+        }
+        let charge = (self.volts(raw) - self.empty_volts) / (self.full_volts - self.empty_volts);
+        Some((charge * 100.0).clamp(0.0, 100.0))
+    }
+}
+
+/// What happens once the charge falls to a threshold.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum BatteryAction {
+    /// Only report it with a `BatteryLow` event.
+    Warn,
+    /// Lower channel A and B to at most `max_level`.
+    LowerLevels {
+        max_level: u8,
+    },
+    Kill,
+}
+
+impl BatteryAction {
+    /// The commands carrying out the action on a device in `state`.
+    pub fn commands(&self, state: &TwoBState) -> Vec<TwoBCommand> {
+        match *self {
+            BatteryAction::Warn => Vec::new(),
+            BatteryAction::LowerLevels { max_level } => {
+                TwoBCommand::lower_levels(state, max_level, max_level)
+            }
+            BatteryAction::Kill => vec![TwoBCommand::Kill],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BatteryThreshold {
+    pub percent: u8,
+    pub action: BatteryAction,
+}
+
+/// Everything but the model can be left out of the JSON and takes the value of
+/// `BatteryConfig::new`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatteryConfig {
+    pub model: BatteryModel,
+    #[serde(default = "default_thresholds")]
+    pub thresholds: Vec<BatteryThreshold>,
+    /// Percent the charge has to rise above a threshold before it can trigger again.
+    #[serde(default = "default_hysteresis")]
+    pub hysteresis: u8,
+    /// Shortest time between two samples.
+    #[serde(default = "default_sample_interval")]
+    pub sample_interval: Duration,
+    /// Samples the discharge rate is estimated from.
+    #[serde(default = "default_window")]
+    pub window: usize,
+}
+
+fn default_thresholds() -> Vec<BatteryThreshold> {
+    vec![
+        BatteryThreshold {
+            percent: 20,
+            action: BatteryAction::Warn,
+        },
+        BatteryThreshold {
+            percent: 10,
+            action: BatteryAction::LowerLevels { max_level: 20 },
+        },
+        BatteryThreshold {
+            percent: 3,
+            action: BatteryAction::Kill,
+        },
+    ]
+}
+
+fn default_hysteresis() -> u8 {
+    5
+}
+
+fn default_sample_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_window() -> usize {
+    30
+}
+
+impl BatteryConfig {
+    /// Warns at 20 %, lowers channel A and B to 20 at 10 % and kills at 3 %.
+    pub fn new(model: BatteryModel) -> Self {
+        BatteryConfig {
+            model,
+            thresholds: default_thresholds(),
+            hysteresis: default_hysteresis(),
+            sample_interval: default_sample_interval(),
+            window: default_window(),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, TwoBError> {
+        let config: Self =
+            serde_json::from_str(json).map_err(|e| TwoBError::ParserError(e.to_string()))?;
+        if config.model.full_volts <= config.model.empty_volts {
+            return Err(TwoBError::ParserError(format!(
+                "full_volts {} has to be above empty_volts {}",
+                config.model.full_volts, config.model.empty_volts
+            )));
+        }
+        Ok(config)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, TwoBError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatteryStatus {
+    pub supply: PowerSupply,
+    pub raw: u16,
+    pub volts: f64,
+    pub percent: Option<f64>,
+    /// Percent lost per hour, estimated from the recent samples. Negative while
+    /// charging.
+    pub discharge_rate: Option<f64>,
+    /// Time until the charge reaches 0 at the current discharge rate.
+    pub remaining: Option<Duration>,
+}
+
+/// Discharge rates below this, in percent per hour, are taken as a steady charge, e.g.
+/// the rounding noise of a constant reading, and give no remaining time.
+const MIN_DISCHARGE_RATE: f64 = 0.01;
+
+/// Samples the battery value, estimates how fast it runs down and reports thresholds
+/// the charge fell to.
+pub struct BatteryMonitor {
+    config: BatteryConfig,
+    samples: VecDeque<(Instant, u16)>,
+    /// Thresholds that triggered and haven't been rearmed yet, by index.
+    triggered: Vec<bool>,
+}
+
+impl BatteryMonitor {
+    pub fn new(config: BatteryConfig) -> Self {
+        BatteryMonitor {
+            triggered: vec![false; config.thresholds.len()],
+            config,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &BatteryConfig {
+        &self.config
+    }
+
+    /// Keeps the samples, as long as they were taken with the same model.
+    pub fn set_config(&mut self, config: BatteryConfig) {
+        if config.model != self.config.model {
+            self.samples.clear();
+        }
+        self.triggered = vec![false; config.thresholds.len()];
+        self.config = config;
+    }
+
+    /// Records `raw` unless the last sample is younger than the sample interval and
+    /// returns the thresholds the charge fell to with it.
+    pub fn record(&mut self, raw: u16, now: Instant) -> Vec<BatteryThreshold> {
+        if let Some((last, _)) = self.samples.back() {
+            if now.saturating_duration_since(*last) < self.config.sample_interval {
+                return Vec::new();
+            }
+        }
+        self.samples.push_back((now, raw));
+        while self.samples.len() > self.config.window.max(2) {
+            self.samples.pop_front();
+        }
+        let percent = match self.config.model.percentage(raw) {
+            Some(percent) => percent,
+            None => {
+                self.triggered.iter_mut().for_each(|t| *t = false);
+                return Vec::new();
+            }
+        };
+        let mut crossed = Vec::new();
+        for (threshold, triggered) in self.config.thresholds.iter().zip(&mut self.triggered) {
+            let level = f64::from(threshold.percent);
+            if !*triggered && percent <= level {
+                *triggered = true;
+                crossed.push(*threshold);
+            } else if *triggered && percent > level + f64::from(self.config.hysteresis) {
+                *triggered = false;
+            }
+        }
+        crossed
+    }
+
+    /// Percent per hour from a least squares fit over the samples.
+    fn discharge_rate(&self) -> Option<f64> {
+        let (first, _) = *self.samples.front()?;
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .map(|(at, raw)| {
+                let hours = at.duration_since(first).as_secs_f64() / 3600.0;
+                self.config
+                    .model
+                    .percentage(*raw)
+                    .map(|percent| (hours, percent))
+            })
+            .collect::<Option<_>>()?;
+        if points.len() < 2 {
+            return None;
+        }
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_p = points.iter().map(|(_, p)| p).sum::<f64>() / n;
+        let covariance: f64 = points
+            .iter()
+            .map(|(t, p)| (t - mean_t) * (p - mean_p))
+            .sum();
+        let variance: f64 = points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+        if variance == 0.0 {
+            return None;
+        }
+        Some(-covariance / variance)
+    }
+
+    /// Status of the last sample, `None` before the first one.
+    pub fn status(&self) -> Option<BatteryStatus> {
+        let (_, raw) = *self.samples.back()?;
+        let model = &self.config.model;
+        let percent = model.percentage(raw);
+        let discharge_rate = self.discharge_rate();
+        let remaining = match (percent, discharge_rate) {
+            (Some(percent), Some(rate)) if rate >= MIN_DISCHARGE_RATE => {
+                Duration::try_from_secs_f64(percent / rate * 3600.0).ok()
+            }
+            _ => None,
+        };
+        Some(BatteryStatus {
+            supply: model.supply,
+            raw,
+            volts: model.volts(raw),
+            percent,
+            discharge_rate,
+            remaining,
+        })
+    }
+}
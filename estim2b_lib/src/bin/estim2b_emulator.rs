@@ -0,0 +1,28 @@
+use estim2b_lib::*;
+use std::net::TcpListener;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::Duration;
+
+/// Serves the emulated 2B on a pseudo-terminal, or as a raw TCP serial bridge when an
+/// address like `127.0.0.1:2000` is given.
+fn main() -> Result<(), TwoBError> {
+    if let Some(address) = std::env::args().nth(1) {
+        let listener = TcpListener::bind(&address)?;
+        println!("Emulated 2B listening on tcp://{}", listener.local_addr()?);
+        let running = AtomicBool::new(true);
+        let mut emulator = Emulator::new()?;
+        for stream in listener.incoming() {
+            // The state is kept when a client disconnects, like a 2B behind a bridge.
+            if let Err(e) = emulator.serve(&mut stream?, &running) {
+                eprintln!("Client disconnected: {:?}", e);
+            }
+        }
+        return Ok(());
+    }
+    let pty = Emulator::new()?.spawn_pty()?;
+    println!("Emulated 2B listening on {}", pty.path());
+    loop {
+        thread::sleep(Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::*;
+
+/// A single command of the 2B serial protocol.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TwoBCommand {
+    RefreshState,
+    Reset,
+    Kill,
+    SetJoinedChannels(bool),
+    SetMode(TwoBMode),
+    SetPower(TwoBPower),
+    SetMap(TwoBMap),
+    SetBias(TwoBBias),
+    SetRamp(TwoBRamp),
+    SetWarp(TwoBWarp),
+    IncrementChannel(TwoBChannel),
+    DecrementChannel(TwoBChannel),
+    SetChannel(TwoBChannel, u8),
+}
+
+impl TwoBCommand {
+    /// Highest level of channels A to D the protocol accepts.
+    pub const MAX_LEVEL: u8 = 100;
+
+    /// Commands lowering channel A to at most `a` and channel B to at most `b` on a
+    /// device in `state`.
+    pub(crate) fn lower_levels(state: &TwoBState, a: u8, b: u8) -> Vec<TwoBCommand> {
+        vec![
+            TwoBCommand::SetChannel(TwoBChannel::A, state.channel_a.min(a)),
+            TwoBCommand::SetChannel(TwoBChannel::B, state.channel_b.min(b)),
+        ]
+    }
+
+    /// Rejects commands the 2B can't take, such as levels above `MAX_LEVEL`.
+    pub fn validate(&self) -> Result<(), TwoBError> {
+        match *self {
+            TwoBCommand::SetChannel(channel, value) if value > Self::MAX_LEVEL => {
+                Err(TwoBError::Unsupported(format!(
+                    "Level {} for channel {} is outside 0 to {}",
+                    value,
+                    channel,
+                    Self::MAX_LEVEL
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Wire representation without the terminating `\r`.
+    pub fn encode(&self) -> String {
+        use TwoBCommand::*;
+        match *self {
+            RefreshState => "V".into(),
+            Reset => "E".into(),
+            Kill => "K".into(),
+            SetJoinedChannels(enable) => format!("J{}", if enable { 1 } else { 0 }),
+            SetMode(mode) => format!("M{}", u8::from(mode)),
+            SetPower(power) => power.value().to_string(),
+            SetMap(map) => format!("O{}", u8::from(map)),
+            SetBias(bias) => format!("Q{}", u8::from(bias)),
+            SetRamp(ramp) => format!("R{}", u8::from(ramp)),
+            SetWarp(warp) => format!("W{}", u8::from(warp)),
+            IncrementChannel(channel) => format!("{}+", channel.value()),
+            DecrementChannel(channel) => format!("{}-", channel.value()),
+            SetChannel(channel, value) => format!("{}{}", channel.value(), value),
+        }
+    }
+
+    /// Whether sending the command twice has the same effect as sending it once.
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(
+            self,
+            TwoBCommand::IncrementChannel(_) | TwoBCommand::DecrementChannel(_)
+        )
+    }
+
+    /// Parses and validates a wire command, with or without the terminating `\r`.
+    pub fn decode(s: &str) -> Result<Self, TwoBError> {
+        let command = Self::parse(s)?;
+        command.validate()?;
+        Ok(command)
+    }
+
+    fn parse(s: &str) -> Result<Self, TwoBError> {
+        use TwoBCommand::*;
+        let s = s.trim_end_matches(['\r', '\n']);
+        let mut chars = s.chars();
+        let head = chars
+            .next()
+            .ok_or_else(|| TwoBError::ParserError("Empty command".into()))?;
+        let argument = chars.as_str();
+        let no_argument = |command: TwoBCommand| {
+            if argument.is_empty() {
+                Ok(command)
+            } else {
+                Err(TwoBError::ParserError(format!(
+                    "Command '{}' takes no argument",
+                    head
+                )))
+            }
+        };
+        match head {
+            'V' => no_argument(RefreshState),
+            'E' => no_argument(Reset),
+            'K' => no_argument(Kill),
+            'H' => no_argument(SetPower(TwoBPower::HIGH)),
+            'L' => no_argument(SetPower(TwoBPower::LOW)),
+            'Y' => no_argument(SetPower(TwoBPower::DYNAMIC)),
+            'J' => match argument {
+                "0" => Ok(SetJoinedChannels(false)),
+                "1" => Ok(SetJoinedChannels(true)),
+                _ => Err(TwoBError::ParserError(format!(
+                    "Invalid joined channels flag '{}'",
+                    argument
+                ))),
+            },
+            'M' => Ok(SetMode(TwoBMode::try_from(number(argument)?)?)),
+            'O' => Ok(SetMap(TwoBMap::try_from(number(argument)?)?)),
+            'Q' => Ok(SetBias(TwoBBias::try_from(number(argument)?)?)),
+            'R' => Ok(SetRamp(TwoBRamp::try_from(number(argument)?)?)),
+            'W' => Ok(SetWarp(TwoBWarp::try_from(number(argument)?)?)),
+            'A'..='D' => {
+                let channel = TwoBChannel::from_str(&head.to_string())?;
+                match argument {
+                    "+" => Ok(IncrementChannel(channel)),
+                    "-" => Ok(DecrementChannel(channel)),
+                    value => Ok(SetChannel(channel, number(value)?)),
+                }
+            }
+            _ => Err(TwoBError::ParserError(format!("Unknown command '{}'", s))),
+        }
+    }
+}
+
+fn number(argument: &str) -> Result<u8, TwoBError> {
+    if argument.is_empty() || !argument.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(TwoBError::ParserError(format!(
+            "Invalid numeric argument '{}'",
+            argument
+        )));
+    }
+    Ok(argument.parse()?)
+}
+
+impl fmt::Display for TwoBCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.encode())
+    }
+}
+
+impl FromStr for TwoBCommand {
+    type Err = TwoBError;
+    fn from_str(s: &str) -> Result<Self, TwoBError> {
+        Self::decode(s)
+    }
+}
@@ -0,0 +1,234 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+use crate::*;
+
+/// Identifies a proposal held by `Confirmations`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ProposalId(pub u64);
+
+/// When a change of channel A or B has to be confirmed before it is applied.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfirmationConfig {
+    /// Level above which raising channel A or B has to be confirmed.
+    pub max_level: Option<u8>,
+    /// Rise of channel A or B in a single change above which it has to be confirmed.
+    pub max_delta: Option<u8>,
+    /// Time a proposal waits for confirmation.
+    pub expiry: Duration,
+    /// Resolved proposals kept for auditing.
+    pub history: usize,
+}
+
+impl Default for ConfirmationConfig {
+    fn default() -> Self {
+        ConfirmationConfig {
+            max_level: None,
+            max_delta: None,
+            expiry: Duration::from_secs(60),
+            history: 100,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ProposedChange {
+    Commands(Vec<TwoBCommand>),
+    State(TwoBState),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Proposal {
+    pub id: ProposalId,
+    pub change: ProposedChange,
+    pub proposed_by: String,
+    pub proposed_at: SystemTime,
+    pub expires_at: SystemTime,
+}
+
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ProposalOutcome {
+    Confirmed,
+    Cancelled,
+    Expired,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ResolvedProposal {
+    pub proposal: Proposal,
+    pub outcome: ProposalOutcome,
+    /// Who confirmed or cancelled it, `None` if it expired.
+    pub resolved_by: Option<String>,
+    pub resolved_at: SystemTime,
+}
+
+/// Holds changes raising channel A or B beyond the thresholds of its config until they
+/// are confirmed, cancelled or expire, and keeps the recent outcomes.
+///
+/// Who may confirm is up to the caller; applying a confirmed change as well.
+pub struct Confirmations {
+    config: ConfirmationConfig,
+    next_id: u64,
+    pending: Vec<Proposal>,
+    history: VecDeque<ResolvedProposal>,
+}
+
+impl Confirmations {
+    pub fn new(config: ConfirmationConfig) -> Self {
+        Confirmations {
+            config,
+            next_id: 1,
+            pending: Vec::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &ConfirmationConfig {
+        &self.config
+    }
+
+    /// Applies to proposals made from now on.
+    pub fn set_config(&mut self, config: ConfirmationConfig) {
+        self.config = config;
+    }
+
+    fn exceeds(&self, level: u8, start: u8) -> bool {
+        level > start
+            && (self.config.max_level.is_some_and(|max| level > max)
+                || self.config.max_delta.is_some_and(|max| level - start > max))
+    }
+
+    /// Whether `change` takes channel A or B of a device in `state` beyond the
+    /// thresholds at any point. Rises are measured from the lowest level the channel
+    /// had during the change, so several steps in one change count together. Joining
+    /// the channels raises B to A.
+    pub fn needs_confirmation(&self, change: &ProposedChange, state: &TwoBState) -> bool {
+        use TwoBCommand::*;
+        let commands = match change {
+            ProposedChange::State(target) => {
+                return self.exceeds(target.channel_a, state.channel_a)
+                    || self.exceeds(level_b(target), level_b(state))
+            }
+            ProposedChange::Commands(commands) => commands,
+        };
+        // Current and lowest level of channel A and B.
+        let mut levels = [
+            (state.channel_a, state.channel_a),
+            (level_b(state), level_b(state)),
+        ];
+        commands.iter().any(|command| {
+            let (index, level) = match *command {
+                SetChannel(TwoBChannel::A, level) => (0, level),
+                SetChannel(TwoBChannel::B, level) => (1, level),
+                IncrementChannel(TwoBChannel::A) => (0, levels[0].0.saturating_add(1)),
+                IncrementChannel(TwoBChannel::B) => (1, levels[1].0.saturating_add(1)),
+                DecrementChannel(TwoBChannel::A) => (0, levels[0].0.saturating_sub(1)),
+                DecrementChannel(TwoBChannel::B) => (1, levels[1].0.saturating_sub(1)),
+                SetJoinedChannels(true) => (1, levels[0].0.max(levels[1].0)),
+                Kill => {
+                    levels = [(0, 0), (0, 0)];
+                    return false;
+                }
+                _ => return false,
+            };
+            let (current, lowest) = &mut levels[index];
+            *current = level;
+            *lowest = (*lowest).min(level);
+            self.exceeds(level, *lowest)
+        })
+    }
+
+    /// Holds `change` until it is confirmed, cancelled or expires.
+    pub fn propose(
+        &mut self,
+        change: ProposedChange,
+        proposed_by: &str,
+        now: SystemTime,
+    ) -> Proposal {
+        self.expire(now);
+        let proposal = Proposal {
+            id: ProposalId(self.next_id),
+            change,
+            proposed_by: proposed_by.into(),
+            proposed_at: now,
+            expires_at: now + self.config.expiry,
+        };
+        self.next_id += 1;
+        self.pending.push(proposal.clone());
+        proposal
+    }
+
+    /// Takes the pending proposal `id` to be applied, `None` if it isn't pending.
+    pub fn confirm(&mut self, id: ProposalId, by: &str, now: SystemTime) -> Option<Proposal> {
+        self.resolve(id, ProposalOutcome::Confirmed, by, now)
+    }
+
+    pub fn cancel(&mut self, id: ProposalId, by: &str, now: SystemTime) -> Option<Proposal> {
+        self.resolve(id, ProposalOutcome::Cancelled, by, now)
+    }
+
+    fn resolve(
+        &mut self,
+        id: ProposalId,
+        outcome: ProposalOutcome,
+        by: &str,
+        now: SystemTime,
+    ) -> Option<Proposal> {
+        self.expire(now);
+        let index = self.pending.iter().position(|p| p.id == id)?;
+        let proposal = self.pending.remove(index);
+        self.record(proposal.clone(), outcome, Some(by.into()), now);
+        Some(proposal)
+    }
+
+    /// Moves the proposals that ran out to the history and returns them.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<Proposal> {
+        let (expired, pending) = self
+            .pending
+            .drain(..)
+            .partition(|proposal| proposal.expires_at <= now);
+        self.pending = pending;
+        for proposal in &expired {
+            self.record(proposal.clone(), ProposalOutcome::Expired, None, now);
+        }
+        expired
+    }
+
+    fn record(
+        &mut self,
+        proposal: Proposal,
+        outcome: ProposalOutcome,
+        resolved_by: Option<String>,
+        resolved_at: SystemTime,
+    ) {
+        self.history.push_back(ResolvedProposal {
+            proposal,
+            outcome,
+            resolved_by,
+            resolved_at,
+        });
+        while self.history.len() > self.config.history {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn pending(&self) -> Vec<Proposal> {
+        self.pending.clone()
+    }
+
+    /// Resolved proposals, oldest first.
+    pub fn history(&self) -> Vec<ResolvedProposal> {
+        self.history.iter().cloned().collect()
+    }
+}
+
+/// The level channel B runs at: joined channels run at the level of A.
+fn level_b(state: &TwoBState) -> u8 {
+    if state.joined_channels {
+        state.channel_a.max(state.channel_b)
+    } else {
+        state.channel_b
+    }
+}
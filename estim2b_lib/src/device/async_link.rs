@@ -0,0 +1,237 @@
+use async_trait::async_trait;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+use tokio_serial::{DataBits, FlowControl, Parity, SerialPort, SerialPortBuilderExt, StopBits};
+
+use crate::device::link::{frame, LinkConfig, ReplyBuffer, Retries};
+use crate::device::network::{escape, is_network_address, TelnetCodec, CONNECT_TIMEOUT};
+use crate::*;
+
+/// Non-blocking byte stream the 2B protocol is spoken over.
+#[async_trait]
+pub trait AsyncTransport: AsyncRead + AsyncWrite + Send + Sync + Unpin {
+    /// Discards everything received but not read yet.
+    async fn clear_input(&mut self) -> Result<(), TwoBError> {
+        let mut buffer = [0u8; 64];
+        loop {
+            match timeout(Duration::from_millis(1), self.read(&mut buffer)).await {
+                Ok(Ok(0)) | Err(_) => return Ok(()),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncTransport for tokio_serial::SerialStream {
+    async fn clear_input(&mut self) -> Result<(), TwoBError> {
+        Ok(self.clear(tokio_serial::ClearBuffer::Input)?)
+    }
+}
+
+impl AsyncTransport for TcpStream {}
+
+impl AsyncTransport for tokio::io::DuplexStream {}
+
+/// Opens a local serial port or a serial port bridged over the network, accepting the
+/// same addresses as `open_transport`.
+pub async fn open_async_transport(address: &str) -> Result<Box<dyn AsyncTransport>, TwoBError> {
+    if let Some(host) = address.strip_prefix("tcp://") {
+        Ok(Box::new(connect_tcp(host).await?))
+    } else if let Some(host) = address.strip_prefix("rfc2217://") {
+        Ok(Box::new(AsyncRfc2217Transport::connect(host).await?))
+    } else if is_network_address(address) {
+        Err(TwoBError::ConnectionError(format!(
+            "Unsupported transport: {}",
+            address
+        )))
+    } else {
+        let stream = tokio_serial::new(address, 9600)
+            .data_bits(DataBits::Eight)
+            .stop_bits(StopBits::One)
+            .parity(Parity::None)
+            .flow_control(FlowControl::None)
+            .open_native_async()?;
+        Ok(Box::new(stream))
+    }
+}
+
+async fn connect_tcp(host: &str) -> Result<TcpStream, TwoBError> {
+    let stream = match timeout(CONNECT_TIMEOUT, TcpStream::connect(host)).await {
+        Ok(stream) => stream?,
+        Err(_) => {
+            return Err(TwoBError::ConnectionError(format!(
+                "Cannot connect to {}",
+                host
+            )))
+        }
+    };
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Async counterpart of `Rfc2217Transport`.
+pub struct AsyncRfc2217Transport {
+    stream: TcpStream,
+    telnet: TelnetCodec,
+    /// Escaped data and answers to the server that haven't been sent yet.
+    outgoing: Vec<u8>,
+}
+
+impl AsyncRfc2217Transport {
+    pub async fn connect(host: &str) -> Result<Self, TwoBError> {
+        let mut stream = connect_tcp(host).await?;
+        stream.write_all(&TelnetCodec::negotiation()).await?;
+        stream.flush().await?;
+        Ok(AsyncRfc2217Transport {
+            stream,
+            telnet: TelnetCodec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.outgoing.is_empty() {
+            let n = ready!(Pin::new(&mut self.stream).poll_write(cx, &self.outgoing))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.outgoing.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for AsyncRfc2217Transport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let mut raw = vec![0u8; buf.remaining().max(1)];
+            let mut raw_buf = ReadBuf::new(&mut raw);
+            ready!(Pin::new(&mut this.stream).poll_read(cx, &mut raw_buf))?;
+            if raw_buf.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            let mut data = Vec::new();
+            let mut replies = Vec::new();
+            this.telnet
+                .decode(raw_buf.filled(), &mut data, &mut replies);
+            if !replies.is_empty() {
+                this.outgoing.extend(replies);
+                // Sent with the next write if the stream isn't writable right now.
+                if let Poll::Ready(Err(e)) = this.poll_send(cx) {
+                    return Poll::Ready(Err(e));
+                }
+            }
+            // A chunk of telnet commands only isn't end of stream, keep waiting for data.
+            if !data.is_empty() {
+                buf.put_slice(&data);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl AsyncWrite for AsyncRfc2217Transport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        this.outgoing.extend(escape(buf));
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.stream).poll_shutdown(cx)
+    }
+}
+
+impl AsyncTransport for AsyncRfc2217Transport {}
+
+/// Async counterpart of `Link` with the same framing, timeout and retry behaviour.
+pub struct AsyncLink {
+    io: Box<dyn AsyncTransport>,
+    config: LinkConfig,
+    buffer: ReplyBuffer,
+}
+
+impl AsyncLink {
+    pub fn new(io: Box<dyn AsyncTransport>, config: LinkConfig) -> Self {
+        AsyncLink {
+            io,
+            config,
+            buffer: ReplyBuffer::default(),
+        }
+    }
+
+    pub fn set_transport(&mut self, io: Box<dyn AsyncTransport>) {
+        self.io = io;
+        self.buffer.clear();
+    }
+
+    pub fn config(&self) -> &LinkConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: LinkConfig) {
+        self.config = config;
+    }
+
+    /// Sends `command` and returns the device's reply, retrying as `Retries` decides.
+    pub async fn request(&mut self, command: &TwoBCommand) -> Result<TwoBReply, TwoBError> {
+        command.validate()?;
+        let mut retries = Retries::new(&self.config, command);
+        loop {
+            let result = self.exchange(command).await;
+            match retries.next(&result) {
+                Some(backoff) => sleep(backoff).await,
+                None => return result,
+            }
+        }
+    }
+
+    async fn exchange(&mut self, command: &TwoBCommand) -> Result<TwoBReply, TwoBError> {
+        self.io.clear_input().await?;
+        self.buffer.clear();
+        self.io.write_all(&frame(command)).await?;
+        self.io.flush().await?;
+
+        let deadline = Instant::now() + self.config.timeout_for(command);
+        let mut chunk = [0u8; 64];
+        loop {
+            if let Some(reply) = self.buffer.reply() {
+                return Ok(reply);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match timeout(remaining, self.io.read(&mut chunk)).await {
+                Err(_) => return Err(self.buffer.timed_out(command)),
+                Ok(Ok(0)) => {
+                    return Err(TwoBError::ConnectionError("Connection closed".into()));
+                }
+                Ok(Ok(n)) => self.buffer.extend(&chunk[..n]),
+                Ok(Err(e)) => return Err(e.into()),
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use async_trait::async_trait;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tokio::time::sleep;
+
+use crate::device::async_link::{open_async_transport, AsyncLink, AsyncTransport};
+use crate::device::link::LinkConfig;
+use crate::device::network::is_network_address;
+use crate::device::reconnect::{ConnectionListener, DeviceCore, ReconnectConfig, SerialLocator};
+use crate::*;
+
+/// Reopens the transport to a 2B after the connection was lost, the async counterpart
+/// of `Opener`.
+pub type AsyncOpener = Box<
+    dyn FnMut() -> Pin<Box<dyn Future<Output = Result<Box<dyn AsyncTransport>, TwoBError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// `USBTwoB` on non-blocking I/O, reconnecting the same way.
+pub struct AsyncUSBTwoB {
+    /// Behind a mutex only because listeners aren't `Sync`; it's never locked across an
+    /// await.
+    core: Mutex<DeviceCore>,
+    link: AsyncLink,
+    opener: Option<AsyncOpener>,
+}
+
+impl AsyncUSBTwoB {
+    /// Opens a local serial port or a network address understood by
+    /// `open_async_transport`.
+    pub async fn open(address: &str) -> Result<Self, TwoBError> {
+        let opener: AsyncOpener = if is_network_address(address) {
+            let address = address.to_string();
+            Box::new(move || {
+                let address = address.clone();
+                Box::pin(async move { open_async_transport(&address).await })
+            })
+        } else {
+            let locator = SerialLocator::new(address);
+            Box::new(move || {
+                let path = locator.locate();
+                Box::pin(async move { open_async_transport(&path).await })
+            })
+        };
+        Self::connect(opener, LinkConfig::default()).await
+    }
+
+    pub async fn from_transport<T: AsyncTransport + 'static>(io: T) -> Result<Self, TwoBError> {
+        Self::with_config(io, LinkConfig::default()).await
+    }
+
+    pub async fn with_config<T: AsyncTransport + 'static>(
+        io: T,
+        config: LinkConfig,
+    ) -> Result<Self, TwoBError> {
+        Self::open_link(Box::new(io), None, config).await
+    }
+
+    /// Connects through `opener`, which is called again to reopen the device whenever
+    /// the connection is lost.
+    pub async fn connect(mut opener: AsyncOpener, config: LinkConfig) -> Result<Self, TwoBError> {
+        let io = opener().await?;
+        Self::open_link(io, Some(opener), config).await
+    }
+
+    async fn open_link(
+        io: Box<dyn AsyncTransport>,
+        opener: Option<AsyncOpener>,
+        config: LinkConfig,
+    ) -> Result<Self, TwoBError> {
+        let mut link = AsyncLink::new(io, config);
+        let reply = link.request(&TwoBCommand::RefreshState).await?;
+        Ok(AsyncUSBTwoB {
+            core: Mutex::new(DeviceCore::new(reply)),
+            link,
+            opener,
+        })
+    }
+
+    fn core(&self) -> MutexGuard<'_, DeviceCore> {
+        self.core.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn core_mut(&mut self) -> &mut DeviceCore {
+        self.core.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn link_config(&self) -> &LinkConfig {
+        self.link.config()
+    }
+
+    pub fn set_link_config(&mut self, config: LinkConfig) {
+        self.link.set_config(config);
+    }
+
+    pub fn reconnect_config(&self) -> ReconnectConfig {
+        self.core().reconnect.clone()
+    }
+
+    pub fn set_reconnect_config(&mut self, config: ReconnectConfig) {
+        self.core_mut().reconnect = config;
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.core().connection
+    }
+
+    pub fn on_connection_change(&mut self, listener: ConnectionListener) {
+        self.core_mut().listeners.push(listener);
+    }
+
+    pub fn subscribe(&mut self, listener: EventListener) -> SubscriptionId {
+        self.core_mut().events.subscribe(listener)
+    }
+
+    pub fn unsubscribe(&mut self, subscription: SubscriptionId) -> bool {
+        self.core_mut().events.unsubscribe(subscription)
+    }
+
+    pub fn on_failure(&self) -> OnFailure {
+        self.core().on_failure
+    }
+
+    pub fn set_on_failure(&mut self, on_failure: OnFailure) {
+        self.core_mut().on_failure = on_failure;
+    }
+
+    /// Reopens the device and, if configured, restores the last known state with
+    /// channel A and B capped at the safe level.
+    pub async fn reconnect(&mut self) -> Result<(), TwoBError> {
+        let mut opener = match self.opener.take() {
+            Some(opener) => opener,
+            None => {
+                return Err(TwoBError::ConnectionError(
+                    "Transport can't be reopened".into(),
+                ))
+            }
+        };
+        let previous = self.core_mut().state.clone();
+        self.core_mut()
+            .set_connection(ConnectionState::Reconnecting);
+
+        let mut result = Err(TwoBError::ConnectionError("No reconnect attempt".into()));
+        for attempt in 0..self.core_mut().reconnect.attempts {
+            if let Some(delay) = self.core_mut().attempt_delay(attempt) {
+                sleep(delay).await;
+            }
+            result = match opener().await {
+                Ok(io) => {
+                    self.link.set_transport(io);
+                    self.link.request(&TwoBCommand::RefreshState).await
+                }
+                Err(e) => Err(e),
+            };
+            if result.is_ok() {
+                break;
+            }
+        }
+        self.opener = Some(opener);
+
+        let result = match result {
+            Ok(reply) => {
+                self.core_mut().update(reply);
+                match self.core_mut().restore_target(previous) {
+                    Some(target) => self.set_state(target).await,
+                    None => Ok(()),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        self.core_mut().reconnected(&result);
+        result
+    }
+
+    async fn send(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        match self.link.request(&command).await {
+            Ok(reply) => {
+                self.core_mut().update(reply);
+                Ok(())
+            }
+            Err(e) if self.core().should_reconnect(&e, self.opener.is_some()) => {
+                self.core_mut()
+                    .set_connection(ConnectionState::Disconnected);
+                self.reconnect().await?;
+                // Only commands that are safe to apply twice are repeated, the lost
+                // one might have reached the device.
+                if command.is_idempotent() {
+                    let reply = self.link.request(&command).await?;
+                    self.core_mut().update(reply);
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncTwoB for AsyncUSBTwoB {
+    async fn execute(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        let result = match self.get_capabilities().await.check(&command) {
+            Ok(()) => self.send(command).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            self.core_mut().events.error(Some(command), e);
+        }
+        result
+    }
+
+    async fn refresh_state(&mut self) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::RefreshState).await
+    }
+
+    async fn reset(&mut self) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::Reset).await
+    }
+
+    async fn kill(&mut self) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::Kill).await
+    }
+
+    async fn set_joined_channels(&mut self, enable: bool) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetJoinedChannels(enable)).await
+    }
+
+    async fn set_mode(&mut self, mode: TwoBMode) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetMode(mode)).await
+    }
+
+    async fn set_power(&mut self, power: TwoBPower) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetPower(power)).await
+    }
+
+    async fn set_map(&mut self, map: TwoBMap) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetMap(map)).await
+    }
+
+    async fn set_bias(&mut self, bias: TwoBBias) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetBias(bias)).await
+    }
+
+    async fn set_ramp(&mut self, ramp: TwoBRamp) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetRamp(ramp)).await
+    }
+
+    async fn set_warp(&mut self, warp: TwoBWarp) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetWarp(warp)).await
+    }
+
+    async fn increment_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::IncrementChannel(channel)).await
+    }
+
+    async fn decrement_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::DecrementChannel(channel)).await
+    }
+
+    async fn set_channel(&mut self, channel: TwoBChannel, value: u8) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetChannel(channel, value)).await
+    }
+
+    async fn set_state(&mut self, state: TwoBState) -> Result<(), TwoBError> {
+        let result = match self.get_capabilities().await.check_state(&state) {
+            Ok(()) => {
+                let on_failure = self.core_mut().on_failure;
+                Transaction::new(state, on_failure).apply_async(self).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            self.core_mut().events.error(None, e);
+        }
+        result.map(|_| ())
+    }
+
+    async fn get_state(&self) -> TwoBState {
+        self.core().state.clone()
+    }
+
+    async fn get_version(&self) -> String {
+        self.core().version.clone()
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use serialport::{SerialPortInfo, SerialPortType};
+use std::time::{Duration, Instant};
+
+use crate::device::link::{Link, LinkConfig};
+use crate::device::transport::open_serial;
+use crate::*;
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UsbInfo {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+/// A serial port that answered like a 2B.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveredTwoB {
+    pub port: String,
+    pub usb: Option<UsbInfo>,
+    pub version: String,
+    pub battery: u16,
+    /// Time between sending the probe and receiving the reply.
+    pub latency: Duration,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveryOptions {
+    /// Only probe USB ports with this serial number.
+    pub serial_number: Option<String>,
+    /// Ports that are never opened.
+    pub skip_ports: Vec<String>,
+    /// USB vendor and product ids of devices known not to be a 2B.
+    pub skip_usb_ids: Vec<(u16, u16)>,
+    /// Don't probe ports that aren't USB serial adapters.
+    pub usb_only: bool,
+    /// Timeouts used while probing.
+    pub link: LinkConfig,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        DiscoveryOptions {
+            serial_number: None,
+            skip_ports: Vec::new(),
+            skip_usb_ids: Vec::new(),
+            usb_only: false,
+            link: LinkConfig {
+                retries: 0,
+                ..LinkConfig::default()
+            },
+        }
+    }
+}
+
+impl DiscoveryOptions {
+    fn should_probe(&self, port: &SerialPortInfo, usb: Option<&UsbInfo>) -> bool {
+        if self.skip_ports.contains(&port.port_name) {
+            return false;
+        }
+        match usb {
+            Some(usb) => {
+                let wanted = match &self.serial_number {
+                    Some(serial_number) => usb.serial_number.as_ref() == Some(serial_number),
+                    None => true,
+                };
+                wanted && !self.skip_usb_ids.contains(&(usb.vid, usb.pid))
+            }
+            None => !self.usb_only && self.serial_number.is_none(),
+        }
+    }
+}
+
+/// Probes every available serial port and returns all that answer like a 2B.
+pub fn discover(options: &DiscoveryOptions) -> Result<Vec<DiscoveredTwoB>, TwoBError> {
+    Ok(discover_ports(&serialport::available_ports()?, options))
+}
+
+/// Like `discover`, but only considers `ports`.
+pub fn discover_ports(ports: &[SerialPortInfo], options: &DiscoveryOptions) -> Vec<DiscoveredTwoB> {
+    probe_ports(ports, options)
+        .map(|(found, ..)| found)
+        .collect()
+}
+
+/// Probes `ports` one after the other as the iterator advances, yielding every 2B with
+/// the link it answered on and its reply, so they can be used without probing again.
+pub(crate) fn probe_ports<'a>(
+    ports: &'a [SerialPortInfo],
+    options: &'a DiscoveryOptions,
+) -> impl Iterator<Item = (DiscoveredTwoB, Link, TwoBReply)> + 'a {
+    ports.iter().filter_map(move |port| {
+        let usb = match &port.port_type {
+            SerialPortType::UsbPort(info) => Some(UsbInfo {
+                vid: info.vid,
+                pid: info.pid,
+                serial_number: info.serial_number.clone(),
+                manufacturer: info.manufacturer.clone(),
+                product: info.product.clone(),
+            }),
+            _ => None,
+        };
+        if !options.should_probe(port, usb.as_ref()) {
+            return None;
+        }
+        let (link, reply, latency) = probe(&port.port_name, options).ok()?;
+        let found = DiscoveredTwoB {
+            port: port.port_name.clone(),
+            usb,
+            version: reply.version.clone(),
+            battery: reply.battery,
+            latency,
+        };
+        Some((found, link, reply))
+    })
+}
+
+fn probe(port: &str, options: &DiscoveryOptions) -> Result<(Link, TwoBReply, Duration), TwoBError> {
+    let mut link = Link::new(open_serial(port)?, options.link.clone());
+    let start = Instant::now();
+    let reply = link.request(&TwoBCommand::RefreshState)?;
+    Ok((link, reply, start.elapsed()))
+}
@@ -0,0 +1,128 @@
+#[cfg(unix)]
+use serialport::{SerialPort, TTYPort};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::*;
+
+/// Speaks the 2B serial protocol on behalf of a `VirtualTwoB`.
+///
+/// Every command terminated by `\r` is applied to the virtual device and answered with
+/// the device's status line, just like the real box does.
+pub struct Emulator {
+    device: VirtualTwoB,
+}
+
+impl Emulator {
+    pub fn new() -> Result<Self, TwoBError> {
+        Ok(Emulator {
+            device: VirtualTwoB::new()?,
+        })
+    }
+
+    pub fn with_version(version: &str) -> Result<Self, TwoBError> {
+        Ok(Emulator {
+            device: VirtualTwoB::with_version(version)?,
+        })
+    }
+
+    /// Applies a single command (without the trailing `\r`) and returns the reply line.
+    /// Commands the emulator doesn't understand leave the state untouched.
+    pub fn handle(&mut self, command: &str) -> String {
+        if let Ok(command) = TwoBCommand::decode(command.trim()) {
+            let _ = self.device.execute(command);
+        }
+        self.status_line()
+    }
+
+    /// Answers commands read from `stream` until it is closed or `running` is cleared.
+    pub fn serve<S: Read + Write>(
+        &mut self,
+        stream: &mut S,
+        running: &AtomicBool,
+    ) -> Result<(), TwoBError> {
+        let mut command = Vec::new();
+        let mut buffer = [0u8; 64];
+        while running.load(Ordering::Relaxed) {
+            let n = match stream.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(n) => n,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e.into()),
+            };
+            for &byte in &buffer[..n] {
+                match byte {
+                    b'\r' => {
+                        let reply = self.handle(&String::from_utf8_lossy(&command));
+                        command.clear();
+                        stream.write_all(format!("{}\n", reply).as_bytes())?;
+                        stream.flush()?;
+                    }
+                    b'\n' => {}
+                    _ => command.push(byte),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens a pseudo-terminal and serves the protocol on it from a background thread.
+    /// The slave side can be opened like any serial port, e.g. `USBTwoB::try_from(pty.path())`.
+    #[cfg(unix)]
+    pub fn spawn_pty(mut self) -> Result<EmulatorPty, TwoBError> {
+        let (mut master, slave) = TTYPort::pair()?;
+        let path = slave
+            .name()
+            .ok_or_else(|| TwoBError::ConnectionError("Pseudo-terminal has no name".into()))?;
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let running = running.clone();
+            thread::spawn(move || self.serve(&mut master, &running))
+        };
+        Ok(EmulatorPty {
+            path,
+            running,
+            thread: Some(thread),
+            _slave: slave,
+        })
+    }
+
+    fn status_line(&self) -> String {
+        TwoBReply::from_state(&self.device.get_state(), &self.device.get_version()).encode()
+    }
+}
+
+/// Handle to an emulator serving a pseudo-terminal. Dropping it stops the emulator.
+#[cfg(unix)]
+pub struct EmulatorPty {
+    path: String,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), TwoBError>>>,
+    _slave: TTYPort,
+}
+
+#[cfg(unix)]
+impl EmulatorPty {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EmulatorPty {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
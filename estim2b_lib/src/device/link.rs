@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+use std::mem;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::device::transport::Transport;
+use crate::*;
+
+/// Timing and retry behaviour of the request/response engine.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LinkConfig {
+    /// How long to wait for a reply.
+    pub timeout: Duration,
+    /// Commands that need a different reply timeout than `timeout`. Only the kind of
+    /// command is compared, not its argument.
+    pub command_timeouts: Vec<(TwoBCommand, Duration)>,
+    /// How often a failed idempotent command is sent again.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further retry.
+    pub backoff: Duration,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            timeout: Duration::from_millis(100),
+            command_timeouts: vec![(TwoBCommand::Reset, Duration::from_millis(500))],
+            retries: 2,
+            backoff: Duration::from_millis(20),
+        }
+    }
+}
+
+impl LinkConfig {
+    pub fn timeout_for(&self, command: &TwoBCommand) -> Duration {
+        self.command_timeouts
+            .iter()
+            .find(|(other, _)| mem::discriminant(other) == mem::discriminant(command))
+            .map_or(self.timeout, |(_, timeout)| *timeout)
+    }
+}
+
+/// The bytes sent for `command`.
+pub(crate) fn frame(command: &TwoBCommand) -> Vec<u8> {
+    format!("{}\r", command.encode()).into_bytes()
+}
+
+/// When a failed request is sent again. Shared by `Link` and `AsyncLink`.
+pub(crate) struct Retries {
+    left: u32,
+    backoff: Duration,
+}
+
+impl Retries {
+    /// Timeouts and garbled replies are retried with backoff, but only for commands that
+    /// can safely be applied twice; a lost reply to `A+` must not raise the level by two.
+    pub fn new(config: &LinkConfig, command: &TwoBCommand) -> Self {
+        Retries {
+            left: if command.is_idempotent() {
+                config.retries
+            } else {
+                0
+            },
+            backoff: config.backoff,
+        }
+    }
+
+    /// How long to wait before sending the command again after `result`, `None` if
+    /// `result` is final.
+    pub fn next(&mut self, result: &Result<TwoBReply, TwoBError>) -> Option<Duration> {
+        match result {
+            Err(TwoBError::Timeout(_)) | Err(TwoBError::ParserError(_)) if self.left > 0 => {
+                self.left -= 1;
+                let backoff = self.backoff;
+                self.backoff *= 2;
+                Some(backoff)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Collects received bytes until they contain a status line. Shared by `Link` and
+/// `AsyncLink`.
+#[derive(Default)]
+pub(crate) struct ReplyBuffer {
+    buffer: Vec<u8>,
+    last_error: Option<TwoBError>,
+}
+
+impl ReplyBuffer {
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.last_error = None;
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Takes the first complete status line. A partial or corrupted line is skipped,
+    /// the reply may still follow.
+    pub fn reply(&mut self) -> Option<TwoBReply> {
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            match TwoBReply::parse(&line) {
+                Ok(reply) => return Some(reply),
+                Err(e) => self.last_error = Some(TwoBError::from(e)),
+            }
+        }
+        None
+    }
+
+    /// Error for a reply to `command` that didn't arrive in time.
+    pub fn timed_out(&mut self, command: &TwoBCommand) -> TwoBError {
+        self.last_error
+            .take()
+            .unwrap_or_else(|| TwoBError::Timeout(format!("No reply to '{}'", command.encode())))
+    }
+}
+
+/// Sends commands over a `Transport` and waits for the matching status line.
+pub struct Link {
+    io: Box<dyn Transport>,
+    config: LinkConfig,
+    buffer: ReplyBuffer,
+}
+
+impl Link {
+    pub fn new(io: Box<dyn Transport>, config: LinkConfig) -> Self {
+        Link {
+            io,
+            config,
+            buffer: ReplyBuffer::default(),
+        }
+    }
+
+    pub fn set_transport(&mut self, io: Box<dyn Transport>) {
+        self.io = io;
+        self.buffer.clear();
+    }
+
+    pub fn config(&self) -> &LinkConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: LinkConfig) {
+        self.config = config;
+    }
+
+    /// Sends `command` once, without retrying it.
+    pub fn request_once(&mut self, command: &TwoBCommand) -> Result<TwoBReply, TwoBError> {
+        command.validate()?;
+        self.exchange(command)
+    }
+
+    /// Sends `command` and returns the device's reply, retrying as `Retries` decides.
+    pub fn request(&mut self, command: &TwoBCommand) -> Result<TwoBReply, TwoBError> {
+        command.validate()?;
+        let mut retries = Retries::new(&self.config, command);
+        loop {
+            let result = self.exchange(command);
+            match retries.next(&result) {
+                Some(backoff) => sleep(backoff),
+                None => return result,
+            }
+        }
+    }
+
+    fn exchange(&mut self, command: &TwoBCommand) -> Result<TwoBReply, TwoBError> {
+        self.io.clear_input()?;
+        self.buffer.clear();
+        self.io.write_all(&frame(command))?;
+        self.io.flush()?;
+
+        let deadline = Instant::now() + self.config.timeout_for(command);
+        let mut chunk = [0u8; 64];
+        loop {
+            if let Some(reply) = self.buffer.reply() {
+                return Ok(reply);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(self.buffer.timed_out(command));
+            }
+            self.io
+                .set_timeout((deadline - now).max(Duration::from_millis(1)))?;
+            match self.io.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(TwoBError::ConnectionError("Connection closed".into()));
+                }
+                Ok(n) => self.buffer.extend(&chunk[..n]),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
@@ -1,8 +1,32 @@
+#[cfg(feature = "async")]
+pub mod async_link;
+
+#[cfg(feature = "async")]
+pub mod async_usb_two_b;
+
+#[cfg(feature = "usb")]
+pub mod discovery;
+
+#[cfg(feature = "usb")]
+pub mod link;
+
+#[cfg(feature = "usb")]
+pub mod network;
+
+#[cfg(feature = "usb")]
+pub mod reconnect;
+
+#[cfg(feature = "usb")]
+pub mod transport;
+
 #[cfg(feature = "usb")]
 pub mod usb_two_b;
 
 #[cfg(feature = "virtual")]
 pub mod virtual_two_b;
 
+#[cfg(feature = "emulator")]
+pub mod emulator;
+
 #[cfg(feature = "python")]
 pub mod python_wrapper;
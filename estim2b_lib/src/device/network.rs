@@ -0,0 +1,261 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::device::transport::{open_serial, Transport};
+use crate::*;
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+pub(crate) const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens a local serial port or a serial port bridged over the network.
+///
+/// `tcp://host:port` connects to a raw TCP bridge such as `ser2net` in raw mode,
+/// `rfc2217://host:port` to a telnet COM port server. Anything else is taken as the
+/// path of a local serial port.
+pub fn open_transport(address: &str) -> Result<Box<dyn Transport>, TwoBError> {
+    if let Some(host) = address.strip_prefix("tcp://") {
+        Ok(Box::new(open_tcp(host)?))
+    } else if let Some(host) = address.strip_prefix("rfc2217://") {
+        Ok(Box::new(Rfc2217Transport::connect(host)?))
+    } else if is_network_address(address) {
+        Err(TwoBError::ConnectionError(format!(
+            "Unsupported transport: {}",
+            address
+        )))
+    } else {
+        open_serial(address)
+    }
+}
+
+/// Whether `address` names a network transport rather than a local serial port.
+pub fn is_network_address(address: &str) -> bool {
+    address.contains("://")
+}
+
+/// Connects to a raw TCP serial bridge.
+pub fn open_tcp(host: &str) -> Result<TcpStream, TwoBError> {
+    let mut last_error = None;
+    for addr in host.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.map_or_else(
+        || TwoBError::ConnectionError(format!("Cannot resolve {}", host)),
+        TwoBError::from,
+    ))
+}
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+
+const PARITY_NONE: u8 = 1;
+const STOPSIZE_ONE: u8 = 1;
+const CONTROL_NO_FLOW_CONTROL: u8 = 1;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Telnet {
+    Data,
+    /// Last data byte was a carriage return, a following NUL is padding.
+    CarriageReturn,
+    Iac,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// The telnet side of RFC 2217 without any I/O, shared by `Rfc2217Transport` and the
+/// async transport.
+pub(crate) struct TelnetCodec {
+    state: Telnet,
+}
+
+impl TelnetCodec {
+    pub fn new() -> Self {
+        TelnetCodec {
+            state: Telnet::Data,
+        }
+    }
+
+    /// Sent when connecting: the options used and the line settings the 2B expects.
+    pub fn negotiation() -> Vec<u8> {
+        let mut negotiation = vec![
+            IAC,
+            WILL,
+            BINARY,
+            IAC,
+            DO,
+            BINARY,
+            IAC,
+            WILL,
+            SUPPRESS_GO_AHEAD,
+            IAC,
+            DO,
+            SUPPRESS_GO_AHEAD,
+            IAC,
+            WILL,
+            COM_PORT_OPTION,
+        ];
+        let settings: [(u8, &[u8]); 5] = [
+            (SET_BAUDRATE, &9600u32.to_be_bytes()),
+            (SET_DATASIZE, &[8]),
+            (SET_PARITY, &[PARITY_NONE]),
+            (SET_STOPSIZE, &[STOPSIZE_ONE]),
+            (SET_CONTROL, &[CONTROL_NO_FLOW_CONTROL]),
+        ];
+        for (command, value) in settings {
+            negotiation.extend_from_slice(&[IAC, SB, COM_PORT_OPTION, command]);
+            negotiation.extend(escape(value));
+            negotiation.extend_from_slice(&[IAC, SE]);
+        }
+        negotiation
+    }
+
+    /// Filters telnet commands from `raw`. Data is appended to `data`, the answers to
+    /// the server's option requests to `replies`.
+    pub fn decode(&mut self, raw: &[u8], data: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        for &byte in raw {
+            self.state = match (self.state, byte) {
+                (Telnet::Data | Telnet::CarriageReturn, IAC) => Telnet::Iac,
+                (Telnet::CarriageReturn, 0) => Telnet::Data,
+                (Telnet::Data | Telnet::CarriageReturn, byte) => {
+                    data.push(byte);
+                    if byte == b'\r' {
+                        Telnet::CarriageReturn
+                    } else {
+                        Telnet::Data
+                    }
+                }
+                (Telnet::Iac, IAC) => {
+                    data.push(IAC);
+                    Telnet::Data
+                }
+                (Telnet::Iac, command @ (DO | DONT | WILL | WONT)) => Telnet::Negotiation(command),
+                (Telnet::Iac, SB) => Telnet::Subnegotiation,
+                (Telnet::Iac, _) => Telnet::Data,
+                (Telnet::Negotiation(command), option) => {
+                    replies.extend(refusal(command, option));
+                    Telnet::Data
+                }
+                // Replies to the COM port settings and line state notifications
+                // aren't needed by the 2B protocol.
+                (Telnet::Subnegotiation, IAC) => Telnet::SubnegotiationIac,
+                (Telnet::Subnegotiation, _) => Telnet::Subnegotiation,
+                (Telnet::SubnegotiationIac, SE) => Telnet::Data,
+                (Telnet::SubnegotiationIac, _) => Telnet::Subnegotiation,
+            };
+        }
+    }
+}
+
+/// Refuses every option the server offers or asks for that wasn't requested.
+fn refusal(command: u8, option: u8) -> Vec<u8> {
+    let requested = matches!(option, BINARY | SUPPRESS_GO_AHEAD)
+        || (command == DO && option == COM_PORT_OPTION);
+    match command {
+        DO if !requested => vec![IAC, WONT, option],
+        WILL if !requested => vec![IAC, DONT, option],
+        _ => Vec::new(),
+    }
+}
+
+/// Doubles `0xFF` bytes so they aren't taken for telnet commands.
+pub(crate) fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
+
+/// Serial port served by an RFC 2217 (telnet COM port control) server.
+///
+/// The line settings the 2B expects are negotiated when connecting. Telnet commands are
+/// filtered from the received data and `0xFF` bytes are escaped when writing.
+pub struct Rfc2217Transport {
+    stream: TcpStream,
+    host: String,
+    telnet: TelnetCodec,
+}
+
+impl Rfc2217Transport {
+    pub fn connect(host: &str) -> Result<Self, TwoBError> {
+        let mut transport = Rfc2217Transport {
+            stream: open_tcp(host)?,
+            host: host.into(),
+            telnet: TelnetCodec::new(),
+        };
+        transport.stream.write_all(&TelnetCodec::negotiation())?;
+        transport.stream.flush()?;
+        Ok(transport)
+    }
+}
+
+impl Read for Rfc2217Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut raw = vec![0u8; buf.len().max(1)];
+            let n = self.stream.read(&mut raw)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut data = Vec::with_capacity(n);
+            let mut replies = Vec::new();
+            self.telnet.decode(&raw[..n], &mut data, &mut replies);
+            self.stream.write_all(&replies)?;
+            // A chunk of telnet commands only isn't end of stream, keep waiting for data.
+            if !data.is_empty() {
+                buf[..data.len()].copy_from_slice(&data);
+                return Ok(data.len());
+            }
+        }
+    }
+}
+impl Write for Rfc2217Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write_all(&escape(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for Rfc2217Transport {
+    fn timeout(&self) -> Duration {
+        self.stream.timeout()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), TwoBError> {
+        self.stream.set_timeout(timeout)
+    }
+
+    fn name(&self) -> Option<String> {
+        Some(format!("rfc2217://{}", self.host))
+    }
+}
@@ -1,8 +1,8 @@
 use crate::*;
 use pyo3::{prelude::*, PyObjectProtocol};
 
-use std::boxed::Box;
 use std::str::FromStr;
+use std::time::Duration;
 
 #[pymodule]
 fn estim2b_lib(py: Python, m: &PyModule) -> PyResult<()> {
@@ -15,13 +15,25 @@ impl From<TwoBError> for PyErr {
         match err {
             TwoBError::ConnectionError(e) => pyo3::exceptions::PyIOError::new_err(e),
             TwoBError::ParserError(e) => pyo3::exceptions::PyUnicodeDecodeError::new_err(e),
+            TwoBError::Unsupported(e) => pyo3::exceptions::PyValueError::new_err(e),
+            TwoBError::Timeout(e) => pyo3::exceptions::PyTimeoutError::new_err(e),
+            TwoBError::TransactionFailed(report) => {
+                pyo3::exceptions::PyRuntimeError::new_err(report.to_string())
+            }
+            TwoBError::PolicyViolation(e) => pyo3::exceptions::PyPermissionError::new_err(e),
+            TwoBError::ConfirmationRequired(proposal) => {
+                pyo3::exceptions::PyPermissionError::new_err(format!(
+                    "Proposal {} has to be confirmed",
+                    proposal.id.0
+                ))
+            }
         }
     }
 }
 
 #[pyclass(name = "TwoB")]
 struct PythonWrapper {
-    device: Box<dyn TwoB + Send>,
+    device: TwoBHandle,
 }
 
 #[pyproto]
@@ -115,23 +127,79 @@ impl PyObjectProtocol for TwoBWarp {
 #[pymethods]
 impl PythonWrapper {
     #[new]
-    #[args(path = "None")]
-    fn new(path: Option<&str>) -> PyResult<Self> {
-        if let Some(path) = path {
-            if path == "virtual" && cfg!(feature = "virtual") {
-                Ok(PythonWrapper {
-                    device: Box::new(VirtualTwoB::new()?),
-                })
-            } else {
-                Ok(PythonWrapper {
-                    device: Box::new(USBTwoB::try_from(path)?),
-                })
+    #[args(
+        path = "None",
+        policy = "None",
+        on_exit = "\"Kill\"",
+        audit_log = "None",
+        script = "None"
+    )]
+    /// `policy` is the path of a JSON `SafetyPolicy` every command is checked against.
+    /// `on_exit` is what a 2B is left with once it is closed: Kill, Reset or Leave.
+    /// `audit_log` is the path of the audit log to append commands to, where they are
+    /// recorded as coming from Python or, if given, the `script` name.
+    fn new(
+        path: Option<&str>,
+        policy: Option<&str>,
+        on_exit: &str,
+        audit_log: Option<&str>,
+        script: Option<&str>,
+    ) -> PyResult<Self> {
+        let on_exit = OnExit::from_str(on_exit).map_err(TwoBError::from)?;
+        let mut device: Box<dyn TwoB> = match path {
+            Some("virtual") if cfg!(feature = "virtual") => Box::new(VirtualTwoB::new()?),
+            Some(path) => {
+                let mut device = USBTwoB::try_from(path)?;
+                device.set_on_exit(on_exit);
+                Box::new(device)
+            }
+            None => {
+                let mut device = USBTwoB::new()?;
+                device.set_on_exit(on_exit);
+                Box::new(device)
             }
-        } else {
-            Ok(PythonWrapper {
-                device: Box::new(USBTwoB::new()?),
-            })
+        };
+        if let Some(policy) = policy {
+            let mut safe = SafeTwoB::from_box(device, SafetyPolicy::from_file(policy)?);
+            safe.enforce()?;
+            device = Box::new(safe);
         }
+        let origin = match script {
+            Some(script) => Origin::Script(script.into()),
+            None => Origin::Python,
+        };
+        let device = TwoBHandle::from_box(device).with_origin(origin);
+        if let Some(audit_log) = audit_log {
+            device.set_audit_log(Some(AuditLog::open(audit_log)?))?;
+        }
+        Ok(PythonWrapper { device })
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    /// Leaves the 2B as its on-exit policy says, also when the block raised.
+    fn __exit__(
+        &mut self,
+        _exc_type: Option<&PyAny>,
+        _exc_value: Option<&PyAny>,
+        _traceback: Option<&PyAny>,
+    ) -> bool {
+        let _ = self.device.shutdown();
+        false
+    }
+
+    /// Stops the 2B as its on-exit policy says. Dropping the last reference does the
+    /// same.
+    #[pyo3(text_signature = "()")]
+    fn close(&mut self) -> Result<(), TwoBError> {
+        self.device.shutdown()
+    }
+
+    #[pyo3(text_signature = "(command)")]
+    fn execute(&mut self, command: &str) -> Result<(), TwoBError> {
+        self.device.execute(TwoBCommand::decode(command)?)
     }
 
     #[pyo3(text_signature = "()")]
@@ -149,6 +217,50 @@ impl PythonWrapper {
         self.device.kill()
     }
 
+    /// Kills the 2B and refuses raising levels until `reset_emergency_stop` is called.
+    #[pyo3(text_signature = "()")]
+    fn emergency_stop(&mut self) -> Result<(), TwoBError> {
+        self.device.engage_emergency_stop(StopSource::Library)
+    }
+
+    #[pyo3(text_signature = "(reason)")]
+    fn reset_emergency_stop(&mut self, reason: &str) -> Result<(), TwoBError> {
+        self.device.reset_emergency_stop(reason)
+    }
+
+    #[pyo3(text_signature = "()")]
+    fn is_emergency_stopped(&self) -> bool {
+        self.device.emergency_stop().is_some()
+    }
+
+    /// Kills the 2B once channel A or B was above zero for `max_active` seconds and
+    /// refuses raising levels for `cooldown` seconds after that.
+    #[pyo3(text_signature = "(max_active, cooldown)")]
+    fn set_session_limits(&mut self, max_active: f64, cooldown: f64) -> Result<(), TwoBError> {
+        self.device.set_session_config(Some(SessionConfig {
+            max_active: Duration::from_secs_f64(max_active),
+            cooldown: Duration::from_secs_f64(cooldown),
+            ..SessionConfig::default()
+        }))
+    }
+
+    /// Seconds left of the session, `None` without session limits.
+    #[pyo3(text_signature = "()")]
+    fn get_session_remaining(&self) -> Option<f64> {
+        self.device
+            .session_status()
+            .map(|status| status.remaining.as_secs_f64())
+    }
+
+    /// Seconds until levels can be raised again, `None` outside the cooldown.
+    #[pyo3(text_signature = "()")]
+    fn get_cooldown_remaining(&self) -> Option<f64> {
+        self.device
+            .session_status()
+            .and_then(|status| status.cooldown_remaining)
+            .map(|cooldown| cooldown.as_secs_f64())
+    }
+
     #[pyo3(text_signature = "(enable)")]
     fn set_joined_channels(&mut self, enable: bool) -> Result<(), TwoBError> {
         self.device.set_joined_channels(enable)
@@ -258,10 +370,31 @@ impl PythonWrapper {
     fn get_version(&self) -> String {
         self.device.get_version()
     }
+
+    #[pyo3(text_signature = "(command)")]
+    fn is_supported(&self, command: &str) -> Result<bool, TwoBError> {
+        let command = TwoBCommand::decode(command)?;
+        Ok(self.device.get_capabilities().check(&command).is_ok())
+    }
+}
+
+/// Returns the serial ports of all detected 2Bs.
+#[pyfunction]
+#[pyo3(name = "discover_ports", text_signature = "(serial_number=None)")]
+fn find_ports(serial_number: Option<String>) -> Result<Vec<String>, TwoBError> {
+    let options = DiscoveryOptions {
+        serial_number,
+        ..DiscoveryOptions::default()
+    };
+    Ok(discover(&options)?
+        .into_iter()
+        .map(|found| found.port)
+        .collect())
 }
 
 // register methods for exporting with pyo3
 fn register(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(find_ports, m)?)?;
     m.add_class::<PythonWrapper>()?;
     m.add_class::<TwoBBias>()?;
     m.add_class::<TwoBChannel>()?;
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use serialport::SerialPortType;
+use std::time::Duration;
+
+use crate::device::transport::{open_serial, Transport};
+use crate::*;
+
+/// Reopens the transport to a 2B after the connection was lost.
+pub type Opener = Box<dyn FnMut() -> Result<Box<dyn Transport>, TwoBError> + Send>;
+
+/// Receives every change of the connection state.
+pub type ConnectionListener = Box<dyn FnMut(ConnectionState) + Send>;
+
+/// How `USBTwoB` behaves when the connection to the device is lost. A reply that
+/// doesn't arrive in time is only retried by the link, it doesn't reopen the device.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReconnectConfig {
+    pub enabled: bool,
+    /// How often reopening the device is tried before giving up on the current call.
+    pub attempts: u32,
+    /// Delay between two attempts.
+    pub delay: Duration,
+    /// Whether the last known state is sent to the device after reconnecting.
+    pub restore_state: bool,
+    /// Upper bound for channel A and B when the state is restored. The previous
+    /// intensity is never restored blindly.
+    pub safe_level: u8,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            enabled: true,
+            attempts: 3,
+            delay: Duration::from_millis(500),
+            restore_state: true,
+            safe_level: 0,
+        }
+    }
+}
+
+/// Finds a serial 2B again, by USB serial number if known and by port path otherwise.
+/// USB-serial adapters often come back under a different path after being replugged.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SerialLocator {
+    pub path: String,
+    pub serial_number: Option<String>,
+}
+
+impl SerialLocator {
+    pub fn new(path: &str) -> Self {
+        let serial_number = serialport::available_ports()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|port| port.port_name == path)
+            .and_then(|port| match port.port_type {
+                SerialPortType::UsbPort(info) => info.serial_number,
+                _ => None,
+            });
+        SerialLocator {
+            path: path.into(),
+            serial_number,
+        }
+    }
+
+    pub fn locate(&self) -> String {
+        let serial_number = match &self.serial_number {
+            Some(serial_number) => serial_number,
+            None => return self.path.clone(),
+        };
+        serialport::available_ports()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|port| match &port.port_type {
+                SerialPortType::UsbPort(info) => info.serial_number.as_ref() == Some(serial_number),
+                _ => false,
+            })
+            .map_or_else(|| self.path.clone(), |port| port.port_name)
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Transport>, TwoBError> {
+        open_serial(&self.locate())
+    }
+
+    pub fn into_opener(self) -> Opener {
+        Box::new(move || self.open())
+    }
+}
+
+/// What `USBTwoB` and `AsyncUSBTwoB` know about the device and its connection, kept
+/// apart from their I/O so both update and reconnect the same way.
+pub(crate) struct DeviceCore {
+    pub state: TwoBState,
+    pub version: String,
+    pub reconnect: ReconnectConfig,
+    pub connection: ConnectionState,
+    pub listeners: Vec<ConnectionListener>,
+    pub events: EventBus,
+    pub on_failure: OnFailure,
+}
+
+impl DeviceCore {
+    /// Core of a device that just answered `reply`.
+    pub fn new(reply: TwoBReply) -> Self {
+        DeviceCore {
+            state: reply.state(),
+            version: reply.version,
+            reconnect: ReconnectConfig::default(),
+            connection: ConnectionState::Connected,
+            listeners: Vec::new(),
+            events: EventBus::new(),
+            on_failure: OnFailure::default(),
+        }
+    }
+
+    pub fn update(&mut self, reply: TwoBReply) {
+        let state = reply.state();
+        self.events.state_changed(&self.state, &state);
+        self.events.version_changed(&self.version, &reply.version);
+        self.state = state;
+        self.version = reply.version;
+    }
+
+    pub fn set_connection(&mut self, connection: ConnectionState) {
+        if self.connection != connection {
+            self.events.emit(TwoBChange::Connection {
+                old: self.connection,
+                new: connection,
+            });
+            self.connection = connection;
+            for listener in self.listeners.iter_mut() {
+                listener(connection);
+            }
+        }
+    }
+
+    /// Whether `error` from sending a command is answered by reopening the device.
+    /// Timeouts are only retried by the link.
+    pub fn should_reconnect(&self, error: &TwoBError, can_reopen: bool) -> bool {
+        matches!(error, TwoBError::ConnectionError(_))
+            && self.reconnect.enabled
+            && can_reopen
+            && self.connection != ConnectionState::Reconnecting
+    }
+
+    /// Delay before reopening attempt `attempt`, counted from 0.
+    pub fn attempt_delay(&self, attempt: u32) -> Option<Duration> {
+        (attempt > 0).then_some(self.reconnect.delay)
+    }
+
+    /// The state sent after reconnecting, if any: the last known state from `previous`
+    /// with channel A and B capped at the safe level.
+    pub fn restore_target(&self, previous: TwoBState) -> Option<TwoBState> {
+        let safe_level = self.reconnect.safe_level;
+        self.reconnect.restore_state.then(|| TwoBState {
+            channel_a: previous.channel_a.min(safe_level),
+            channel_b: previous.channel_b.min(safe_level),
+            battery: self.state.battery,
+            ..previous
+        })
+    }
+
+    pub fn reconnected(&mut self, result: &Result<(), TwoBError>) {
+        self.set_connection(if result.is_ok() {
+            ConnectionState::Connected
+        } else {
+            ConnectionState::Disconnected
+        });
+    }
+}
@@ -0,0 +1,86 @@
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::*;
+
+const TIMEOUT: u64 = 100;
+
+/// Opens a local serial port with the settings the 2B expects.
+pub fn open_serial(tty: &str) -> Result<Box<dyn Transport>, TwoBError> {
+    let io = serialport::new(tty, 9600)
+        .data_bits(DataBits::Eight)
+        .stop_bits(StopBits::One)
+        .parity(Parity::None)
+        .timeout(Duration::from_millis(TIMEOUT))
+        .flow_control(FlowControl::None)
+        .open()?;
+    Ok(Box::new(io))
+}
+
+/// Byte stream the 2B protocol is spoken over.
+///
+/// Anything that can be read from and written to with a read timeout can carry the
+/// protocol: a local serial port, a TCP socket, a pseudo-terminal or an in-memory buffer.
+pub trait Transport: Read + Write + Send {
+    fn timeout(&self) -> Duration;
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), TwoBError>;
+
+    fn name(&self) -> Option<String> {
+        None
+    }
+
+    /// Discards everything received but not read yet.
+    fn clear_input(&mut self) -> Result<(), TwoBError> {
+        let timeout = self.timeout();
+        self.set_timeout(Duration::from_millis(1))?;
+        let mut buffer = [0u8; 64];
+        let result = loop {
+            match self.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    break Ok(())
+                }
+                Err(e) => break Err(e.into()),
+            }
+        };
+        self.set_timeout(timeout)?;
+        result
+    }
+}
+
+impl Transport for Box<dyn SerialPort> {
+    fn timeout(&self) -> Duration {
+        self.as_ref().timeout()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), TwoBError> {
+        Ok(self.as_mut().set_timeout(timeout)?)
+    }
+
+    fn name(&self) -> Option<String> {
+        self.as_ref().name()
+    }
+
+    fn clear_input(&mut self) -> Result<(), TwoBError> {
+        Ok(self.as_ref().clear(ClearBuffer::Input)?)
+    }
+}
+
+impl Transport for TcpStream {
+    fn timeout(&self) -> Duration {
+        self.read_timeout().ok().flatten().unwrap_or(Duration::ZERO)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), TwoBError> {
+        Ok(self.set_read_timeout(Some(timeout))?)
+    }
+
+    fn name(&self) -> Option<String> {
+        self.peer_addr().ok().map(|addr| addr.to_string())
+    }
+}
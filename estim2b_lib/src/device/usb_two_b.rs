@@ -1,12 +1,15 @@
-use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
-use std::io::BufRead;
-use std::io::BufReader;
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::thread::sleep;
 
+use crate::device::discovery::{probe_ports, DiscoveryOptions};
+use crate::device::link::{Link, LinkConfig};
+use crate::device::network::{is_network_address, open_transport};
+use crate::device::reconnect::{
+    ConnectionListener, DeviceCore, Opener, ReconnectConfig, SerialLocator,
+};
+use crate::device::transport::Transport;
 use crate::*;
 
-const TIMEOUT: u64 = 100;
-
 impl From<serialport::Error> for TwoBError {
     fn from(sp_error: serialport::Error) -> TwoBError {
         TwoBError::ConnectionError(sp_error.to_string())
@@ -19,176 +22,328 @@ impl From<std::io::Error> for TwoBError {
     }
 }
 
+/// What a `USBTwoB` sends to the device when it is dropped, which also happens when
+/// its owner unwinds from a panic.
+#[derive(
+    Clone, Copy, Debug, Default, Display, EnumString, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub enum OnExit {
+    #[default]
+    /// Zero channel A and B.
+    Kill,
+    /// Return the device to its defaults.
+    Reset,
+    /// Keep the device running as it is.
+    Leave,
+}
+
+impl OnExit {
+    pub fn command(self) -> Option<TwoBCommand> {
+        match self {
+            OnExit::Kill => Some(TwoBCommand::Kill),
+            OnExit::Reset => Some(TwoBCommand::Reset),
+            OnExit::Leave => None,
+        }
+    }
+}
+
 pub struct USBTwoB {
-    state: TwoBState,
-    version: String,
-    io: Box<dyn SerialPort>,
+    core: DeviceCore,
+    link: Link,
+    opener: Option<Opener>,
+    on_exit: OnExit,
 }
 
 impl TryFrom<&str> for USBTwoB {
     type Error = TwoBError;
-    fn try_from(tty: &str) -> Result<Self, TwoBError> {
-        let mut io = serialport::new(tty, 9600)
-            .data_bits(DataBits::Eight)
-            .stop_bits(StopBits::One)
-            .parity(Parity::None)
-            .timeout(Duration::from_millis(TIMEOUT))
-            .flow_control(FlowControl::None)
-            .open()?;
-        //sleep(Duration::from_millis(TIMEOUT));
-        let answer = Self::_send(&mut io, String::from("V"))?;
-        let (state, version) = Self::parse(answer)?;
-        Ok(USBTwoB {
-            io,
-            state,
-            version,
-        })
+    /// Connects to a local serial port or to a network address understood by
+    /// `open_transport`.
+    fn try_from(address: &str) -> Result<Self, TwoBError> {
+        let opener: Opener = if is_network_address(address) {
+            let address = address.to_string();
+            Box::new(move || open_transport(&address))
+        } else {
+            SerialLocator::new(address).into_opener()
+        };
+        Self::connect(opener, LinkConfig::default())
     }
 }
 
 impl USBTwoB {
     pub fn new() -> Result<Self, TwoBError> {
-        for port in serialport::available_ports()? {
-            if let Ok(two_b) = USBTwoB::try_from(port.port_name.as_str()) {
-                return Ok(two_b);
-            }
+        Self::discover(&DiscoveryOptions::default())
+    }
+
+    /// Connects to the first 2B found with `options`, keeping the port it was probed on
+    /// open.
+    pub fn discover(options: &DiscoveryOptions) -> Result<Self, TwoBError> {
+        let ports = serialport::available_ports()?;
+        let (found, mut link, reply) = probe_ports(&ports, options)
+            .next()
+            .ok_or_else(|| TwoBError::ConnectionError("2B could not be detected!".into()))?;
+        link.set_config(LinkConfig::default());
+        let locator = SerialLocator {
+            path: found.port,
+            serial_number: found.usb.and_then(|usb| usb.serial_number),
+        };
+        Ok(Self::from_link(link, reply, Some(locator.into_opener())))
+    }
+
+    pub fn from_transport<T: Transport + 'static>(io: T) -> Result<Self, TwoBError> {
+        Self::with_config(io, LinkConfig::default())
+    }
+
+    pub fn with_config<T: Transport + 'static>(
+        io: T,
+        config: LinkConfig,
+    ) -> Result<Self, TwoBError> {
+        Self::open(Box::new(io), None, config)
+    }
+
+    /// Connects through `opener`, which is called again to reopen the device whenever
+    /// the connection is lost.
+    pub fn connect(mut opener: Opener, config: LinkConfig) -> Result<Self, TwoBError> {
+        let io = opener()?;
+        Self::open(io, Some(opener), config)
+    }
+
+    fn open(
+        io: Box<dyn Transport>,
+        opener: Option<Opener>,
+        config: LinkConfig,
+    ) -> Result<Self, TwoBError> {
+        let mut link = Link::new(io, config);
+        let reply = link.request(&TwoBCommand::RefreshState)?;
+        Ok(Self::from_link(link, reply, opener))
+    }
+
+    /// Wraps a `link` the device already answered `reply` on.
+    fn from_link(link: Link, reply: TwoBReply, opener: Option<Opener>) -> Self {
+        USBTwoB {
+            core: DeviceCore::new(reply),
+            link,
+            opener,
+            on_exit: OnExit::default(),
         }
-        Err(TwoBError::ConnectionError("2B could not be detected!".into()))
     }
 
-    fn _send(io: &mut Box<dyn SerialPort>, msg: String) -> Result<String, TwoBError> {
-        let full_msg = msg + "\r";
-        assert!(io.write(full_msg.as_bytes())? != 0);
-        io.flush()?;
-        let mut answer = String::new();
-        let mut reader = BufReader::new(io);
-        reader.read_line(&mut answer)?;
-        Ok(answer)
+    pub fn link_config(&self) -> &LinkConfig {
+        self.link.config()
+    }
+
+    pub fn set_link_config(&mut self, config: LinkConfig) {
+        self.link.set_config(config);
+    }
+
+    pub fn reconnect_config(&self) -> &ReconnectConfig {
+        &self.core.reconnect
+    }
+
+    pub fn set_reconnect_config(&mut self, config: ReconnectConfig) {
+        self.core.reconnect = config;
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.core.connection
+    }
+
+    pub fn on_connection_change(&mut self, listener: ConnectionListener) {
+        self.core.listeners.push(listener);
+    }
+
+    pub fn on_failure(&self) -> OnFailure {
+        self.core.on_failure
     }
 
-    fn parse(answer: String) -> Result<(TwoBState, String), TwoBError> {
-        let state = TwoBState::try_from(&answer)?;
-        let version = match answer.trim().split(':').last() {
-            Some(x) => x,
-            None => return Err("Couldn't parse version string".into()),
+    /// Chooses whether a failed `set_state` rolls the device back or leaves it as is.
+    pub fn set_on_failure(&mut self, on_failure: OnFailure) {
+        self.core.on_failure = on_failure;
+    }
+
+    pub fn on_exit(&self) -> OnExit {
+        self.on_exit
+    }
+
+    /// Chooses what is sent to the device once this `USBTwoB` is dropped.
+    pub fn set_on_exit(&mut self, on_exit: OnExit) {
+        self.on_exit = on_exit;
+    }
+
+    /// Reopens the device and, if configured, restores the last known state with
+    /// channel A and B capped at the safe level.
+    pub fn reconnect(&mut self) -> Result<(), TwoBError> {
+        let mut opener = match self.opener.take() {
+            Some(opener) => opener,
+            None => {
+                return Err(TwoBError::ConnectionError(
+                    "Transport can't be reopened".into(),
+                ))
+            }
         };
-        Ok((state, version.into()))
+        let previous = self.core.state.clone();
+        self.core.set_connection(ConnectionState::Reconnecting);
+
+        let mut result = Err(TwoBError::ConnectionError("No reconnect attempt".into()));
+        for attempt in 0..self.core.reconnect.attempts {
+            if let Some(delay) = self.core.attempt_delay(attempt) {
+                sleep(delay);
+            }
+            result = opener().and_then(|io| {
+                self.link.set_transport(io);
+                self.link.request(&TwoBCommand::RefreshState)
+            });
+            if result.is_ok() {
+                break;
+            }
+        }
+        self.opener = Some(opener);
+
+        let result = result.and_then(|reply| {
+            self.core.update(reply);
+            match self.core.restore_target(previous) {
+                Some(target) => self.set_state(target),
+                None => Ok(()),
+            }
+        });
+        self.core.reconnected(&result);
+        result
     }
 
-    fn send(&mut self, msg: String) -> Result<(), TwoBError> {
-        let answer = Self::_send(&mut self.io, msg)?;
-        let (state, version) = Self::parse(answer)?;
-        self.state = state;
-        self.version = version;
-        Ok(())
+    fn send(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        match self.link.request(&command) {
+            Ok(reply) => {
+                self.core.update(reply);
+                Ok(())
+            }
+            Err(e) if self.core.should_reconnect(&e, self.opener.is_some()) => {
+                self.core.set_connection(ConnectionState::Disconnected);
+                self.reconnect()?;
+                // Only commands that are safe to apply twice are repeated, the lost
+                // one might have reached the device.
+                if command.is_idempotent() {
+                    let reply = self.link.request(&command)?;
+                    self.core.update(reply);
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for USBTwoB {
+    /// Sends the `OnExit` command once, without reconnecting: a device that can't be
+    /// reached anymore isn't waited for.
+    fn drop(&mut self) {
+        if let Some(command) = self.on_exit.command() {
+            let _ = self.link.request_once(&command);
+        }
     }
 }
 
 impl TwoB for USBTwoB {
+    fn execute(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        let result = self
+            .get_capabilities()
+            .check(&command)
+            .and_then(|_| self.send(command));
+        if let Err(e) = &result {
+            self.core.events.error(Some(command), e);
+        }
+        result
+    }
+
     fn refresh_state(&mut self) -> Result<(), TwoBError> {
-        self.send("V".to_string())
+        self.execute(TwoBCommand::RefreshState)
     }
 
     fn reset(&mut self) -> Result<(), TwoBError> {
-        self.send("E".to_string())
+        self.execute(TwoBCommand::Reset)
     }
 
     fn kill(&mut self) -> Result<(), TwoBError> {
-        self.send("K".to_string())
+        self.execute(TwoBCommand::Kill)
     }
 
     fn set_joined_channels(&mut self, enable: bool) -> Result<(), TwoBError> {
-        self.send(format!("J{}", if enable { 1 } else { 0 }))
+        self.execute(TwoBCommand::SetJoinedChannels(enable))
     }
 
     fn set_mode(&mut self, mode: TwoBMode) -> Result<(), TwoBError> {
-        let number: u8 = mode.into();
-        return self.send(format!("M{}", number));
+        self.execute(TwoBCommand::SetMode(mode))
     }
 
     fn set_power(&mut self, power: TwoBPower) -> Result<(), TwoBError> {
-        self.send(power.value().to_string())
+        self.execute(TwoBCommand::SetPower(power))
     }
 
     fn set_map(&mut self, map: TwoBMap) -> Result<(), TwoBError> {
-        let number: u8 = map.into();
-        return self.send(format!("O{}", number));
+        self.execute(TwoBCommand::SetMap(map))
     }
 
     fn set_bias(&mut self, bias: TwoBBias) -> Result<(), TwoBError> {
-        let number: u8 = bias.into();
-        return self.send(format!("Q{}", number));
+        self.execute(TwoBCommand::SetBias(bias))
     }
 
     fn set_ramp(&mut self, ramp: TwoBRamp) -> Result<(), TwoBError> {
-        let number: u8 = ramp.into();
-        return self.send(format!("R{}", number));
+        self.execute(TwoBCommand::SetRamp(ramp))
     }
 
     fn set_warp(&mut self, warp: TwoBWarp) -> Result<(), TwoBError> {
-        let number: u8 = warp.into();
-        return self.send(format!("W{}", number));
+        self.execute(TwoBCommand::SetWarp(warp))
     }
 
     fn increment_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
-        self.send(format!("{}+", channel.value()))
+        self.execute(TwoBCommand::IncrementChannel(channel))
     }
 
     fn decrement_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
-        self.send(format!("{}-", channel.value()))
+        self.execute(TwoBCommand::DecrementChannel(channel))
     }
 
     fn set_channel(&mut self, channel: TwoBChannel, value: u8) -> Result<(), TwoBError> {
-        self.send(format!("{}{}", channel.value(), value))
+        self.execute(TwoBCommand::SetChannel(channel, value))
     }
 
     fn set_state(&mut self, state: TwoBState) -> Result<(), TwoBError> {
-        macro_rules! compare {
-            ($field:ident) => {
-                self.state.$field != state.$field
-            };
-            ($field:ident, $func:ident) => {
-                if compare!($field) {
-                    self.$func(state.$field)?;
-                }
-            };
-            ($field:ident, $channel: path) => {
-                if compare!($field) {
-                    self.set_channel($channel, state.$field)?;
-                }
-            };
+        let result = self
+            .get_capabilities()
+            .check_state(&state)
+            .and_then(|_| Transaction::new(state, self.core.on_failure).apply(self));
+        if let Err(e) = &result {
+            self.core.events.error(None, e);
         }
+        result.map(|_| ())
+    }
 
-        compare!(mode, set_mode);
-        compare!(power, set_power);
-        compare!(bias, set_bias);
-        compare!(joined_channels, set_joined_channels);
-        compare!(map, set_map);
-        compare!(ramp, set_ramp);
-        compare!(warp, set_warp);
-
-        compare!(channel_a, TwoBChannel::A);
-        compare!(channel_b, TwoBChannel::B);
-        compare!(channel_c, TwoBChannel::C);
-        compare!(channel_d, TwoBChannel::D);
+    fn get_state(&self) -> TwoBState {
+        self.core.state.clone()
+    }
 
-        Ok(())
+    fn subscribe(&mut self, listener: EventListener) -> SubscriptionId {
+        self.core.events.subscribe(listener)
     }
 
-    fn get_state(&self) -> TwoBState {
-        self.state.clone()
+    fn unsubscribe(&mut self, subscription: SubscriptionId) -> bool {
+        self.core.events.unsubscribe(subscription)
     }
 
     fn get_channel(&self, channel: TwoBChannel) -> u8 {
         match channel {
-            TwoBChannel::A => self.state.channel_a,
-            TwoBChannel::B => self.state.channel_b,
-            TwoBChannel::C => self.state.channel_c,
-            TwoBChannel::D => self.state.channel_d,
+            TwoBChannel::A => self.core.state.channel_a,
+            TwoBChannel::B => self.core.state.channel_b,
+            TwoBChannel::C => self.core.state.channel_c,
+            TwoBChannel::D => self.core.state.channel_d,
         }
     }
 
     fn get_version(&self) -> String {
-        self.version.clone()
+        self.core.version.clone()
+    }
+
+    fn exit_command(&self) -> Option<TwoBCommand> {
+        self.on_exit.command()
     }
 }
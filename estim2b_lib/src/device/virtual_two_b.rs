@@ -3,11 +3,16 @@ use crate::*;
 pub struct VirtualTwoB {
     state: TwoBState,
     version: String,
+    events: EventBus,
 }
 
 impl VirtualTwoB {
     pub fn new() -> Result<Self, TwoBError> {
-        let version = String::from("2.122B");
+        Self::with_version("2.122B")
+    }
+
+    pub fn with_version(version: &str) -> Result<Self, TwoBError> {
+        let version = String::from(version);
         let state = TwoBState {
             channel_a: 0,
             channel_b: 0,
@@ -25,105 +30,136 @@ impl VirtualTwoB {
         Ok(VirtualTwoB {
             state,
             version,
+            events: EventBus::new(),
         })
     }
+
+    fn level(&mut self, channel: TwoBChannel) -> &mut u8 {
+        match channel {
+            TwoBChannel::A => &mut self.state.channel_a,
+            TwoBChannel::B => &mut self.state.channel_b,
+            TwoBChannel::C => &mut self.state.channel_c,
+            TwoBChannel::D => &mut self.state.channel_d,
+        }
+    }
+
+    fn apply(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        use TwoBCommand::*;
+        command.validate()?;
+        self.get_capabilities().check(&command)?;
+        match command {
+            RefreshState => {}
+            Reset => {
+                self.state = TwoBState {
+                    channel_a: 0,
+                    channel_b: 0,
+                    channel_c: 50,
+                    channel_d: 50,
+                    mode: TwoBMode::Pulse,
+                    joined_channels: false,
+                    power: TwoBPower::LOW,
+                    bias: TwoBBias::A,
+                    map: TwoBMap::A,
+                    ramp: TwoBRamp::X1,
+                    warp: TwoBWarp::X1,
+                    ..self.state
+                }
+            }
+            Kill => {
+                self.state.channel_a = 0;
+                self.state.channel_b = 0;
+            }
+            SetJoinedChannels(enable) => self.state.joined_channels = enable,
+            SetMode(mode) => self.state.mode = mode,
+            SetPower(power) => self.state.power = power,
+            SetMap(map) => self.state.map = map,
+            SetBias(bias) => self.state.bias = bias,
+            SetRamp(ramp) => self.state.ramp = ramp,
+            SetWarp(warp) => self.state.warp = warp,
+            IncrementChannel(channel) => {
+                let max_level = *self.get_capabilities().levels.end();
+                let level = self.level(channel);
+                *level = level.saturating_add(1).min(max_level);
+            }
+            DecrementChannel(channel) => {
+                let level = self.level(channel);
+                *level = level.saturating_sub(1);
+            }
+            SetChannel(channel, value) => *self.level(channel) = value,
+        }
+        Ok(())
+    }
 }
 
 impl TwoB for VirtualTwoB {
+    fn execute(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        let old = self.state.clone();
+        let result = self.apply(command);
+        match &result {
+            Ok(()) => self.events.state_changed(&old, &self.state),
+            Err(e) => self.events.error(Some(command), e),
+        }
+        result
+    }
+
     fn refresh_state(&mut self) -> Result<(), TwoBError> {
-        Ok(())
+        self.execute(TwoBCommand::RefreshState)
     }
 
     fn reset(&mut self) -> Result<(), TwoBError> {
-        self.state = TwoBState {
-            channel_a: 0,
-            channel_b: 0,
-            channel_c: 50,
-            channel_d: 50,
-            mode: TwoBMode::Pulse,
-            joined_channels: false,
-            power: TwoBPower::LOW,
-            bias: TwoBBias::A,
-            map: TwoBMap::A,
-            ramp: TwoBRamp::X1,
-            warp: TwoBWarp::X1,
-            ..self.state
-        };
-        Ok(())
+        self.execute(TwoBCommand::Reset)
     }
 
     fn kill(&mut self) -> Result<(), TwoBError> {
-        self.state.channel_a = 0;
-        self.state.channel_b = 0;
-        Ok(())
+        self.execute(TwoBCommand::Kill)
     }
 
     fn set_joined_channels(&mut self, enable: bool) -> Result<(), TwoBError> {
-        self.state.joined_channels = enable;
-        Ok(())
+        self.execute(TwoBCommand::SetJoinedChannels(enable))
     }
 
     fn set_mode(&mut self, mode: TwoBMode) -> Result<(), TwoBError> {
-        self.state.mode = mode;
-        Ok(())
+        self.execute(TwoBCommand::SetMode(mode))
     }
 
     fn set_power(&mut self, power: TwoBPower) -> Result<(), TwoBError> {
-        self.state.power = power;
-        Ok(())
+        self.execute(TwoBCommand::SetPower(power))
     }
 
     fn set_map(&mut self, map: TwoBMap) -> Result<(), TwoBError> {
-        self.state.map = map;
-        Ok(())
+        self.execute(TwoBCommand::SetMap(map))
     }
 
     fn set_bias(&mut self, bias: TwoBBias) -> Result<(), TwoBError> {
-        self.state.bias = bias;
-        Ok(())
+        self.execute(TwoBCommand::SetBias(bias))
     }
 
     fn set_ramp(&mut self, ramp: TwoBRamp) -> Result<(), TwoBError> {
-        self.state.ramp = ramp;
-        Ok(())
+        self.execute(TwoBCommand::SetRamp(ramp))
     }
 
     fn set_warp(&mut self, warp: TwoBWarp) -> Result<(), TwoBError> {
-        self.state.warp = warp;
-        Ok(())
+        self.execute(TwoBCommand::SetWarp(warp))
     }
 
     fn increment_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
-        match channel {
-            TwoBChannel::A => self.state.channel_a += 1,
-            TwoBChannel::B => self.state.channel_b += 1,
-            TwoBChannel::C => self.state.channel_c += 1,
-            TwoBChannel::D => self.state.channel_d += 1,
-        }
-        Ok(())
+        self.execute(TwoBCommand::IncrementChannel(channel))
     }
 
     fn decrement_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
-        match channel {
-            TwoBChannel::A => self.state.channel_a -= 1,
-            TwoBChannel::B => self.state.channel_b -= 1,
-            TwoBChannel::C => self.state.channel_c -= 1,
-            TwoBChannel::D => self.state.channel_d -= 1,
-        }
-        Ok(())
+        self.execute(TwoBCommand::DecrementChannel(channel))
     }
 
     fn set_channel(&mut self, channel: TwoBChannel, value: u8) -> Result<(), TwoBError> {
-        match channel {
-            TwoBChannel::A => self.state.channel_a = value,
-            TwoBChannel::B => self.state.channel_b = value,
-            TwoBChannel::C => self.state.channel_c = value,
-            TwoBChannel::D => self.state.channel_d = value,
-        }
-        Ok(())
+        self.execute(TwoBCommand::SetChannel(channel, value))
     }
 
     fn set_state(&mut self, state: TwoBState) -> Result<(), TwoBError> {
+        if let Err(e) = self.get_capabilities().check_state(&state) {
+            self.events.error(None, &e);
+            return Err(e);
+        }
+        self.events.state_changed(&self.state, &state);
         self.state = state;
         Ok(())
     }
@@ -132,6 +168,14 @@ impl TwoB for VirtualTwoB {
         self.state.clone()
     }
 
+    fn subscribe(&mut self, listener: EventListener) -> SubscriptionId {
+        self.events.subscribe(listener)
+    }
+
+    fn unsubscribe(&mut self, subscription: SubscriptionId) -> bool {
+        self.events.unsubscribe(subscription)
+    }
+
     fn get_version(&self) -> String {
         self.version.clone()
     }
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+use crate::*;
+
+/// What engaged an emergency stop.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq, Serialize, Deserialize)]
+pub enum StopSource {
+    Library,
+    Api,
+    Cli,
+    Signal,
+    Watchdog,
+}
+
+/// A latched emergency stop. While it is engaged, every command raising channel A or
+/// B is refused, until it is reset with a reason.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EmergencyStop {
+    pub source: StopSource,
+    pub engaged_at: SystemTime,
+}
+
+pub(crate) const ENGAGED: &str = "the emergency stop is engaged";
+
+impl EmergencyStop {
+    pub fn new(source: StopSource) -> Self {
+        EmergencyStop {
+            source,
+            engaged_at: SystemTime::now(),
+        }
+    }
+
+    /// Refuses `command` if it would raise channel A or B of a device in `state`.
+    pub fn check(&self, command: &TwoBCommand, state: &TwoBState) -> Result<(), TwoBError> {
+        use TwoBCommand::*;
+        let raises = match *command {
+            SetChannel(TwoBChannel::A, level) => level > state.channel_a,
+            SetChannel(TwoBChannel::B, level) => level > state.channel_b,
+            IncrementChannel(TwoBChannel::A | TwoBChannel::B) => true,
+            // One of the channels takes over the level of the other.
+            SetJoinedChannels(true) => state.channel_a != state.channel_b,
+            _ => false,
+        };
+        if raises {
+            return Err(TwoBError::refused(&format!("{:?}", command), ENGAGED));
+        }
+        Ok(())
+    }
+
+    /// Refuses a transition from `current` to `state` that raises channel A or B.
+    pub fn check_state(&self, state: &TwoBState, current: &TwoBState) -> Result<(), TwoBError> {
+        if state.channel_a > current.channel_a || state.channel_b > current.channel_b {
+            return Err(TwoBError::refused("set_state", ENGAGED));
+        }
+        Ok(())
+    }
+}
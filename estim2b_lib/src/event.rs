@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+use crate::*;
+
+/// Receives every event of the device it is subscribed to.
+pub type EventListener = Box<dyn FnMut(&TwoBEvent) + Send>;
+
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+    Reconnecting,
+}
+
+/// Identifies a listener passed to `TwoB::subscribe`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SubscriptionId(u64);
+
+impl SubscriptionId {
+    /// Returned by devices that don't report events.
+    pub const NONE: SubscriptionId = SubscriptionId(u64::MAX);
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TwoBChange {
+    Level {
+        channel: TwoBChannel,
+        old: u8,
+        new: u8,
+    },
+    Mode {
+        old: TwoBMode,
+        new: TwoBMode,
+    },
+    Power {
+        old: TwoBPower,
+        new: TwoBPower,
+    },
+    Bias {
+        old: TwoBBias,
+        new: TwoBBias,
+    },
+    JoinedChannels {
+        old: bool,
+        new: bool,
+    },
+    Map {
+        old: TwoBMap,
+        new: TwoBMap,
+    },
+    Ramp {
+        old: TwoBRamp,
+        new: TwoBRamp,
+    },
+    Warp {
+        old: TwoBWarp,
+        new: TwoBWarp,
+    },
+    Battery {
+        old: u16,
+        new: u16,
+    },
+    Version {
+        old: String,
+        new: String,
+    },
+    Connection {
+        old: ConnectionState,
+        new: ConnectionState,
+    },
+    /// A call failed. `command` is `None` for calls that aren't a single command, like
+    /// `set_state`.
+    Error {
+        command: Option<TwoBCommand>,
+        message: String,
+    },
+    /// A watchdog lease ran out without a heartbeat.
+    LeaseExpired {
+        lease: LeaseId,
+        name: String,
+    },
+    /// The last watchdog lease ran out and `action` is being taken.
+    WatchdogTripped {
+        action: ExpiryAction,
+    },
+    EmergencyStopEngaged {
+        stop: EmergencyStop,
+    },
+    EmergencyStopReset {
+        reason: String,
+    },
+    /// The estimated charge fell to the threshold at `percent`.
+    BatteryLow {
+        percent: u8,
+        action: BatteryAction,
+    },
+    /// The session has `remaining` time left.
+    SessionWarning {
+        remaining: Duration,
+    },
+    /// The session reached its limit; the device was killed and raising levels is
+    /// refused for `cooldown`.
+    SessionLimitReached {
+        cooldown: Duration,
+    },
+    CooldownEnded,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TwoBEvent {
+    pub timestamp: SystemTime,
+    pub change: TwoBChange,
+}
+
+/// Every change between two states, in the order of the state's fields.
+pub fn changes(old: &TwoBState, new: &TwoBState) -> Vec<TwoBChange> {
+    let mut changes = Vec::new();
+    macro_rules! compare {
+        ($field:ident, $change:ident) => {
+            if old.$field != new.$field {
+                changes.push(TwoBChange::$change {
+                    old: old.$field,
+                    new: new.$field,
+                });
+            }
+        };
+    }
+    compare!(mode, Mode);
+    for (channel, old, new) in [
+        (TwoBChannel::A, old.channel_a, new.channel_a),
+        (TwoBChannel::B, old.channel_b, new.channel_b),
+        (TwoBChannel::C, old.channel_c, new.channel_c),
+        (TwoBChannel::D, old.channel_d, new.channel_d),
+    ] {
+        if old != new {
+            changes.push(TwoBChange::Level { channel, old, new });
+        }
+    }
+    compare!(power, Power);
+    compare!(bias, Bias);
+    compare!(joined_channels, JoinedChannels);
+    compare!(map, Map);
+    compare!(ramp, Ramp);
+    compare!(warp, Warp);
+    compare!(battery, Battery);
+    changes
+}
+
+/// Listeners of one device. `TwoB` implementations keep one and report every change
+/// to it.
+#[derive(Default)]
+pub struct EventBus {
+    listeners: Vec<(SubscriptionId, EventListener)>,
+    next_id: u64,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&mut self, listener: EventListener) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.listeners.push((id, listener));
+        id
+    }
+
+    /// Returns whether `subscription` was subscribed.
+    pub fn unsubscribe(&mut self, subscription: SubscriptionId) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|(id, _)| *id != subscription);
+        self.listeners.len() != before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    pub fn emit(&mut self, change: TwoBChange) {
+        self.forward(&TwoBEvent {
+            timestamp: SystemTime::now(),
+            change,
+        });
+    }
+
+    /// Passes an event on unchanged, e.g. one received from a wrapped device.
+    pub fn forward(&mut self, event: &TwoBEvent) {
+        for (_, listener) in self.listeners.iter_mut() {
+            listener(event);
+        }
+    }
+
+    /// Emits every change between `old` and `new`.
+    pub fn state_changed(&mut self, old: &TwoBState, new: &TwoBState) {
+        if self.listeners.is_empty() {
+            return;
+        }
+        for change in changes(old, new) {
+            self.emit(change);
+        }
+    }
+
+    pub fn version_changed(&mut self, old: &str, new: &str) {
+        if old != new {
+            self.emit(TwoBChange::Version {
+                old: old.into(),
+                new: new.into(),
+            });
+        }
+    }
+
+    pub fn error(&mut self, command: Option<TwoBCommand>, error: &TwoBError) {
+        self.emit(TwoBChange::Error {
+            command,
+            message: format!("{:?}", error),
+        });
+    }
+}
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::*;
+
+/// Firmware revision as reported in the status line, e.g. `2.122B`.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u16,
+    pub variant: Option<char>,
+}
+
+impl FirmwareVersion {
+    pub const fn new(major: u8, minor: u16, variant: Option<char>) -> Self {
+        FirmwareVersion {
+            major,
+            minor,
+            variant,
+        }
+    }
+}
+
+impl FromStr for FirmwareVersion {
+    type Err = TwoBError;
+    fn from_str(s: &str) -> Result<Self, TwoBError> {
+        let invalid = || TwoBError::ParserError(format!("Invalid firmware version '{}'", s));
+        let (major, rest) = s.trim().split_once('.').ok_or_else(invalid)?;
+        let (minor, variant) = match rest.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => (&rest[..i], Some(c)),
+            _ => (rest, None),
+        };
+        if major.is_empty() || minor.is_empty() {
+            return Err(invalid());
+        }
+        Ok(FirmwareVersion {
+            major: major.parse().map_err(|_| invalid())?,
+            minor: minor.parse().map_err(|_| invalid())?,
+            variant,
+        })
+    }
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if let Some(variant) = self.variant {
+            write!(f, "{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
+/// Features of the firmware revisions from `since` on.
+struct Revision {
+    since: FirmwareVersion,
+    dynamic_power: bool,
+    audio_modes: bool,
+    user_modes: bool,
+}
+
+/// Known firmware revisions, oldest first. Versions before the first one are treated
+/// like it, versions after the last one like the last one.
+const REVISIONS: [Revision; 2] = [
+    Revision {
+        since: FirmwareVersion::new(2, 0, None),
+        dynamic_power: false,
+        audio_modes: false,
+        user_modes: false,
+    },
+    Revision {
+        since: FirmwareVersion::new(2, 120, None),
+        dynamic_power: true,
+        audio_modes: true,
+        user_modes: true,
+    },
+];
+
+/// What a 2B running a given firmware revision supports.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub modes: Vec<TwoBMode>,
+    /// Levels accepted for channels A to D.
+    pub levels: RangeInclusive<u8>,
+    pub power_modes: Vec<TwoBPower>,
+    pub audio_modes: bool,
+    pub user_modes: bool,
+}
+
+impl Default for Capabilities {
+    /// Capabilities of the newest known firmware, used when the version is unknown.
+    fn default() -> Self {
+        let end: u8 = TwoBMode::Training.into();
+        Capabilities {
+            modes: (0..=end)
+                .filter_map(|mode| TwoBMode::try_from(mode).ok())
+                .collect(),
+            levels: 0..=TwoBCommand::MAX_LEVEL,
+            power_modes: vec![TwoBPower::HIGH, TwoBPower::LOW, TwoBPower::DYNAMIC],
+            audio_modes: true,
+            user_modes: true,
+        }
+    }
+}
+
+impl Capabilities {
+    pub fn for_version(version: &FirmwareVersion) -> Self {
+        let revision = REVISIONS
+            .iter()
+            .rev()
+            .find(|revision| revision.since <= *version)
+            .unwrap_or(&REVISIONS[0]);
+        let mut power_modes = vec![TwoBPower::HIGH, TwoBPower::LOW];
+        if revision.dynamic_power {
+            power_modes.push(TwoBPower::DYNAMIC);
+        }
+        Capabilities {
+            power_modes,
+            audio_modes: revision.audio_modes,
+            user_modes: revision.user_modes,
+            ..Capabilities::default()
+        }
+    }
+
+    /// Rejects commands the firmware doesn't support.
+    pub fn check(&self, command: &TwoBCommand) -> Result<(), TwoBError> {
+        match *command {
+            TwoBCommand::SetMode(mode) if !self.modes.contains(&mode) => Err(
+                TwoBError::Unsupported(format!("Mode {} is not supported", mode)),
+            ),
+            TwoBCommand::SetPower(power) if !self.power_modes.contains(&power) => Err(
+                TwoBError::Unsupported(format!("Power {} is not supported", power)),
+            ),
+            TwoBCommand::SetChannel(channel, value) if !self.levels.contains(&value) => {
+                Err(TwoBError::Unsupported(format!(
+                    "Level {} for channel {} is outside {} to {}",
+                    value,
+                    channel,
+                    self.levels.start(),
+                    self.levels.end()
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Rejects states the firmware can't represent.
+    pub fn check_state(&self, state: &TwoBState) -> Result<(), TwoBError> {
+        self.check(&TwoBCommand::SetMode(state.mode))?;
+        self.check(&TwoBCommand::SetPower(state.power))?;
+        self.check(&TwoBCommand::SetChannel(TwoBChannel::A, state.channel_a))?;
+        self.check(&TwoBCommand::SetChannel(TwoBChannel::B, state.channel_b))?;
+        self.check(&TwoBCommand::SetChannel(TwoBChannel::C, state.channel_c))?;
+        self.check(&TwoBCommand::SetChannel(TwoBChannel::D, state.channel_d))
+    }
+}
@@ -0,0 +1,1147 @@
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::emergency_stop::ENGAGED;
+use crate::*;
+
+/// Runs on the device, or gets the error refusing it, and returns what passes the
+/// outcome back to the caller. The caller is only answered once the snapshot reflects
+/// what the call did.
+type Call = Box<dyn FnOnce(Result<&mut dyn TwoB, TwoBError>) -> Notify + Send>;
+type Notify = Box<dyn FnOnce() + Send>;
+
+/// Where the worker of a `TwoBHandle` takes the time for ramps, polling, leases and
+/// sessions from, `Instant::now` unless set with `with_clock`.
+pub type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+thread_local! {
+    /// Set on worker threads, which must never wait for themselves.
+    static ON_WORKER: Cell<bool> = const { Cell::new(false) };
+    /// Set on the threads calling listeners, which hold the listeners' lock.
+    static ON_EVENTS: Cell<bool> = const { Cell::new(false) };
+}
+
+enum Request {
+    Submit(Vec<TwoBCommand>, Origin, Sender<Result<(), TwoBError>>),
+    Call(Call, Origin),
+    SetState(TwoBState, Origin, Sender<Result<(), TwoBError>>),
+    Configure(QueueConfig),
+    Poll(Option<Duration>),
+    Slew(SlewConfig),
+    Register(String, Duration, Sender<LeaseId>),
+    Heartbeat(LeaseId, Sender<bool>),
+    Release(LeaseId, Sender<bool>),
+    Watchdog(WatchdogConfig),
+    EmergencyStop(StopSource, Origin, Sender<Result<(), TwoBError>>),
+    ResetEmergencyStop(String, Origin, Sender<Result<(), TwoBError>>),
+    Shutdown(Sender<()>),
+    Exit(Sender<Result<(), TwoBError>>),
+    Battery(Option<BatteryConfig>),
+    Session(Option<SessionConfig>),
+    Audit(Option<AuditLog>),
+}
+
+/// Requests that run once the queue is drained.
+enum Deferred {
+    Call(Call, Origin),
+    SetState(TwoBState, Origin, Sender<Result<(), TwoBError>>),
+}
+
+/// What the worker last read from the device and its queue.
+struct Snapshot {
+    state: TwoBState,
+    as_of: SystemTime,
+    version: String,
+    capabilities: Capabilities,
+    queue: QueueMetrics,
+    ramps: Vec<Ramp>,
+    leases: Vec<Lease>,
+    emergency_stop: Option<EmergencyStop>,
+    battery: Option<BatteryStatus>,
+    session: Option<SessionStatus>,
+    audit_log: Option<PathBuf>,
+    /// When the snapshot was taken by the clock of the worker, to bring the session
+    /// status up to date.
+    taken: Instant,
+}
+
+/// State of the device together with when the device last confirmed it.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CachedState {
+    pub state: TwoBState,
+    pub as_of: SystemTime,
+    pub emergency_stop: Option<EmergencyStop>,
+    pub session: Option<SessionStatus>,
+}
+
+impl CachedState {
+    /// Time since the device last confirmed the state.
+    pub fn age(&self) -> Duration {
+        self.as_of.elapsed().unwrap_or_default()
+    }
+}
+
+/// Cheap, clonable handle to a 2B owned by a worker thread.
+///
+/// Commands are sent to the worker over a channel and go through a `CommandQueue`, so
+/// they are paced and coalesced and `Kill` jumps ahead of everything queued. Getters
+/// never wait for the worker: they read the state the device reported last. A panic
+/// in the device is returned as an error instead of poisoning the handle.
+///
+/// With a poll interval set, the worker refreshes the state whenever the link was idle
+/// that long, so changes made on the device itself are noticed and reported as
+/// events.
+///
+/// With a slew config set, rises of channel A and B, including those of `set_state`,
+/// are ramped up by the worker while the caller returns right away. `Kill` ends every
+/// ramp, `ramps` shows their progress.
+///
+/// An emergency stop kills the device right away and drops everything queued for the
+/// link, failing the waiting submitters. It latches: until it is reset with a reason,
+/// every command raising channel A or B fails with a `PolicyViolation`, and so does
+/// every `call`, as it could do anything on the device.
+///
+/// With a battery config set, the battery value of every confirmed state is sampled,
+/// and the actions of the thresholds the charge falls to are queued, `Kill` ahead of
+/// everything. Set a poll interval as well to keep sampling while idle.
+///
+/// With a session config set, the time channel A or B is above zero is counted. Close
+/// to the limit both are lowered to zero, at the limit the device is killed, and until
+/// the cooldown is over every command raising them fails with a `PolicyViolation`.
+///
+/// With an audit log set, every command, state and `call` is appended to it as it is
+/// sent to the device, or refused, with the `Origin` of the handle it came from, the
+/// resulting state and its error, if any. So are emergency stops and their resets. A
+/// command coalesced from several handles is logged with an `Origin::Coalesced`, the
+/// ramp steps, polls and actions the handle takes itself with an `Origin::Automatic`.
+///
+/// Remote controllers can register a watchdog lease and keep it alive with
+/// `heartbeat`. Once the last lease runs out, the worker takes the `ExpiryAction` of
+/// the watchdog config ahead of everything queued and reports it as an event.
+///
+/// Events are delivered on a thread of their own, so a listener may use the handle,
+/// e.g. kill the device, while the worker goes on. A listener must not subscribe or
+/// unsubscribe itself, that panics.
+///
+/// The worker stops and drops the device once every handle is dropped, and dropping
+/// the last handle waits for that, so a `USBTwoB` has sent its `OnExit` command before
+/// the process can exit. `shutdown` does the same right away.
+#[derive(Clone)]
+pub struct TwoBHandle {
+    /// Shared, so the panic hook can hold it weakly.
+    requests: Arc<Sender<Request>>,
+    snapshot: Arc<ArcSwap<Snapshot>>,
+    listeners: Arc<Mutex<EventBus>>,
+    origin: Origin,
+    clock: Clock,
+    // Declared last, so it is dropped after `requests` lets the worker stop.
+    _worker: Arc<WorkerThread>,
+}
+
+/// Joins the worker once the last handle is gone.
+struct WorkerThread(Option<JoinHandle<()>>);
+
+impl Drop for WorkerThread {
+    fn drop(&mut self) {
+        if let Some(thread) = self.0.take() {
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+impl TwoBHandle {
+    pub fn new<T: TwoB + 'static>(device: T) -> Self {
+        Self::from_box(Box::new(device))
+    }
+
+    pub fn from_box(device: Box<dyn TwoB>) -> Self {
+        Self::with_config(device, QueueConfig::default())
+    }
+
+    pub fn with_config(device: Box<dyn TwoB>, config: QueueConfig) -> Self {
+        Self::with_clock(device, config, Arc::new(Instant::now))
+    }
+
+    /// A handle whose worker takes the time from `clock`, so a test can step ramps,
+    /// leases and sessions along without waiting for them.
+    pub fn with_clock(mut device: Box<dyn TwoB>, config: QueueConfig, clock: Clock) -> Self {
+        let listeners = Arc::new(Mutex::new(EventBus::new()));
+        let events = dispatch(listeners.clone());
+        let forwarded = events.clone();
+        device.subscribe(Box::new(move |event| {
+            let _ = forwarded.send(event.clone());
+        }));
+        let queue = CommandQueue::new(config);
+        let snapshot = Arc::new(ArcSwap::from_pointee(Snapshot {
+            state: device.get_state(),
+            as_of: SystemTime::now(),
+            version: device.get_version(),
+            capabilities: device.get_capabilities(),
+            queue: queue.metrics(),
+            ramps: Vec::new(),
+            leases: Vec::new(),
+            emergency_stop: None,
+            battery: None,
+            session: None,
+            audit_log: None,
+            taken: clock(),
+        }));
+        let (requests, receiver) = mpsc::channel();
+        let worker = Worker {
+            device,
+            queue,
+            requests: receiver,
+            snapshot: snapshot.clone(),
+            waiting: Vec::new(),
+            next_submission: 0,
+            deferred: VecDeque::new(),
+            poll_interval: None,
+            slew: SlewLimiter::new(SlewConfig::default()),
+            watchdog: Watchdog::default(),
+            events,
+            emergency_stop: None,
+            exit: None,
+            battery: None,
+            session: None,
+            audit: None,
+            as_of: SystemTime::now(),
+            last_activity: clock(),
+            clock: clock.clone(),
+        };
+        let thread = thread::Builder::new()
+            .name("estim2b-device".into())
+            .spawn(move || worker.run())
+            .expect("Cannot spawn the 2B worker thread");
+        TwoBHandle {
+            requests: Arc::new(requests),
+            snapshot,
+            listeners,
+            origin: Origin::default(),
+            clock,
+            _worker: Arc::new(WorkerThread(Some(thread))),
+        }
+    }
+
+    /// A handle to the same device whose commands are audited as coming from `origin`.
+    pub fn with_origin(&self, origin: Origin) -> TwoBHandle {
+        TwoBHandle {
+            origin,
+            ..self.clone()
+        }
+    }
+
+    /// Origin the commands of this handle are audited with.
+    pub fn origin(&self) -> &Origin {
+        &self.origin
+    }
+
+    /// Queues `commands` and waits until the queue has been drained. The returned
+    /// error is the first one of these commands, or of a command of another handle
+    /// they were merged with.
+    pub fn submit(&self, commands: Vec<TwoBCommand>) -> Result<(), TwoBError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::Submit(commands, self.origin.clone(), reply))?;
+        result.recv().map_err(|_| stopped())?
+    }
+
+    /// Runs `f` on the worker once everything queued so far has been sent. Fails with
+    /// a `PolicyViolation` while the emergency stop is engaged.
+    pub fn call<R, F>(&self, f: F) -> Result<R, TwoBError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut dyn TwoB) -> R + Send + 'static,
+    {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::Call(
+            Box::new(move |two_b| {
+                let result = two_b.map(f);
+                Box::new(move || {
+                    let _ = reply.send(result);
+                })
+            }),
+            self.origin.clone(),
+        ))?;
+        result.recv().map_err(|_| stopped())?
+    }
+
+    /// State the device reported last.
+    pub fn state(&self) -> TwoBState {
+        self.snapshot.load().state.clone()
+    }
+
+    pub fn cached_state(&self) -> CachedState {
+        let snapshot = self.snapshot.load();
+        CachedState {
+            state: snapshot.state.clone(),
+            as_of: snapshot.as_of,
+            emergency_stop: snapshot.emergency_stop.clone(),
+            session: self.session_status(),
+        }
+    }
+
+    /// Kills the device ahead of everything queued and latches the emergency stop.
+    pub fn engage_emergency_stop(&self, source: StopSource) -> Result<(), TwoBError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::EmergencyStop(source, self.origin.clone(), reply))?;
+        result.recv().map_err(|_| stopped())?
+    }
+
+    /// Releases the emergency stop. `reason` must not be empty and is reported with
+    /// the event.
+    pub fn reset_emergency_stop(&self, reason: &str) -> Result<(), TwoBError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::ResetEmergencyStop(
+            reason.into(),
+            self.origin.clone(),
+            reply,
+        ))?;
+        result.recv().map_err(|_| stopped())?
+    }
+
+    /// The emergency stop, if it is engaged.
+    pub fn emergency_stop(&self) -> Option<EmergencyStop> {
+        self.snapshot.load().emergency_stop.clone()
+    }
+
+    pub fn version(&self) -> String {
+        self.snapshot.load().version.clone()
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.snapshot.load().capabilities.clone()
+    }
+
+    pub fn queue_metrics(&self) -> QueueMetrics {
+        self.snapshot.load().queue.clone()
+    }
+
+    /// Level rises in progress, with the level each is heading for.
+    pub fn ramps(&self) -> Vec<Ramp> {
+        self.snapshot.load().ramps.clone()
+    }
+
+    /// Watchdog leases still alive.
+    pub fn leases(&self) -> Vec<Lease> {
+        self.snapshot.load().leases.clone()
+    }
+
+    /// Registers a watchdog lease that runs out unless `heartbeat` is called at least
+    /// every `timeout`.
+    pub fn register_lease(&self, name: &str, timeout: Duration) -> Result<LeaseId, TwoBError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::Register(name.into(), timeout, reply))?;
+        result.recv().map_err(|_| stopped())
+    }
+
+    /// Keeps `lease` alive for another timeout. Returns `false` once it ran out or was
+    /// released.
+    pub fn heartbeat(&self, lease: LeaseId) -> Result<bool, TwoBError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::Heartbeat(lease, reply))?;
+        result.recv().map_err(|_| stopped())
+    }
+
+    /// Ends `lease` without tripping the watchdog.
+    pub fn release_lease(&self, lease: LeaseId) -> Result<bool, TwoBError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::Release(lease, reply))?;
+        result.recv().map_err(|_| stopped())
+    }
+
+    pub fn set_watchdog_config(&self, config: WatchdogConfig) -> Result<(), TwoBError> {
+        self.send(Request::Watchdog(config))
+    }
+
+    pub fn set_queue_config(&self, config: QueueConfig) -> Result<(), TwoBError> {
+        self.send(Request::Configure(config))
+    }
+
+    /// Refreshes the state after the link was idle for `interval`. `None`, the
+    /// default, turns polling off.
+    pub fn set_poll_interval(&self, interval: Option<Duration>) -> Result<(), TwoBError> {
+        self.send(Request::Poll(interval))
+    }
+
+    /// Limits how fast channel A and B rise. The default lets levels jump.
+    pub fn set_slew_config(&self, config: SlewConfig) -> Result<(), TwoBError> {
+        self.send(Request::Slew(config))
+    }
+
+    /// Charge estimate of the battery monitor, `None` without a battery config or
+    /// before the first sample.
+    pub fn battery_status(&self) -> Option<BatteryStatus> {
+        self.snapshot.load().battery.clone()
+    }
+
+    /// Monitors the battery with `config`. `None`, the default, turns monitoring off.
+    pub fn set_battery_config(&self, config: Option<BatteryConfig>) -> Result<(), TwoBError> {
+        self.send(Request::Battery(config))
+    }
+
+    /// Time counted in the current session and what is left of it, `None` without a
+    /// session config.
+    pub fn session_status(&self) -> Option<SessionStatus> {
+        let snapshot = self.snapshot.load();
+        let elapsed = (self.clock)().saturating_duration_since(snapshot.taken);
+        snapshot
+            .session
+            .as_ref()
+            .map(|status| status.after(elapsed))
+    }
+
+    /// Governs sessions with `config`. `None`, the default, lifts the limits.
+    pub fn set_session_config(&self, config: Option<SessionConfig>) -> Result<(), TwoBError> {
+        self.send(Request::Session(config))
+    }
+
+    /// File of the audit log, `None` while auditing is off.
+    pub fn audit_log_path(&self) -> Option<PathBuf> {
+        self.snapshot.load().audit_log.clone()
+    }
+
+    /// Appends every action carried out from now on to `log`. `None`, the default,
+    /// turns auditing off.
+    pub fn set_audit_log(&self, log: Option<AuditLog>) -> Result<(), TwoBError> {
+        self.send(Request::Audit(log))
+    }
+
+    /// Stops the worker and drops the device right away, so a `USBTwoB` sends its
+    /// `OnExit` command. Queued commands are discarded and every later call fails.
+    pub fn shutdown(&self) -> Result<(), TwoBError> {
+        if ON_WORKER.with(Cell::get) {
+            return Err(TwoBError::Unsupported(
+                "The 2B worker can't shut itself down".into(),
+            ));
+        }
+        let (reply, result) = mpsc::channel();
+        self.send(Request::Shutdown(reply))?;
+        result.recv().map_err(|_| stopped())
+    }
+
+    /// Installs a panic hook that sends the `exit_command` of the device, e.g. the
+    /// `OnExit` command of a `USBTwoB`, ahead of everything queued and then runs the
+    /// previous hook. The device stays usable, as the panic may be caught, e.g. by a
+    /// web server. The hook doesn't keep the worker running once every handle is
+    /// dropped. Panics of the device itself are left to the worker, which returns
+    /// them as errors.
+    pub fn exit_on_panic(&self) {
+        let requests = Arc::downgrade(&self.requests);
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !ON_WORKER.with(Cell::get) {
+                if let Some(requests) = requests.upgrade() {
+                    let (reply, result) = mpsc::channel();
+                    if requests.send(Request::Exit(reply)).is_ok() {
+                        let _ = result.recv();
+                    }
+                }
+            }
+            previous(info);
+        }));
+    }
+
+    fn send(&self, request: Request) -> Result<(), TwoBError> {
+        self.requests.send(request).map_err(|_| stopped())
+    }
+
+    fn listeners(&self) -> MutexGuard<'_, EventBus> {
+        assert!(
+            !ON_EVENTS.with(Cell::get),
+            "A listener can't subscribe or unsubscribe on a handle"
+        );
+        self.listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn stopped() -> TwoBError {
+    TwoBError::ConnectionError("The 2B worker stopped or panicked".into())
+}
+
+/// Passes events on to `listeners` on a thread of their own, so the worker never waits
+/// for a listener. The thread ends once every sender is gone.
+fn dispatch(listeners: Arc<Mutex<EventBus>>) -> Sender<TwoBEvent> {
+    let (events, received) = mpsc::channel::<TwoBEvent>();
+    thread::Builder::new()
+        .name("estim2b-events".into())
+        .spawn(move || {
+            ON_EVENTS.with(|on_events| on_events.set(true));
+            for event in received {
+                listeners
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .forward(&event);
+            }
+        })
+        .expect("Cannot spawn the 2B event thread");
+    events
+}
+
+impl TwoB for TwoBHandle {
+    fn execute(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        self.submit(vec![command])
+    }
+
+    fn refresh_state(&mut self) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::RefreshState)
+    }
+
+    fn reset(&mut self) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::Reset)
+    }
+
+    fn kill(&mut self) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::Kill)
+    }
+
+    fn set_joined_channels(&mut self, enable: bool) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetJoinedChannels(enable))
+    }
+
+    fn set_mode(&mut self, mode: TwoBMode) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetMode(mode))
+    }
+
+    fn set_power(&mut self, power: TwoBPower) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetPower(power))
+    }
+
+    fn set_map(&mut self, map: TwoBMap) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetMap(map))
+    }
+
+    fn set_bias(&mut self, bias: TwoBBias) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetBias(bias))
+    }
+
+    fn set_ramp(&mut self, ramp: TwoBRamp) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetRamp(ramp))
+    }
+
+    fn set_warp(&mut self, warp: TwoBWarp) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetWarp(warp))
+    }
+
+    fn increment_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::IncrementChannel(channel))
+    }
+
+    fn decrement_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::DecrementChannel(channel))
+    }
+
+    fn set_channel(&mut self, channel: TwoBChannel, value: u8) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetChannel(channel, value))
+    }
+
+    fn set_state(&mut self, state: TwoBState) -> Result<(), TwoBError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::SetState(state, self.origin.clone(), reply))?;
+        result.recv().map_err(|_| stopped())?
+    }
+
+    fn get_state(&self) -> TwoBState {
+        self.state()
+    }
+
+    fn subscribe(&mut self, listener: EventListener) -> SubscriptionId {
+        self.listeners().subscribe(listener)
+    }
+
+    fn unsubscribe(&mut self, subscription: SubscriptionId) -> bool {
+        self.listeners().unsubscribe(subscription)
+    }
+
+    fn get_version(&self) -> String {
+        self.version()
+    }
+
+    fn get_capabilities(&self) -> Capabilities {
+        self.capabilities()
+    }
+}
+
+/// Submitter waiting for the queue to drain, with the first error of its commands.
+struct Waiter {
+    submission: u64,
+    reply: Sender<Result<(), TwoBError>>,
+    result: Result<(), TwoBError>,
+    origin: Origin,
+}
+
+struct Worker {
+    device: Box<dyn TwoB>,
+    queue: CommandQueue,
+    requests: Receiver<Request>,
+    snapshot: Arc<ArcSwap<Snapshot>>,
+    waiting: Vec<Waiter>,
+    next_submission: u64,
+    deferred: VecDeque<Deferred>,
+    poll_interval: Option<Duration>,
+    slew: SlewLimiter,
+    watchdog: Watchdog,
+    events: Sender<TwoBEvent>,
+    emergency_stop: Option<EmergencyStop>,
+    exit: Option<Sender<()>>,
+    battery: Option<BatteryMonitor>,
+    session: Option<SessionGovernor>,
+    audit: Option<AuditLog>,
+    /// When the device last confirmed its state.
+    as_of: SystemTime,
+    last_activity: Instant,
+    clock: Clock,
+}
+
+impl Worker {
+    fn run(mut self) {
+        ON_WORKER.with(|on_worker| on_worker.set(true));
+        let exit = loop {
+            if let Some(reply) = self.exit.take() {
+                break Some(reply);
+            }
+            self.expire();
+            self.govern();
+            if self.queue.is_empty() && self.deferred.is_empty() && self.waiting.is_empty() {
+                let now = (self.clock)();
+                let timeout = [
+                    self.poll_wait(now),
+                    self.slew.wait_time(now),
+                    self.watchdog.wait_time(now),
+                    self.session.as_ref().and_then(|g| g.wait_time(now)),
+                ]
+                .into_iter()
+                .flatten()
+                .min();
+                let request = match timeout {
+                    None => self.requests.recv().ok(),
+                    Some(timeout) => match self.requests.recv_timeout(timeout) {
+                        Ok(request) => Some(request),
+                        Err(RecvTimeoutError::Timeout) => {
+                            let now = (self.clock)();
+                            if self.slew.wait_time(now) == Some(Duration::ZERO) {
+                                self.ramp();
+                            } else if self.poll_wait(now) == Some(Duration::ZERO) {
+                                self.poll();
+                            }
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Disconnected) => None,
+                    },
+                };
+                match request {
+                    Some(request) => self.accept(request),
+                    None => break None,
+                }
+                continue;
+            }
+            // Keep taking requests while waiting for the next slot, so a kill sent in
+            // the meantime still goes first.
+            let wait = self.queue.wait_time();
+            match self.requests.recv_timeout(wait) {
+                Ok(request) => self.accept(request),
+                Err(RecvTimeoutError::Timeout) => self.step(),
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(wait);
+                    self.step();
+                }
+            }
+        };
+        // Dropping the device sends its `OnExit` command before `shutdown` returns.
+        drop(self);
+        if let Some(reply) = exit {
+            let _ = reply.send(());
+        }
+    }
+
+    fn accept(&mut self, request: Request) {
+        match request {
+            Request::Submit(commands, origin, reply) => {
+                let submission = self.wait(origin, reply);
+                for command in commands {
+                    self.queue.push_from(command, submission);
+                }
+            }
+            Request::Call(call, origin) => self.deferred.push_back(Deferred::Call(call, origin)),
+            Request::SetState(state, origin, reply) => self
+                .deferred
+                .push_back(Deferred::SetState(state, origin, reply)),
+            Request::Configure(config) => self.queue.set_config(config),
+            Request::Poll(interval) => self.poll_interval = interval,
+            Request::Slew(config) => self.slew.set_config(config),
+            // Answered once the snapshot shows the lease.
+            Request::Register(name, timeout, reply) => {
+                let lease = self.watchdog.register(name, timeout, (self.clock)());
+                self.publish();
+                let _ = reply.send(lease);
+                return;
+            }
+            Request::Heartbeat(lease, reply) => {
+                let alive = self.watchdog.heartbeat(lease, (self.clock)());
+                self.publish();
+                let _ = reply.send(alive);
+                return;
+            }
+            Request::Release(lease, reply) => {
+                let released = self.watchdog.release(lease);
+                self.publish();
+                let _ = reply.send(released);
+                return;
+            }
+            Request::Watchdog(config) => self.watchdog.set_config(config),
+            Request::Shutdown(reply) => self.exit = Some(reply),
+            Request::Exit(reply) => {
+                let result = self.exit_device();
+                let _ = reply.send(result);
+                return;
+            }
+            Request::Battery(config) => match (&mut self.battery, config) {
+                (Some(monitor), Some(config)) => monitor.set_config(config),
+                (battery, config) => *battery = config.map(BatteryMonitor::new),
+            },
+            Request::Session(config) => match (&mut self.session, config) {
+                (Some(governor), Some(config)) => governor.set_config(config),
+                (session, config) => {
+                    *session = config.map(SessionGovernor::new);
+                    self.govern();
+                }
+            },
+            Request::Audit(log) => self.audit = log,
+            Request::EmergencyStop(source, origin, reply) => {
+                let result = self.engage(source, origin);
+                let _ = reply.send(result);
+                return;
+            }
+            Request::ResetEmergencyStop(reason, origin, reply) => {
+                let result = self.release(reason, origin);
+                let _ = reply.send(result);
+                return;
+            }
+        }
+        self.publish();
+    }
+
+    /// Sends the next queued command or, once the queue is drained, runs the next
+    /// deferred request.
+    fn step(&mut self) {
+        let state = self.device.get_state();
+        let capabilities = self.device.get_capabilities();
+        if let Some((command, submissions)) = self.queue.pop_from(&state, &capabilities) {
+            let origin = self.origin_of(&submissions);
+            let result = match self.check_limits(&command, &state) {
+                Err(e) => {
+                    let result = Err(e);
+                    self.audit(origin, AuditedAction::Commands(vec![command]), &result);
+                    result
+                }
+                Ok(()) => match self.slew.plan(command, &state) {
+                    Some(command) => self.execute(command, origin),
+                    // A rise that became a ramp takes its first step right away, so the
+                    // caller learns whether the device accepts it.
+                    None => self.ramp_step(origin),
+                },
+            };
+            self.confirmed(&result);
+            if let Err(e) = result {
+                for waiter in self.waiting.iter_mut() {
+                    if waiter.result.is_ok() && submissions.contains(&waiter.submission) {
+                        waiter.result = Err(e.clone());
+                    }
+                }
+            }
+            self.publish();
+        }
+        if !self.queue.is_empty() {
+            return;
+        }
+        for waiter in std::mem::take(&mut self.waiting) {
+            let _ = waiter.reply.send(waiter.result);
+        }
+        match self.deferred.pop_front() {
+            Some(Deferred::Call(call, origin)) => {
+                let (notify, result) = match self.emergency_stop {
+                    Some(_) => {
+                        let e = TwoBError::refused("call", ENGAGED);
+                        (Ok(call(Err(e.clone()))), Err(e))
+                    }
+                    None => {
+                        let notify = self.guarded(|two_b| call(Ok(two_b)));
+                        let result = notify.as_ref().map(|_| ()).map_err(Clone::clone);
+                        (notify, result)
+                    }
+                };
+                self.last_activity = (self.clock)();
+                self.audit(origin, AuditedAction::Call, &result);
+                self.publish();
+                if let Ok(notify) = notify {
+                    notify();
+                }
+            }
+            Some(Deferred::SetState(state, origin, reply)) => {
+                let current = self.device.get_state();
+                let result = match &self.emergency_stop {
+                    Some(stop) => stop.check_state(&state, &current),
+                    None => Ok(()),
+                };
+                let result = result.and_then(|()| match &self.session {
+                    Some(governor) => governor.check_state(&state, &current),
+                    None => Ok(()),
+                });
+                // The state actually sent, with rises left to the slew limiter.
+                let state = match result {
+                    Ok(()) => self.slew.plan_state(state, &current),
+                    Err(_) => state,
+                };
+                let result = result.and_then(|()| {
+                    let sent = state.clone();
+                    self.guarded(|two_b| two_b.set_state(sent)).and_then(|r| r)
+                });
+                self.confirmed(&result);
+                self.audit(origin, AuditedAction::SetState(state), &result);
+                self.publish();
+                let _ = reply.send(result);
+            }
+            None => {}
+        }
+    }
+
+    /// Refuses raises while the emergency stop is engaged or the session governor
+    /// holds levels down.
+    fn check_limits(&self, command: &TwoBCommand, state: &TwoBState) -> Result<(), TwoBError> {
+        let result = match &self.emergency_stop {
+            Some(stop) => stop.check(command, state),
+            None => Ok(()),
+        };
+        let result = result.and_then(|()| match &self.session {
+            Some(governor) => governor.check(command, state),
+            None => Ok(()),
+        });
+        if let Err(e) = &result {
+            self.error(Some(*command), e);
+        }
+        result
+    }
+
+    /// Latches the emergency stop, drops everything queued and sends `Kill` at once.
+    /// Engaging it again kills the device again but keeps the first source.
+    fn engage(&mut self, source: StopSource, origin: Origin) -> Result<(), TwoBError> {
+        self.slew.cancel();
+        self.queue.clear();
+        let dropped = TwoBError::refused("Queued commands", ENGAGED);
+        for waiter in self.waiting.iter_mut().filter(|w| w.result.is_ok()) {
+            waiter.result = Err(dropped.clone());
+        }
+        for deferred in std::mem::take(&mut self.deferred) {
+            match deferred {
+                Deferred::SetState(state, origin, reply) => {
+                    let result = Err(TwoBError::refused("set_state", ENGAGED));
+                    self.audit(origin, AuditedAction::SetState(state), &result);
+                    let _ = reply.send(result);
+                }
+                // Refused once they run, after the waiting submitters are answered.
+                call => self.deferred.push_back(call),
+            }
+        }
+        if self.emergency_stop.is_none() {
+            let stop = EmergencyStop::new(source);
+            self.emergency_stop = Some(stop.clone());
+            self.emit(TwoBChange::EmergencyStopEngaged { stop });
+        }
+        let result = self.guarded(|two_b| two_b.kill()).and_then(|r| r);
+        self.confirmed(&result);
+        self.audit(origin, AuditedAction::EmergencyStop(source), &result);
+        self.publish();
+        result
+    }
+
+    /// Sends the `exit_command` of the device at once and ends every ramp, so they
+    /// don't raise levels again.
+    fn exit_device(&mut self) -> Result<(), TwoBError> {
+        let command = match self.device.exit_command() {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        self.slew.cancel();
+        let result = self.execute(command, Origin::Automatic("panic".into()));
+        self.confirmed(&result);
+        self.publish();
+        result
+    }
+
+    fn release(&mut self, reason: String, origin: Origin) -> Result<(), TwoBError> {
+        if reason.trim().is_empty() {
+            return Err(TwoBError::ParserError(
+                "Resetting the emergency stop needs a reason".into(),
+            ));
+        }
+        if self.emergency_stop.take().is_some() {
+            self.audit(
+                origin,
+                AuditedAction::ResetEmergencyStop {
+                    reason: reason.clone(),
+                },
+                &Ok(()),
+            );
+            self.emit(TwoBChange::EmergencyStopReset { reason });
+            self.publish();
+        }
+        Ok(())
+    }
+
+    /// Queues `commands` the handle sends itself ahead of everything else, so they are
+    /// audited with `source` once sent.
+    fn queue_automatic(&mut self, source: &str, commands: Vec<TwoBCommand>) {
+        if commands.is_empty() {
+            return;
+        }
+        // Nobody waits for the reply.
+        let (reply, _) = mpsc::channel();
+        let submission = self.wait(Origin::Automatic(source.into()), reply);
+        self.queue.push_front_from(&commands, submission);
+    }
+
+    /// Adds a submitter waiting for the queue to drain and returns its submission.
+    fn wait(&mut self, origin: Origin, reply: Sender<Result<(), TwoBError>>) -> u64 {
+        let submission = self.next_submission;
+        self.next_submission += 1;
+        self.waiting.push(Waiter {
+            submission,
+            reply,
+            result: Ok(()),
+            origin,
+        });
+        submission
+    }
+
+    /// Origin of a command merged from `submissions`.
+    fn origin_of(&self, submissions: &[u64]) -> Origin {
+        let mut origins: Vec<Origin> = Vec::new();
+        for waiter in &self.waiting {
+            if submissions.contains(&waiter.submission) && !origins.contains(&waiter.origin) {
+                origins.push(waiter.origin.clone());
+            }
+        }
+        match origins.len() {
+            0 => Origin::default(),
+            1 => origins.remove(0),
+            _ => Origin::Coalesced(origins),
+        }
+    }
+
+    /// Sends `command` and audits it with its result.
+    fn execute(&mut self, command: TwoBCommand, origin: Origin) -> Result<(), TwoBError> {
+        let result = self.guarded(|two_b| two_b.execute(command)).and_then(|r| r);
+        self.audit(origin, AuditedAction::Commands(vec![command]), &result);
+        result
+    }
+
+    /// Appends the outcome of `action` to the audit log, reporting a failing write as
+    /// an error event.
+    fn audit(&mut self, origin: Origin, action: AuditedAction, result: &Result<(), TwoBError>) {
+        let log = match &mut self.audit {
+            Some(log) => log,
+            None => return,
+        };
+        let state = self.device.get_state();
+        if let Err(e) = log.append(origin, action, state, result.clone().err()) {
+            self.error(None, &e);
+        }
+    }
+
+    fn poll_wait(&self, now: Instant) -> Option<Duration> {
+        self.poll_interval
+            .map(|interval| (self.last_activity + interval).saturating_duration_since(now))
+    }
+
+    /// Drops leases that ran out and, once the last one did, queues the expiry action.
+    fn expire(&mut self) {
+        let (expired, tripped) = self.watchdog.expire((self.clock)());
+        if expired.is_empty() {
+            return;
+        }
+        for lease in expired {
+            self.emit(TwoBChange::LeaseExpired {
+                lease: lease.id,
+                name: lease.name,
+            });
+        }
+        if tripped {
+            let action = self.watchdog.config().action.clone();
+            self.emit(TwoBChange::WatchdogTripped {
+                action: action.clone(),
+            });
+            if action == ExpiryAction::EmergencyStop {
+                let _ = self.engage(StopSource::Watchdog, Origin::Automatic("watchdog".into()));
+                return;
+            }
+            let commands = action.commands(&self.device.get_state());
+            self.queue_automatic("watchdog", commands);
+        }
+        self.publish();
+    }
+
+    /// Counts the session time and queues what the governor asks for, `Kill` ahead of
+    /// everything. Ramps are cancelled so they don't raise levels again.
+    fn govern(&mut self) {
+        let governor = match &mut self.session {
+            Some(governor) => governor,
+            None => return,
+        };
+        let was_active = governor.is_active();
+        let (changes, commands) = governor.update(&self.device.get_state(), (self.clock)());
+        let is_active = governor.is_active();
+        if changes.is_empty() && commands.is_empty() && was_active == is_active {
+            return;
+        }
+        for change in changes {
+            self.emit(change);
+        }
+        if !commands.is_empty() {
+            self.slew.cancel();
+        }
+        self.queue_automatic("session", commands);
+        self.publish();
+    }
+
+    fn ramp(&mut self) {
+        let result = self.ramp_step(Origin::Automatic("slew".into()));
+        self.confirmed(&result);
+        self.publish();
+    }
+
+    /// Sends the next due ramp step. A failing step ends every ramp.
+    fn ramp_step(&mut self, origin: Origin) -> Result<(), TwoBError> {
+        let state = self.device.get_state();
+        let command = match self.slew.next(&state, (self.clock)()) {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        let result = self.execute(command, origin);
+        if result.is_err() {
+            self.slew.cancel();
+        }
+        result
+    }
+
+    fn poll(&mut self) {
+        let result = self.execute(
+            TwoBCommand::RefreshState,
+            Origin::Automatic("poller".into()),
+        );
+        self.confirmed(&result);
+        self.publish();
+    }
+
+    fn confirmed(&mut self, result: &Result<(), TwoBError>) {
+        self.last_activity = (self.clock)();
+        if result.is_ok() {
+            self.as_of = SystemTime::now();
+            self.sample_battery();
+            self.govern();
+        }
+    }
+
+    /// Records the battery value and queues the actions of the thresholds it fell to.
+    fn sample_battery(&mut self) {
+        let monitor = match &mut self.battery {
+            Some(monitor) => monitor,
+            None => return,
+        };
+        let state = self.device.get_state();
+        let crossed = monitor.record(state.battery, (self.clock)());
+        if crossed.is_empty() {
+            return;
+        }
+        let mut commands = Vec::new();
+        for threshold in crossed {
+            self.emit(TwoBChange::BatteryLow {
+                percent: threshold.percent,
+                action: threshold.action,
+            });
+            commands.extend(threshold.action.commands(&state));
+        }
+        self.queue_automatic("battery", commands);
+    }
+
+    fn emit(&self, change: TwoBChange) {
+        let _ = self.events.send(TwoBEvent {
+            timestamp: SystemTime::now(),
+            change,
+        });
+    }
+
+    fn error(&self, command: Option<TwoBCommand>, error: &TwoBError) {
+        self.emit(TwoBChange::Error {
+            command,
+            message: format!("{:?}", error),
+        });
+    }
+
+    fn guarded<R, F>(&mut self, f: F) -> Result<R, TwoBError>
+    where
+        F: FnOnce(&mut dyn TwoB) -> R,
+    {
+        let device = self.device.as_mut();
+        panic::catch_unwind(AssertUnwindSafe(|| f(device))).map_err(|_| {
+            TwoBError::ConnectionError("The 2B panicked while handling a command".into())
+        })
+    }
+
+    fn publish(&self) {
+        let state = self.device.get_state();
+        let now = (self.clock)();
+        self.snapshot.store(Arc::new(Snapshot {
+            ramps: self.slew.ramps(&state),
+            leases: self.watchdog.leases(),
+            emergency_stop: self.emergency_stop.clone(),
+            battery: self.battery.as_ref().and_then(BatteryMonitor::status),
+            session: self.session.as_ref().map(|g| g.status(now)),
+            audit_log: self.audit.as_ref().map(|log| log.path().to_path_buf()),
+            taken: now,
+            state,
+            as_of: self.as_of,
+            version: self.device.get_version(),
+            capabilities: self.device.get_capabilities(),
+            queue: self.queue.metrics(),
+        }));
+    }
+}
+
+#[cfg(feature = "async")]
+impl TwoBHandle {
+    async fn call_async<R, F>(&self, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut TwoBHandle) -> R + Send + 'static,
+    {
+        let mut handle = self.clone();
+        tokio::task::spawn_blocking(move || f(&mut handle))
+            .await
+            .unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
+    }
+
+    /// `submit` for async callers, waiting on tokio's blocking thread pool.
+    pub async fn submit_async(&self, commands: Vec<TwoBCommand>) -> Result<(), TwoBError> {
+        self.call_async(move |handle| handle.submit(commands)).await
+    }
+
+    pub async fn engage_emergency_stop_async(&self, source: StopSource) -> Result<(), TwoBError> {
+        self.call_async(move |handle| handle.engage_emergency_stop(source))
+            .await
+    }
+
+    pub async fn set_state_async(&self, state: TwoBState) -> Result<(), TwoBError> {
+        self.call_async(move |handle| TwoB::set_state(handle, state))
+            .await
+    }
+}
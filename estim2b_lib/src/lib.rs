@@ -1,17 +1,82 @@
 // TODO Workaround until https://github.com/PyO3/pyo3/issues/780 and https://github.com/PyO3/pyo3/issues/1003 is resolved
 #![feature(cfg_eval)]
 
+#[cfg(feature = "async")]
+mod async_two_b;
+mod audit;
+mod battery;
+mod command;
+mod confirmation;
 mod device;
+mod emergency_stop;
+mod event;
+mod firmware;
+mod handle;
+mod reply;
+mod safety;
+mod scheduler;
+mod session;
+mod slew;
+mod transaction;
+mod watchdog;
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::num::ParseIntError;
 use std::convert::Infallible;
 
+pub use audit::{token_fingerprint, AuditLog, AuditRecord, AuditedAction, Origin};
+pub use battery::{
+    BatteryAction, BatteryConfig, BatteryModel, BatteryMonitor, BatteryStatus, BatteryThreshold,
+    PowerSupply,
+};
+pub use command::TwoBCommand;
+pub use confirmation::{
+    ConfirmationConfig, Confirmations, Proposal, ProposalId, ProposalOutcome, ProposedChange,
+    ResolvedProposal,
+};
+pub use emergency_stop::{EmergencyStop, StopSource};
+pub use event::{
+    changes, ConnectionState, EventBus, EventListener, SubscriptionId, TwoBChange, TwoBEvent,
+};
+pub use firmware::{Capabilities, FirmwareVersion};
+pub use handle::{CachedState, Clock, TwoBHandle};
+pub use reply::{ReplyError, TwoBReply};
+pub use safety::{ChannelLimits, Enforcement, ModeLimit, SafeTwoB, SafetyPolicy};
+pub use scheduler::{CommandQueue, QueueConfig, QueueMetrics};
+pub use session::{SessionConfig, SessionGovernor, SessionStatus};
+pub use slew::{Ramp, SlewConfig, SlewLimiter};
+pub use transaction::{
+    differing_fields, plan, FailedCommand, OnFailure, Transaction, TransactionReport,
+};
+pub use watchdog::{ExpiryAction, Lease, LeaseId, Watchdog, WatchdogConfig};
+
+#[cfg(feature = "async")]
+pub use async_two_b::{AsyncTwoB, BlockingAdapter};
+#[cfg(feature = "async")]
+pub use device::async_link::{open_async_transport, AsyncRfc2217Transport, AsyncTransport};
+#[cfg(feature = "async")]
+pub use device::async_usb_two_b::{AsyncOpener, AsyncUSBTwoB};
 #[cfg(feature = "usb")]
-pub use device::usb_two_b::USBTwoB;
+pub use device::discovery::{
+    discover, discover_ports, DiscoveredTwoB, DiscoveryOptions, UsbInfo,
+};
+#[cfg(feature = "usb")]
+pub use device::link::LinkConfig;
+#[cfg(feature = "usb")]
+pub use device::network::{is_network_address, open_tcp, open_transport, Rfc2217Transport};
+#[cfg(feature = "usb")]
+pub use device::reconnect::{ConnectionListener, Opener, ReconnectConfig, SerialLocator};
+#[cfg(feature = "usb")]
+pub use device::transport::{open_serial, Transport};
+#[cfg(feature = "usb")]
+pub use device::usb_two_b::{OnExit, USBTwoB};
 #[cfg(feature = "virtual")]
 pub use device::virtual_two_b::VirtualTwoB;
+#[cfg(feature = "emulator")]
+pub use device::emulator::Emulator;
+#[cfg(all(feature = "emulator", unix))]
+pub use device::emulator::EmulatorPty;
 
 #[cfg(feature = "python")]
 use pyo3::prelude::pyclass;
@@ -24,10 +89,23 @@ use strum_macros::{Display, EnumString, EnumVariantNames};
 
 
 
-#[derive(Debug, Display, Serialize, Deserialize)]
+#[derive(Clone, Debug, Display, Serialize, Deserialize)]
 pub enum TwoBError {
     ConnectionError(String),
     ParserError(String),
+    Unsupported(String),
+    Timeout(String),
+    TransactionFailed(TransactionReport),
+    PolicyViolation(String),
+    /// The change was held as a proposal and is only applied once it is confirmed.
+    ConfirmationRequired(Proposal),
+}
+
+impl TwoBError {
+    /// A `PolicyViolation` refusing `command` for `reason`.
+    pub(crate) fn refused(command: &str, reason: &str) -> TwoBError {
+        TwoBError::PolicyViolation(format!("{} refused, {}", command, reason))
+    }
 }
 
 impl From<&str> for TwoBError {
@@ -202,6 +280,7 @@ pub enum TwoBPower {
 }
 
 impl TwoBPower {
+    /// Command selecting this power level.
     const fn value(self) -> char {
         use TwoBPower::*;
         match self {
@@ -210,17 +289,26 @@ impl TwoBPower {
             DYNAMIC => 'Y',
         }
     }
+
+    /// Token the device reports this power level with in its status line.
+    /// Dynamic power is selected with `Y` but reported as `D`.
+    const fn reply_value(self) -> char {
+        use TwoBPower::*;
+        match self {
+            HIGH => 'H',
+            LOW => 'L',
+            DYNAMIC => 'D',
+        }
+    }
 }
 
 impl TryFrom<String> for TwoBPower {
     type Error = TwoBError;
     fn try_from(s: String) -> Result<Self, TwoBError> {
-        match s {
-            x if Self::HIGH.value().to_string() == x => Ok(Self::HIGH),
-            y if Self::LOW.value().to_string() == y => Ok(Self::LOW),
-            z if *"D" == z => Ok(Self::LOW),
-            _ => Err("Cannot parse Power".into()),
-        }
+        [Self::HIGH, Self::LOW, Self::DYNAMIC]
+            .into_iter()
+            .find(|power| power.reply_value().to_string() == s)
+            .ok_or_else(|| "Cannot parse Power".into())
     }
 }
 
@@ -281,42 +369,7 @@ pub struct TwoBState {
 impl TryFrom<&String> for TwoBState {
     type Error = TwoBError;
     fn try_from(s: &String) -> Result<Self, TwoBError> {
-        macro_rules! get_value {
-            ($x:ident) => {
-                if let Some(value) = $x.next() {
-                    Ok(value.parse()?)
-                }
-                else {
-                    Err(TwoBError::ConnectionError("No value to parse".into()))
-                }
-
-            };
-            ($x:ident, $t:ty) => {
-                if let Some(value) = $x.next() {
-                    Ok(value.parse::<$t>()?)
-                }
-                else {
-                    Err(TwoBError::ConnectionError("No value to parse".into()))
-                }
-            };
-        }
-
-        let mut split = s.split(':');
-
-        Ok(TwoBState {
-            battery: get_value!(split)?,
-            channel_a: get_value!(split, u8)? / 2,
-            channel_b: get_value!(split, u8)? / 2,
-            channel_c: get_value!(split, u8)? / 2,
-            channel_d: get_value!(split, u8)? / 2,
-            mode: TwoBMode::try_from(get_value!(split, u8)?)?,
-            power: TwoBPower::try_from(get_value!(split, String)?)?,
-            bias: TwoBBias::try_from(get_value!(split, u8)?)?,
-            joined_channels: get_value!(split, u8)? == 1,
-            map: TwoBMap::try_from(get_value!(split, u8)?)?,
-            warp: TwoBWarp::try_from(get_value!(split, u8)?)?,
-            ramp: TwoBRamp::try_from(get_value!(split, u8)?)?,
-        })
+        Ok(TwoBReply::parse(s)?.state())
     }
 }
 
@@ -327,6 +380,25 @@ impl fmt::Display for TwoBState {
 }
 
 pub trait TwoB: Send {
+    fn execute(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        use TwoBCommand::*;
+        match command {
+            RefreshState => self.refresh_state(),
+            Reset => self.reset(),
+            Kill => self.kill(),
+            SetJoinedChannels(enable) => self.set_joined_channels(enable),
+            SetMode(mode) => self.set_mode(mode),
+            SetPower(power) => self.set_power(power),
+            SetMap(map) => self.set_map(map),
+            SetBias(bias) => self.set_bias(bias),
+            SetRamp(ramp) => self.set_ramp(ramp),
+            SetWarp(warp) => self.set_warp(warp),
+            IncrementChannel(channel) => self.increment_channel(channel),
+            DecrementChannel(channel) => self.decrement_channel(channel),
+            SetChannel(channel, value) => self.set_channel(channel, value),
+        }
+    }
+
     fn refresh_state(&mut self) -> Result<(), TwoBError>;
 
     fn reset(&mut self) -> Result<(), TwoBError>;
@@ -357,6 +429,18 @@ pub trait TwoB: Send {
 
     fn get_state(&self) -> TwoBState;
 
+    /// Calls `listener` with every change of the device from now on, including changes
+    /// only noticed by `refresh_state`. Devices that don't report events drop the
+    /// listener.
+    fn subscribe(&mut self, _listener: EventListener) -> SubscriptionId {
+        SubscriptionId::NONE
+    }
+
+    /// Returns whether `subscription` was subscribed.
+    fn unsubscribe(&mut self, _subscription: SubscriptionId) -> bool {
+        false
+    }
+
     fn get_mode(&self) -> TwoBMode {
         self.get_state().mode
     }
@@ -399,4 +483,20 @@ pub trait TwoB: Send {
     }
 
     fn get_version(&self) -> String;
+
+    fn get_firmware_version(&self) -> Result<FirmwareVersion, TwoBError> {
+        self.get_version().parse()
+    }
+
+    fn get_capabilities(&self) -> Capabilities {
+        self.get_firmware_version()
+            .map(|version| Capabilities::for_version(&version))
+            .unwrap_or_default()
+    }
+
+    /// Command to send when the owner of the device exits or panics, `Kill` unless
+    /// the device is told otherwise.
+    fn exit_command(&self) -> Option<TwoBCommand> {
+        Some(TwoBCommand::Kill)
+    }
 }
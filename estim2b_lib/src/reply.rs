@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::*;
+
+const FIELDS: [&str; 13] = [
+    "battery",
+    "channel_a",
+    "channel_b",
+    "channel_c",
+    "channel_d",
+    "mode",
+    "power",
+    "bias",
+    "joined_channels",
+    "map",
+    "warp",
+    "ramp",
+    "version",
+];
+
+/// Status line the 2B answers every command with:
+/// `battery:A:B:C:D:mode:power:bias:joined:map:warp:ramp:version`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TwoBReply {
+    pub battery: u16,
+    /// Channel values as reported by the device, which are twice the level.
+    pub raw_channels: [u16; 4],
+    pub mode: TwoBMode,
+    pub power: TwoBPower,
+    pub bias: TwoBBias,
+    pub joined_channels: bool,
+    pub map: TwoBMap,
+    pub warp: TwoBWarp,
+    pub ramp: TwoBRamp,
+    pub version: String,
+}
+
+/// Describes why a status line couldn't be parsed.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReplyError {
+    /// Name of the offending field.
+    pub field: String,
+    /// Byte offset of the offending field within the line.
+    pub position: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ReplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Invalid {} at position {}: {}",
+            self.field, self.position, self.reason
+        )
+    }
+}
+
+impl From<ReplyError> for TwoBError {
+    fn from(e: ReplyError) -> TwoBError {
+        TwoBError::ParserError(e.to_string())
+    }
+}
+
+impl TwoBReply {
+    pub fn from_state(state: &TwoBState, version: &str) -> Self {
+        TwoBReply {
+            battery: state.battery,
+            raw_channels: [
+                state.channel_a,
+                state.channel_b,
+                state.channel_c,
+                state.channel_d,
+            ]
+            .map(|level| u16::from(level) * 2),
+            mode: state.mode,
+            power: state.power,
+            bias: state.bias,
+            joined_channels: state.joined_channels,
+            map: state.map,
+            warp: state.warp,
+            ramp: state.ramp,
+            version: version.into(),
+        }
+    }
+
+    pub fn state(&self) -> TwoBState {
+        let [channel_a, channel_b, channel_c, channel_d] = self
+            .raw_channels
+            .map(|raw| (raw / 2).min(u8::MAX.into()) as u8);
+        TwoBState {
+            mode: self.mode,
+            channel_a,
+            channel_b,
+            channel_c,
+            channel_d,
+            power: self.power,
+            bias: self.bias,
+            joined_channels: self.joined_channels,
+            map: self.map,
+            ramp: self.ramp,
+            warp: self.warp,
+            battery: self.battery,
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, ReplyError> {
+        let line = line.trim_end();
+        let mut fields = Vec::with_capacity(FIELDS.len());
+        let mut position = 0;
+        for value in line.split(':') {
+            fields.push((position, value));
+            position += value.len() + 1;
+        }
+        if fields.len() > FIELDS.len() {
+            return Err(ReplyError {
+                field: "reply".into(),
+                position: fields[FIELDS.len()].0,
+                reason: format!("expected {} fields but got {}", FIELDS.len(), fields.len()),
+            });
+        }
+
+        let field = |index: usize| -> Result<(usize, &str), ReplyError> {
+            fields.get(index).copied().ok_or_else(|| ReplyError {
+                field: FIELDS[index].into(),
+                position: line.len(),
+                reason: "missing".into(),
+            })
+        };
+        let error = |index: usize, position: usize, reason: String| ReplyError {
+            field: FIELDS[index].into(),
+            position,
+            reason,
+        };
+        let number = |index: usize| -> Result<u16, ReplyError> {
+            let (position, value) = field(index)?;
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(error(
+                    index,
+                    position,
+                    format!("'{}' is not a number", value),
+                ));
+            }
+            value
+                .parse()
+                .map_err(|e| error(index, position, format!("'{}': {}", value, e)))
+        };
+        macro_rules! enumeration {
+            ($index:expr, $t:ty) => {{
+                let value = number($index)?;
+                u8::try_from(value)
+                    .ok()
+                    .and_then(|value| <$t>::try_from(value).ok())
+                    .ok_or_else(|| {
+                        error(
+                            $index,
+                            fields[$index].0,
+                            format!("'{}' is out of range", value),
+                        )
+                    })?
+            }};
+        }
+
+        let channel = |index: usize| -> Result<u16, ReplyError> {
+            let value = number(index)?;
+            if value / 2 > u8::MAX.into() {
+                return Err(error(
+                    index,
+                    fields[index].0,
+                    format!("'{}' is out of range", value),
+                ));
+            }
+            Ok(value)
+        };
+
+        let battery = number(0)?;
+        let raw_channels = [channel(1)?, channel(2)?, channel(3)?, channel(4)?];
+        let mode = enumeration!(5, TwoBMode);
+        let (position, power) = field(6)?;
+        let power = TwoBPower::try_from(power.to_string())
+            .map_err(|_| error(6, position, format!("unknown power '{}'", power)))?;
+        let bias = enumeration!(7, TwoBBias);
+        let (position, joined_channels) = field(8)?;
+        let joined_channels = match joined_channels {
+            "0" => false,
+            "1" => true,
+            value => return Err(error(8, position, format!("'{}' is not 0 or 1", value))),
+        };
+        let map = enumeration!(9, TwoBMap);
+        let warp = enumeration!(10, TwoBWarp);
+        let ramp = enumeration!(11, TwoBRamp);
+        let (position, version) = field(12)?;
+        if version.is_empty() {
+            return Err(error(12, position, "empty".into()));
+        }
+
+        Ok(TwoBReply {
+            battery,
+            raw_channels,
+            mode,
+            power,
+            bias,
+            joined_channels,
+            map,
+            warp,
+            ramp,
+            version: version.into(),
+        })
+    }
+
+    /// Wire representation without the terminating newline.
+    pub fn encode(&self) -> String {
+        format!(
+            "{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}",
+            self.battery,
+            self.raw_channels[0],
+            self.raw_channels[1],
+            self.raw_channels[2],
+            self.raw_channels[3],
+            u8::from(self.mode),
+            self.power.reply_value(),
+            u8::from(self.bias),
+            if self.joined_channels { 1 } else { 0 },
+            u8::from(self.map),
+            u8::from(self.warp),
+            u8::from(self.ramp),
+            self.version,
+        )
+    }
+}
+
+impl fmt::Display for TwoBReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.encode())
+    }
+}
+
+impl FromStr for TwoBReply {
+    type Err = ReplyError;
+    fn from_str(s: &str) -> Result<Self, ReplyError> {
+        Self::parse(s)
+    }
+}
@@ -0,0 +1,408 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::*;
+
+/// What `SafeTwoB` does with a command exceeding a level limit. Forbidden modes and
+/// power settings are always rejected.
+#[derive(Clone, Copy, Debug, Default, Display, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Enforcement {
+    #[default]
+    /// Fail with `TwoBError::PolicyViolation`.
+    Reject,
+    /// Lower the level to the limit and go on.
+    Clamp,
+}
+
+/// Highest level per channel. `None` leaves a channel to the firmware's maximum.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelLimits {
+    pub a: Option<u8>,
+    pub b: Option<u8>,
+    pub c: Option<u8>,
+    pub d: Option<u8>,
+}
+
+impl ChannelLimits {
+    pub fn get(&self, channel: TwoBChannel) -> Option<u8> {
+        match channel {
+            TwoBChannel::A => self.a,
+            TwoBChannel::B => self.b,
+            TwoBChannel::C => self.c,
+            TwoBChannel::D => self.d,
+        }
+    }
+}
+
+/// Highest level of channel A and B while `mode` is selected.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ModeLimit {
+    pub mode: TwoBMode,
+    pub max_level: u8,
+}
+
+/// Limits `SafeTwoB` keeps the device within, usually read from a JSON file.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SafetyPolicy {
+    pub enforcement: Enforcement,
+    pub max_levels: ChannelLimits,
+    pub mode_limits: Vec<ModeLimit>,
+    /// Modes that may be selected. Empty allows every mode.
+    pub allowed_modes: Vec<TwoBMode>,
+    pub forbidden_powers: Vec<TwoBPower>,
+}
+
+const OUTPUTS: [TwoBChannel; 2] = [TwoBChannel::A, TwoBChannel::B];
+const CHANNELS: [TwoBChannel; 4] = [
+    TwoBChannel::A,
+    TwoBChannel::B,
+    TwoBChannel::C,
+    TwoBChannel::D,
+];
+
+fn lower(a: Option<u8>, b: Option<u8>) -> Option<u8> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn level_of(state: &TwoBState, channel: TwoBChannel) -> u8 {
+    match channel {
+        TwoBChannel::A => state.channel_a,
+        TwoBChannel::B => state.channel_b,
+        TwoBChannel::C => state.channel_c,
+        TwoBChannel::D => state.channel_d,
+    }
+}
+
+fn set_level(state: &mut TwoBState, channel: TwoBChannel, level: u8) {
+    match channel {
+        TwoBChannel::A => state.channel_a = level,
+        TwoBChannel::B => state.channel_b = level,
+        TwoBChannel::C => state.channel_c = level,
+        TwoBChannel::D => state.channel_d = level,
+    }
+}
+
+fn exceeded(channel: TwoBChannel, level: u8, limit: u8) -> TwoBError {
+    TwoBError::PolicyViolation(format!(
+        "Level {} for channel {} exceeds the limit of {}",
+        level, channel, limit
+    ))
+}
+
+impl SafetyPolicy {
+    pub fn from_json(json: &str) -> Result<Self, TwoBError> {
+        serde_json::from_str(json).map_err(|e| TwoBError::ParserError(e.to_string()))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, TwoBError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Highest level `channel` may have in `state`. Mode limits apply to channel A and
+    /// B, and joined channels share the lower limit of the two.
+    pub fn max_level(&self, channel: TwoBChannel, state: &TwoBState) -> Option<u8> {
+        let own = |channel: TwoBChannel| {
+            let mode_limit = self
+                .mode_limits
+                .iter()
+                .find(|limit| limit.mode == state.mode)
+                .map(|limit| limit.max_level)
+                .filter(|_| OUTPUTS.contains(&channel));
+            lower(self.max_levels.get(channel), mode_limit)
+        };
+        if state.joined_channels && OUTPUTS.contains(&channel) {
+            lower(own(TwoBChannel::A), own(TwoBChannel::B))
+        } else {
+            own(channel)
+        }
+    }
+
+    pub fn check_mode(&self, mode: TwoBMode) -> Result<(), TwoBError> {
+        if self.allowed_modes.is_empty() || self.allowed_modes.contains(&mode) {
+            Ok(())
+        } else {
+            Err(TwoBError::PolicyViolation(format!(
+                "Mode {} is not allowed",
+                mode
+            )))
+        }
+    }
+
+    pub fn check_power(&self, power: TwoBPower) -> Result<(), TwoBError> {
+        if self.forbidden_powers.contains(&power) {
+            Err(TwoBError::PolicyViolation(format!(
+                "Power {} is forbidden",
+                power
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns `state` within the policy, with levels clamped if the policy allows it.
+    pub fn check_state(&self, state: &TwoBState) -> Result<TwoBState, TwoBError> {
+        self.check_mode(state.mode)?;
+        self.check_power(state.power)?;
+        let mut checked = state.clone();
+        for channel in CHANNELS {
+            let level = level_of(state, channel);
+            match self.max_level(channel, state) {
+                Some(limit) if level > limit => match self.enforcement {
+                    Enforcement::Reject => return Err(exceeded(channel, level, limit)),
+                    Enforcement::Clamp => set_level(&mut checked, channel, limit),
+                },
+                _ => {}
+            }
+        }
+        Ok(checked)
+    }
+
+    /// Commands to send instead of `command` while the device is in `state`. Clamping
+    /// may lower levels before a mode change, or drop an increment entirely.
+    pub fn enforce(
+        &self,
+        command: TwoBCommand,
+        state: &TwoBState,
+    ) -> Result<Vec<TwoBCommand>, TwoBError> {
+        use TwoBCommand::*;
+        match command {
+            SetMode(mode) => {
+                self.check_mode(mode)?;
+                self.lower_for(
+                    command,
+                    &TwoBState {
+                        mode,
+                        ..state.clone()
+                    },
+                    state,
+                )
+            }
+            SetJoinedChannels(joined_channels) => self.lower_for(
+                command,
+                &TwoBState {
+                    joined_channels,
+                    ..state.clone()
+                },
+                state,
+            ),
+            SetPower(power) => {
+                self.check_power(power)?;
+                Ok(vec![command])
+            }
+            SetChannel(channel, level) => match self.max_level(channel, state) {
+                Some(limit) if level > limit => match self.enforcement {
+                    Enforcement::Reject => Err(exceeded(channel, level, limit)),
+                    Enforcement::Clamp => Ok(vec![SetChannel(channel, limit)]),
+                },
+                _ => Ok(vec![command]),
+            },
+            IncrementChannel(channel) => {
+                let level = level_of(state, channel);
+                match self.max_level(channel, state) {
+                    Some(limit) if level >= limit => match self.enforcement {
+                        Enforcement::Reject => {
+                            Err(exceeded(channel, level.saturating_add(1), limit))
+                        }
+                        Enforcement::Clamp => Ok(Vec::new()),
+                    },
+                    _ => Ok(vec![command]),
+                }
+            }
+            command => Ok(vec![command]),
+        }
+    }
+
+    /// `command` preceded by lowering every output above its limit in `target`.
+    fn lower_for(
+        &self,
+        command: TwoBCommand,
+        target: &TwoBState,
+        state: &TwoBState,
+    ) -> Result<Vec<TwoBCommand>, TwoBError> {
+        let mut commands = Vec::new();
+        for channel in OUTPUTS {
+            let level = level_of(state, channel);
+            match self.max_level(channel, target) {
+                Some(limit) if level > limit => match self.enforcement {
+                    Enforcement::Reject => return Err(exceeded(channel, level, limit)),
+                    Enforcement::Clamp => commands.push(TwoBCommand::SetChannel(channel, limit)),
+                },
+                _ => {}
+            }
+        }
+        commands.push(command);
+        Ok(commands)
+    }
+}
+
+/// Keeps a `TwoB` within a `SafetyPolicy`. Every command and `set_state` is checked
+/// against the policy before it reaches the device.
+///
+/// The state the device already has when it is wrapped is left alone until `enforce`
+/// is called. `Reset` and `Kill` are always let through, and `enforce` runs after every
+/// `Reset` as the defaults it restores may be outside the policy.
+pub struct SafeTwoB<T: TwoB + ?Sized> {
+    policy: SafetyPolicy,
+    device: Box<T>,
+}
+
+impl<T: TwoB> SafeTwoB<T> {
+    pub fn new(device: T, policy: SafetyPolicy) -> Self {
+        Self::from_box(Box::new(device), policy)
+    }
+}
+
+impl<T: TwoB + ?Sized> SafeTwoB<T> {
+    pub fn from_box(device: Box<T>, policy: SafetyPolicy) -> Self {
+        SafeTwoB { policy, device }
+    }
+
+    pub fn policy(&self) -> &SafetyPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: SafetyPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.device
+    }
+
+    /// Brings the device within the policy: a mode that isn't allowed is replaced by
+    /// the first allowed one, a forbidden power setting by the first one that isn't,
+    /// and every level above its limit is lowered, before the mode changes.
+    pub fn enforce(&mut self) -> Result<(), TwoBError> {
+        let state = self.device.get_state();
+        self.lower_to(&state)?;
+        if self.policy.check_mode(state.mode).is_err() {
+            let mode = self.policy.allowed_modes[0];
+            self.lower_to(&TwoBState { mode, ..state })?;
+            self.device.set_mode(mode)?;
+        }
+        let power = self.device.get_power();
+        if self.policy.check_power(power).is_err() {
+            let allowed = [TwoBPower::LOW, TwoBPower::HIGH, TwoBPower::DYNAMIC]
+                .into_iter()
+                .find(|power| self.policy.check_power(*power).is_ok())
+                .ok_or_else(|| {
+                    TwoBError::PolicyViolation("Every power setting is forbidden".into())
+                })?;
+            self.device.set_power(allowed)?;
+        }
+        Ok(())
+    }
+
+    /// Lowers every level above its limit in `state` on the device.
+    fn lower_to(&mut self, state: &TwoBState) -> Result<(), TwoBError> {
+        let current = self.device.get_state();
+        for channel in CHANNELS {
+            let level = level_of(&current, channel);
+            match self.policy.max_level(channel, state) {
+                Some(limit) if level > limit => self.device.set_channel(channel, limit)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: TwoB + ?Sized> TwoB for SafeTwoB<T> {
+    fn execute(&mut self, command: TwoBCommand) -> Result<(), TwoBError> {
+        for command in self.policy.enforce(command, &self.device.get_state())? {
+            self.device.execute(command)?;
+        }
+        if command == TwoBCommand::Reset {
+            self.enforce()?;
+        }
+        Ok(())
+    }
+
+    fn refresh_state(&mut self) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::RefreshState)
+    }
+
+    fn reset(&mut self) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::Reset)
+    }
+
+    fn kill(&mut self) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::Kill)
+    }
+
+    fn set_joined_channels(&mut self, enable: bool) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetJoinedChannels(enable))
+    }
+
+    fn set_mode(&mut self, mode: TwoBMode) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetMode(mode))
+    }
+
+    fn set_power(&mut self, power: TwoBPower) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetPower(power))
+    }
+
+    fn set_map(&mut self, map: TwoBMap) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetMap(map))
+    }
+
+    fn set_bias(&mut self, bias: TwoBBias) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetBias(bias))
+    }
+
+    fn set_ramp(&mut self, ramp: TwoBRamp) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetRamp(ramp))
+    }
+
+    fn set_warp(&mut self, warp: TwoBWarp) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetWarp(warp))
+    }
+
+    fn increment_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::IncrementChannel(channel))
+    }
+
+    fn decrement_channel(&mut self, channel: TwoBChannel) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::DecrementChannel(channel))
+    }
+
+    fn set_channel(&mut self, channel: TwoBChannel, value: u8) -> Result<(), TwoBError> {
+        self.execute(TwoBCommand::SetChannel(channel, value))
+    }
+
+    fn set_state(&mut self, state: TwoBState) -> Result<(), TwoBError> {
+        let state = self.policy.check_state(&state)?;
+        self.device.set_state(state)
+    }
+
+    fn get_state(&self) -> TwoBState {
+        self.device.get_state()
+    }
+
+    fn subscribe(&mut self, listener: EventListener) -> SubscriptionId {
+        self.device.subscribe(listener)
+    }
+
+    fn unsubscribe(&mut self, subscription: SubscriptionId) -> bool {
+        self.device.unsubscribe(subscription)
+    }
+
+    fn get_version(&self) -> String {
+        self.device.get_version()
+    }
+
+    fn get_capabilities(&self) -> Capabilities {
+        self.device.get_capabilities()
+    }
+
+    fn exit_command(&self) -> Option<TwoBCommand> {
+        self.device.exit_command()
+    }
+}
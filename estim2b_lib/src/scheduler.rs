@@ -0,0 +1,379 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+/// Pacing of the commands taken from a `CommandQueue`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct QueueConfig {
+    /// Minimum time between sending two commands. The 2B drops commands that arrive
+    /// while it is still answering the previous one.
+    pub min_gap: Duration,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            min_gap: Duration::from_millis(50),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct QueueMetrics {
+    /// Commands currently waiting.
+    pub depth: usize,
+    /// Commands pushed since the queue was created.
+    pub submitted: u64,
+    /// Commands that were merged into or replaced by a later one.
+    pub coalesced: u64,
+    /// Commands taken from the queue to be sent.
+    pub dispatched: u64,
+    /// Time the most recently dispatched command spent in the queue.
+    pub last_latency: Duration,
+    pub max_latency: Duration,
+    pub mean_latency: Duration,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Pending {
+    Command(TwoBCommand),
+    /// Level change of a channel. Without a base the delta is applied to the level the
+    /// device reports when the command is sent.
+    Level {
+        channel: TwoBChannel,
+        base: Option<u8>,
+        delta: i16,
+    },
+}
+
+impl Pending {
+    fn from_command(command: TwoBCommand) -> Self {
+        use TwoBCommand::*;
+        match command {
+            IncrementChannel(channel) => Pending::Level {
+                channel,
+                base: None,
+                delta: 1,
+            },
+            DecrementChannel(channel) => Pending::Level {
+                channel,
+                base: None,
+                delta: -1,
+            },
+            SetChannel(channel, value) => Pending::Level {
+                channel,
+                base: Some(value),
+                delta: 0,
+            },
+            command => Pending::Command(command),
+        }
+    }
+
+    fn channel(&self) -> Option<TwoBChannel> {
+        match self {
+            Pending::Level { channel, .. } => Some(*channel),
+            Pending::Command(_) => None,
+        }
+    }
+
+    /// Whether a `Kill` makes `self` redundant.
+    fn killed(&self) -> bool {
+        matches!(self.channel(), Some(TwoBChannel::A | TwoBChannel::B))
+            || matches!(self, Pending::Command(TwoBCommand::Kill))
+    }
+
+    /// Merges `later` into `self` if `later` makes `self` redundant.
+    fn merge(&self, later: &Pending) -> Option<Pending> {
+        match (self, later) {
+            (
+                Pending::Level {
+                    channel,
+                    base,
+                    delta,
+                },
+                Pending::Level {
+                    channel: other,
+                    base: None,
+                    delta: other_delta,
+                },
+            ) if channel == other => Some(Pending::Level {
+                channel: *channel,
+                base: *base,
+                delta: delta + other_delta,
+            }),
+            (Pending::Level { channel, .. }, Pending::Level { channel: other, .. })
+                if channel == other =>
+            {
+                Some(*later)
+            }
+            (Pending::Command(command), Pending::Command(other))
+                if std::mem::discriminant(command) == std::mem::discriminant(other) =>
+            {
+                Some(*later)
+            }
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, state: &TwoBState, levels: &RangeInclusive<u8>) -> Option<TwoBCommand> {
+        match *self {
+            Pending::Command(command) => Some(command),
+            Pending::Level {
+                channel,
+                base: None,
+                delta,
+            } => match delta {
+                0 => None,
+                1 => Some(TwoBCommand::IncrementChannel(channel)),
+                -1 => Some(TwoBCommand::DecrementChannel(channel)),
+                _ => Some(TwoBCommand::SetChannel(
+                    channel,
+                    Self::level(level_of(state, channel), delta, levels),
+                )),
+            },
+            Pending::Level {
+                channel,
+                base: Some(base),
+                delta,
+            } => Some(TwoBCommand::SetChannel(
+                channel,
+                Self::level(base, delta, levels),
+            )),
+        }
+    }
+
+    fn level(base: u8, delta: i16, levels: &RangeInclusive<u8>) -> u8 {
+        (base as i16 + delta).clamp(*levels.start() as i16, *levels.end() as i16) as u8
+    }
+}
+
+fn level_of(state: &TwoBState, channel: TwoBChannel) -> u8 {
+    match channel {
+        TwoBChannel::A => state.channel_a,
+        TwoBChannel::B => state.channel_b,
+        TwoBChannel::C => state.channel_c,
+        TwoBChannel::D => state.channel_d,
+    }
+}
+
+struct Entry {
+    pending: Pending,
+    /// When the oldest command merged into this entry was pushed.
+    queued_at: Instant,
+    /// Submissions the commands merged into this entry came from.
+    submissions: Vec<u64>,
+}
+
+/// Queue in front of the serial link that paces and coalesces commands.
+///
+/// A command that supersedes a queued one replaces it: ten `A+` become a single level
+/// change of +10, a `set_mode` drops any queued `set_mode`, and so on. The merged
+/// command takes the position of the newer one. `Kill` drops queued level changes of
+/// channel A and B and jumps the queue, `Reset` drops everything queued before it.
+pub struct CommandQueue {
+    config: QueueConfig,
+    entries: VecDeque<Entry>,
+    last_sent: Option<Instant>,
+    metrics: QueueMetrics,
+    total_latency: Duration,
+}
+
+impl CommandQueue {
+    pub fn new(config: QueueConfig) -> Self {
+        CommandQueue {
+            config,
+            entries: VecDeque::new(),
+            last_sent: None,
+            metrics: QueueMetrics::default(),
+            total_latency: Duration::ZERO,
+        }
+    }
+
+    pub fn config(&self) -> &QueueConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: QueueConfig) {
+        self.config = config;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        QueueMetrics {
+            depth: self.entries.len(),
+            ..self.metrics.clone()
+        }
+    }
+
+    pub fn push(&mut self, command: TwoBCommand) {
+        self.enqueue(command, Vec::new());
+    }
+
+    /// Pushes `command` as part of `submission`, which `pop_from` reports it with.
+    pub(crate) fn push_from(&mut self, command: TwoBCommand, submission: u64) {
+        self.enqueue(command, vec![submission]);
+    }
+
+    fn enqueue(&mut self, command: TwoBCommand, submissions: Vec<u64>) {
+        self.metrics.submitted += 1;
+        let before = self.entries.len();
+        let now = Instant::now();
+        match command {
+            TwoBCommand::Kill => {
+                self.entries.retain(|entry| !entry.pending.killed());
+                self.metrics.coalesced += (before - self.entries.len()) as u64;
+                self.entries.push_front(Entry {
+                    pending: Pending::Command(command),
+                    queued_at: now,
+                    submissions,
+                });
+                return;
+            }
+            TwoBCommand::Reset => {
+                self.entries.clear();
+                self.metrics.coalesced += before as u64;
+            }
+            _ => {}
+        }
+
+        let mut entry = Entry {
+            pending: Pending::from_command(command),
+            queued_at: now,
+            submissions,
+        };
+        let superseded = self
+            .entries
+            .iter()
+            .rposition(|queued| queued.pending.merge(&entry.pending).is_some());
+        if let Some(index) = superseded {
+            if let Some(mut queued) = self.entries.remove(index) {
+                for submission in entry.submissions {
+                    if !queued.submissions.contains(&submission) {
+                        queued.submissions.push(submission);
+                    }
+                }
+                entry = Entry {
+                    pending: queued
+                        .pending
+                        .merge(&entry.pending)
+                        .unwrap_or(entry.pending),
+                    queued_at: queued.queued_at,
+                    submissions: queued.submissions,
+                };
+                self.metrics.coalesced += 1;
+            }
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Puts `commands` ahead of everything queued, in their order, dropping the queued
+    /// commands they supersede. `Kill` drops queued level changes of channel A and B as
+    /// in `push`.
+    pub fn push_front(&mut self, commands: &[TwoBCommand]) {
+        self.enqueue_front(commands, Vec::new());
+    }
+
+    /// Pushes `commands` to the front as part of `submission`.
+    pub(crate) fn push_front_from(&mut self, commands: &[TwoBCommand], submission: u64) {
+        self.enqueue_front(commands, vec![submission]);
+    }
+
+    fn enqueue_front(&mut self, commands: &[TwoBCommand], submissions: Vec<u64>) {
+        let now = Instant::now();
+        for (position, &command) in commands.iter().enumerate() {
+            self.metrics.submitted += 1;
+            let entry = Entry {
+                pending: Pending::from_command(command),
+                queued_at: now,
+                submissions: submissions.clone(),
+            };
+            let before = self.entries.len();
+            let mut index = 0;
+            self.entries.retain(|queued| {
+                index += 1;
+                index <= position
+                    || match command {
+                        TwoBCommand::Kill => !queued.pending.killed(),
+                        _ => queued.pending.merge(&entry.pending).is_none(),
+                    }
+            });
+            self.metrics.coalesced += (before - self.entries.len()) as u64;
+            self.entries.insert(position, entry);
+        }
+    }
+
+    /// Drops everything queued.
+    pub fn clear(&mut self) {
+        self.metrics.coalesced += self.entries.len() as u64;
+        self.entries.clear();
+    }
+
+    /// Time until the next command may be sent.
+    pub fn wait_time(&self) -> Duration {
+        self.last_sent.map_or(Duration::ZERO, |last_sent| {
+            (last_sent + self.config.min_gap).saturating_duration_since(Instant::now())
+        })
+    }
+
+    /// Takes the next command, turning queued level changes into commands against the
+    /// current `state` of the device. Level changes that cancelled out are skipped.
+    pub fn pop(&mut self, state: &TwoBState, capabilities: &Capabilities) -> Option<TwoBCommand> {
+        self.pop_from(state, capabilities)
+            .map(|(command, _)| command)
+    }
+
+    /// Takes the next command like `pop`, with the submissions it came from.
+    pub(crate) fn pop_from(
+        &mut self,
+        state: &TwoBState,
+        capabilities: &Capabilities,
+    ) -> Option<(TwoBCommand, Vec<u64>)> {
+        while let Some(entry) = self.entries.pop_front() {
+            let command = match entry.pending.resolve(state, &capabilities.levels) {
+                Some(command) => command,
+                None => {
+                    self.metrics.coalesced += 1;
+                    continue;
+                }
+            };
+            let latency = entry.queued_at.elapsed();
+            self.metrics.dispatched += 1;
+            self.metrics.last_latency = latency;
+            self.metrics.max_latency = self.metrics.max_latency.max(latency);
+            self.total_latency += latency;
+            self.metrics.mean_latency = self.total_latency / self.metrics.dispatched as u32;
+            self.last_sent = Some(Instant::now());
+            return Some((command, entry.submissions));
+        }
+        None
+    }
+
+    /// Sends everything queued to `two_b`, waiting `min_gap` between two commands.
+    /// Every command is attempted; the first error is returned.
+    pub fn run<T: TwoB + ?Sized>(&mut self, two_b: &mut T) -> Result<(), TwoBError> {
+        let mut result = Ok(());
+        loop {
+            sleep(self.wait_time());
+            let command = match self.pop(&two_b.get_state(), &two_b.get_capabilities()) {
+                Some(command) => command,
+                None => return result,
+            };
+            if let Err(e) = two_b.execute(command) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+/// How often levels are lowered while ramping down to the session limit.
+const RAMP_STEP: Duration = Duration::from_millis(250);
+
+/// Limits of a session. Time counts while channel A or B is above zero; a pause as
+/// long as `cooldown` starts a new session.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Longest stimulation before the device is killed.
+    pub max_active: Duration,
+    /// Time after the limit during which no level can be raised.
+    pub cooldown: Duration,
+    /// Remaining times a `SessionWarning` is sent at.
+    pub warnings: Vec<Duration>,
+    /// Time before the limit over which channel A and B are lowered to zero.
+    pub ramp_down: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            max_active: Duration::from_secs(30 * 60),
+            cooldown: Duration::from_secs(10 * 60),
+            warnings: vec![Duration::from_secs(5 * 60), Duration::from_secs(60)],
+            ramp_down: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SessionStatus {
+    /// Whether channel A or B is above zero.
+    pub active: bool,
+    pub active_time: Duration,
+    pub remaining: Duration,
+    /// Time until levels can be raised again after the limit was reached.
+    pub cooldown_remaining: Option<Duration>,
+}
+
+impl SessionStatus {
+    /// The status `elapsed` later, as long as nothing changes in the meantime.
+    pub fn after(&self, elapsed: Duration) -> SessionStatus {
+        let mut status = self.clone();
+        if status.active {
+            status.active_time += elapsed;
+            status.remaining = status.remaining.saturating_sub(elapsed);
+        }
+        status.cooldown_remaining = status
+            .cooldown_remaining
+            .map(|cooldown| cooldown.saturating_sub(elapsed));
+        status
+    }
+}
+
+/// Enforces the session limits on a device whose state is passed to `update`
+/// regularly; `wait_time` tells when the next update is due.
+pub struct SessionGovernor {
+    config: SessionConfig,
+    active_time: Duration,
+    active: bool,
+    last_update: Option<Instant>,
+    inactive_since: Option<Instant>,
+    cooldown_until: Option<Instant>,
+    warned: Vec<bool>,
+    /// Levels of channel A and B when the ramp down started.
+    ramp_from: Option<(u8, u8)>,
+}
+
+impl SessionGovernor {
+    pub fn new(config: SessionConfig) -> Self {
+        SessionGovernor {
+            warned: vec![false; config.warnings.len()],
+            config,
+            active_time: Duration::ZERO,
+            active: false,
+            last_update: None,
+            inactive_since: None,
+            cooldown_until: None,
+            ramp_from: None,
+        }
+    }
+
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    /// Keeps the time of the running session.
+    pub fn set_config(&mut self, config: SessionConfig) {
+        self.warned = vec![false; config.warnings.len()];
+        self.config = config;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    fn reset(&mut self) {
+        self.active_time = Duration::ZERO;
+        self.warned.iter_mut().for_each(|warned| *warned = false);
+        self.ramp_from = None;
+    }
+
+    fn remaining(&self) -> Duration {
+        self.config.max_active.saturating_sub(self.active_time)
+    }
+
+    /// Highest levels of channel A and B while ramping down, `None` before.
+    fn caps(&self) -> Option<(u8, u8)> {
+        let (a, b) = self.ramp_from?;
+        let share = if self.config.ramp_down.is_zero() {
+            0.0
+        } else {
+            self.remaining().as_secs_f64() / self.config.ramp_down.as_secs_f64()
+        };
+        let cap = |level: u8| (f64::from(level) * share).ceil() as u8;
+        Some((cap(a), cap(b)))
+    }
+
+    /// Counts the time since the last update and returns the events and the commands
+    /// to send for the session of a device in `state`.
+    pub fn update(
+        &mut self,
+        state: &TwoBState,
+        now: Instant,
+    ) -> (Vec<TwoBChange>, Vec<TwoBCommand>) {
+        let mut changes = Vec::new();
+        let mut commands = Vec::new();
+        if let Some(last_update) = self.last_update {
+            if self.active {
+                self.active_time += now.saturating_duration_since(last_update);
+            }
+        }
+        self.last_update = Some(now);
+        let active = state.channel_a > 0 || state.channel_b > 0;
+        if active != self.active {
+            self.inactive_since = if active { None } else { Some(now) };
+            self.active = active;
+        }
+
+        if let Some(until) = self.cooldown_until {
+            if now < until {
+                if active {
+                    commands.push(TwoBCommand::Kill);
+                }
+                return (changes, commands);
+            }
+            self.cooldown_until = None;
+            self.reset();
+            changes.push(TwoBChange::CooldownEnded);
+        }
+        if !active {
+            if let Some(since) = self.inactive_since {
+                if now.saturating_duration_since(since) >= self.config.cooldown {
+                    self.reset();
+                }
+            }
+            return (changes, commands);
+        }
+
+        let remaining = self.remaining();
+        for (warning, warned) in self.config.warnings.iter().zip(&mut self.warned) {
+            if !*warned && remaining <= *warning {
+                *warned = true;
+                changes.push(TwoBChange::SessionWarning { remaining });
+            }
+        }
+        if remaining.is_zero() {
+            commands.push(TwoBCommand::Kill);
+            self.cooldown_until = Some(now + self.config.cooldown);
+            changes.push(TwoBChange::SessionLimitReached {
+                cooldown: self.config.cooldown,
+            });
+            return (changes, commands);
+        }
+        if remaining <= self.config.ramp_down {
+            self.ramp_from
+                .get_or_insert((state.channel_a, state.channel_b));
+            if let Some((a, b)) = self.caps() {
+                if state.channel_a > a {
+                    commands.push(TwoBCommand::SetChannel(TwoBChannel::A, a));
+                }
+                if state.channel_b > b {
+                    commands.push(TwoBCommand::SetChannel(TwoBChannel::B, b));
+                }
+            }
+        }
+        (changes, commands)
+    }
+
+    /// Time until the next update is due, `None` while nothing is pending.
+    pub fn wait_time(&self, now: Instant) -> Option<Duration> {
+        let since_update = self
+            .last_update
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        if let Some(until) = self.cooldown_until {
+            return Some(until.saturating_duration_since(now));
+        }
+        if !self.active {
+            if self.active_time.is_zero() {
+                return None;
+            }
+            return self
+                .inactive_since
+                .map(|since| (since + self.config.cooldown).saturating_duration_since(now));
+        }
+        let remaining = self.remaining().saturating_sub(since_update);
+        let mut due = remaining;
+        for (warning, warned) in self.config.warnings.iter().zip(&self.warned) {
+            if !*warned {
+                due = due.min(remaining.saturating_sub(*warning));
+            }
+        }
+        if remaining > self.config.ramp_down {
+            due = due.min(remaining - self.config.ramp_down);
+        } else {
+            due = due.min(RAMP_STEP);
+        }
+        Some(due)
+    }
+
+    /// Refuses `command` if it would raise channel A or B of a device in `state` during
+    /// the cooldown or above the ramp down. Joining the channels raises B to A.
+    pub fn check(&self, command: &TwoBCommand, state: &TwoBState) -> Result<(), TwoBError> {
+        use TwoBCommand::*;
+        let (a, b) = match (self.cooldown_until, self.caps()) {
+            (Some(_), _) => (0, 0),
+            (None, Some(caps)) => caps,
+            (None, None) => return Ok(()),
+        };
+        let target = match *command {
+            SetChannel(TwoBChannel::A, level) => Some((level, state.channel_a, a)),
+            SetChannel(TwoBChannel::B, level) => Some((level, state.channel_b, b)),
+            IncrementChannel(TwoBChannel::A) => {
+                Some((state.channel_a.saturating_add(1), state.channel_a, a))
+            }
+            IncrementChannel(TwoBChannel::B) => {
+                Some((state.channel_b.saturating_add(1), state.channel_b, b))
+            }
+            SetJoinedChannels(true) => Some((state.channel_a, state.channel_b, b)),
+            _ => None,
+        };
+        match target {
+            Some((level, current, cap)) if level > current && level > cap => {
+                Err(TwoBError::refused(&format!("{:?}", command), self.reason()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_state(&self, state: &TwoBState, current: &TwoBState) -> Result<(), TwoBError> {
+        // Joined channels run at the level of A.
+        let b = if state.joined_channels {
+            state.channel_a.max(state.channel_b)
+        } else {
+            state.channel_b
+        };
+        for (channel, level) in [(TwoBChannel::A, state.channel_a), (TwoBChannel::B, b)] {
+            if self
+                .check(&TwoBCommand::SetChannel(channel, level), current)
+                .is_err()
+            {
+                return Err(TwoBError::refused("set_state", self.reason()));
+            }
+        }
+        Ok(())
+    }
+
+    fn reason(&self) -> &'static str {
+        if self.cooldown_until.is_some() {
+            "the session is cooling down"
+        } else {
+            "the session is ramping down"
+        }
+    }
+
+    pub fn status(&self, now: Instant) -> SessionStatus {
+        let since_update = self
+            .last_update
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        SessionStatus {
+            active: self.active,
+            active_time: self.active_time,
+            remaining: self.remaining(),
+            cooldown_remaining: self
+                .cooldown_until
+                .map(|until| until.saturating_duration_since(now)),
+        }
+        .after(if self.cooldown_until.is_some() {
+            Duration::ZERO
+        } else {
+            since_update
+        })
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+/// How fast the output levels may rise.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SlewConfig {
+    /// Highest rate channel A and B rise at, in levels per second. `None` lets levels
+    /// jump.
+    pub max_rate: Option<f64>,
+}
+
+/// A level rise in progress.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Ramp {
+    pub channel: TwoBChannel,
+    pub target: u8,
+    /// Level the device reported last.
+    pub level: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Target {
+    channel: TwoBChannel,
+    level: u8,
+    /// Level of the last step, a device that stays below it ends the ramp.
+    sent: Option<u8>,
+}
+
+/// Turns level rises of channel A and B into a ramp of single steps no faster than
+/// `max_rate`. Decreases are sent at once and end a ramp of the channel, `Kill` and
+/// `Reset` end every ramp.
+///
+/// The limiter only plans; the caller sends the commands returned by `plan` and,
+/// whenever `wait_time` has passed, by `next`.
+pub struct SlewLimiter {
+    config: SlewConfig,
+    targets: Vec<Target>,
+    last_step: [Option<Instant>; 2],
+}
+
+fn index(channel: TwoBChannel) -> Option<usize> {
+    match channel {
+        TwoBChannel::A => Some(0),
+        TwoBChannel::B => Some(1),
+        _ => None,
+    }
+}
+
+fn level_of(state: &TwoBState, channel: TwoBChannel) -> u8 {
+    match channel {
+        TwoBChannel::A => state.channel_a,
+        TwoBChannel::B => state.channel_b,
+        TwoBChannel::C => state.channel_c,
+        TwoBChannel::D => state.channel_d,
+    }
+}
+
+impl SlewLimiter {
+    pub fn new(config: SlewConfig) -> Self {
+        SlewLimiter {
+            config,
+            targets: Vec::new(),
+            last_step: [None; 2],
+        }
+    }
+
+    pub fn config(&self) -> &SlewConfig {
+        &self.config
+    }
+
+    /// Changing the rate keeps ramps in progress, turning the limiter off ends them.
+    pub fn set_config(&mut self, config: SlewConfig) {
+        self.config = config;
+        if self.interval().is_none() {
+            self.targets.clear();
+        }
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        self.config
+            .max_rate
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| Duration::from_secs_f64(1.0 / rate))
+    }
+
+    pub fn ramps(&self, state: &TwoBState) -> Vec<Ramp> {
+        self.targets
+            .iter()
+            .filter(|target| level_of(state, target.channel) < target.level)
+            .map(|target| Ramp {
+                channel: target.channel,
+                target: target.level,
+                level: level_of(state, target.channel),
+            })
+            .collect()
+    }
+
+    pub fn is_ramping(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    pub fn cancel(&mut self) {
+        self.targets.clear();
+    }
+
+    fn cancel_channel(&mut self, channel: TwoBChannel) {
+        self.targets.retain(|target| target.channel != channel);
+    }
+
+    fn ramp_to(&mut self, channel: TwoBChannel, level: u8) {
+        self.cancel_channel(channel);
+        self.targets.push(Target {
+            channel,
+            level,
+            sent: None,
+        });
+    }
+
+    /// The command to send now instead of `command`. Rises of channel A and B are
+    /// turned into a ramp, leaving nothing to send right away.
+    pub fn plan(&mut self, command: TwoBCommand, state: &TwoBState) -> Option<TwoBCommand> {
+        use TwoBCommand::*;
+        if self.interval().is_none() {
+            return Some(command);
+        }
+        match command {
+            Kill | Reset => self.cancel(),
+            SetChannel(channel, level) if index(channel).is_some() => {
+                if level > level_of(state, channel) {
+                    self.ramp_to(channel, level);
+                    return None;
+                }
+                self.cancel_channel(channel);
+            }
+            IncrementChannel(channel) if index(channel).is_some() => {
+                let ramp = self
+                    .targets
+                    .iter()
+                    .find(|target| target.channel == channel)
+                    .map(|target| target.level);
+                let target = ramp.unwrap_or_else(|| level_of(state, channel));
+                // At the maximum the device keeps an increment a no-op, so there is
+                // nothing to ramp to.
+                if target >= TwoBCommand::MAX_LEVEL {
+                    return ramp.is_none().then_some(command);
+                }
+                self.ramp_to(channel, target + 1);
+                return None;
+            }
+            DecrementChannel(channel) => self.cancel_channel(channel),
+            _ => {}
+        }
+        Some(command)
+    }
+
+    /// Time until the next ramp step is due, `None` without a ramp.
+    pub fn wait_time(&self, now: Instant) -> Option<Duration> {
+        let interval = self.interval()?;
+        self.targets
+            .iter()
+            .filter_map(|target| index(target.channel))
+            .map(|i| match self.last_step[i] {
+                Some(last_step) => (last_step + interval).saturating_duration_since(now),
+                None => Duration::ZERO,
+            })
+            .min()
+    }
+
+    /// The next due ramp step. Ramps that reached their target end, as do ramps the
+    /// device didn't follow, e.g. because a safety policy clamped the level.
+    pub fn next(&mut self, state: &TwoBState, now: Instant) -> Option<TwoBCommand> {
+        let interval = self.interval()?;
+        self.targets.retain(|target| {
+            let level = level_of(state, target.channel);
+            level < target.level && target.sent.is_none_or(|sent| level >= sent)
+        });
+        let last_step = self.last_step;
+        let due = self.targets.iter_mut().find(|target| {
+            index(target.channel)
+                .and_then(|i| last_step[i])
+                .is_none_or(|last_step| now >= last_step + interval)
+        })?;
+        let level = level_of(state, due.channel) + 1;
+        due.sent = Some(level);
+        let channel = due.channel;
+        if let Some(i) = index(channel) {
+            self.last_step[i] = Some(now);
+        }
+        Some(TwoBCommand::SetChannel(channel, level))
+    }
+
+    /// `state` with channel A and B no higher than in `current`, ramping them to their
+    /// level in `state` afterwards.
+    pub fn plan_state(&mut self, state: TwoBState, current: &TwoBState) -> TwoBState {
+        if self.interval().is_none() {
+            return state;
+        }
+        let mut planned = state.clone();
+        for channel in [TwoBChannel::A, TwoBChannel::B] {
+            let level = level_of(&state, channel);
+            if level > level_of(current, channel) {
+                self.ramp_to(channel, level);
+                match channel {
+                    TwoBChannel::A => planned.channel_a = current.channel_a,
+                    _ => planned.channel_b = current.channel_b,
+                }
+            } else {
+                self.cancel_channel(channel);
+            }
+        }
+        planned
+    }
+}
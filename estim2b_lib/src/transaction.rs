@@ -0,0 +1,338 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+
+use crate::*;
+
+/// What happens when a transaction didn't bring the device into the target state.
+#[derive(Clone, Copy, Debug, Default, Display, Eq, PartialEq, Serialize, Deserialize)]
+pub enum OnFailure {
+    #[default]
+    /// Return the device to the settings it had before the transaction. Levels are
+    /// never raised again, a level the transaction already lowered stays lowered.
+    Rollback,
+    /// Leave the device as it is and only report what was applied.
+    Report,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FailedCommand {
+    pub command: TwoBCommand,
+    pub error: String,
+}
+
+/// Outcome of a transaction, returned on success and inside
+/// `TwoBError::TransactionFailed` otherwise.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TransactionReport {
+    /// Commands the device acknowledged, in the order they were sent.
+    pub applied: Vec<TwoBCommand>,
+    /// The command that aborted the transaction.
+    pub failed: Option<FailedCommand>,
+    /// Fields the device reported with a different value than requested.
+    pub mismatched: Vec<String>,
+    /// Whether the device is back in its settings from before the transaction, with no
+    /// level above where it was.
+    pub rolled_back: bool,
+}
+
+impl TransactionReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_none() && self.mismatched.is_empty()
+    }
+}
+
+impl fmt::Display for TransactionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let applied: Vec<String> = self.applied.iter().map(TwoBCommand::encode).collect();
+        write!(f, "applied [{}]", applied.join(", "))?;
+        if let Some(failed) = &self.failed {
+            write!(
+                f,
+                ", '{}' failed: {}",
+                failed.command.encode(),
+                failed.error
+            )?;
+        }
+        if !self.mismatched.is_empty() {
+            write!(f, ", mismatched: {}", self.mismatched.join(", "))?;
+        }
+        if self.rolled_back {
+            write!(f, ", rolled back")?;
+        }
+        Ok(())
+    }
+}
+
+/// Channel levels that have to go down, in channel order.
+fn lower_levels(from: &TwoBState, to: &TwoBState) -> Vec<TwoBCommand> {
+    levels(from, to)
+        .into_iter()
+        .filter(|&(_, from, to)| to < from)
+        .map(|(channel, _, to)| TwoBCommand::SetChannel(channel, to))
+        .collect()
+}
+
+/// Every channel level that differs, in channel order.
+fn changed_levels(from: &TwoBState, to: &TwoBState) -> Vec<TwoBCommand> {
+    levels(from, to)
+        .into_iter()
+        .filter(|&(_, from, to)| to != from)
+        .map(|(channel, _, to)| TwoBCommand::SetChannel(channel, to))
+        .collect()
+}
+
+fn levels(from: &TwoBState, to: &TwoBState) -> [(TwoBChannel, u8, u8); 4] {
+    [
+        (TwoBChannel::A, from.channel_a, to.channel_a),
+        (TwoBChannel::B, from.channel_b, to.channel_b),
+        (TwoBChannel::C, from.channel_c, to.channel_c),
+        (TwoBChannel::D, from.channel_d, to.channel_d),
+    ]
+}
+
+fn settings(from: &TwoBState, to: &TwoBState) -> Vec<TwoBCommand> {
+    let mut commands = Vec::new();
+    macro_rules! compare {
+        ($field:ident, $command:path) => {
+            if from.$field != to.$field {
+                commands.push($command(to.$field));
+            }
+        };
+    }
+    compare!(mode, TwoBCommand::SetMode);
+    compare!(power, TwoBCommand::SetPower);
+    compare!(bias, TwoBCommand::SetBias);
+    compare!(joined_channels, TwoBCommand::SetJoinedChannels);
+    compare!(map, TwoBCommand::SetMap);
+    compare!(ramp, TwoBCommand::SetRamp);
+    compare!(warp, TwoBCommand::SetWarp);
+    commands
+}
+
+/// Minimal list of commands turning `from` into `to`.
+///
+/// Levels that go down are lowered first, then the settings are changed and levels that
+/// go up are raised last, so the output never runs a new mode at the old, higher level.
+pub fn plan(from: &TwoBState, to: &TwoBState) -> Vec<TwoBCommand> {
+    let mut commands = lower_levels(from, to);
+    commands.extend(settings(from, to));
+    commands.extend(
+        levels(from, to)
+            .into_iter()
+            .filter(|&(_, from, to)| to > from)
+            .map(|(channel, _, to)| TwoBCommand::SetChannel(channel, to)),
+    );
+    commands
+}
+
+/// State a failed transaction returns to: the settings of `before`, with every level
+/// at most where it was before and where it is now.
+fn rollback_target(before: &TwoBState, current: &TwoBState) -> TwoBState {
+    TwoBState {
+        channel_a: before.channel_a.min(current.channel_a),
+        channel_b: before.channel_b.min(current.channel_b),
+        channel_c: before.channel_c.min(current.channel_c),
+        channel_d: before.channel_d.min(current.channel_d),
+        ..before.clone()
+    }
+}
+
+/// Names of the fields that differ between `a` and `b`. The battery isn't compared.
+pub fn differing_fields(a: &TwoBState, b: &TwoBState) -> Vec<String> {
+    let mut fields = Vec::new();
+    macro_rules! compare {
+        ($($field:ident),*) => {
+            $(
+                if a.$field != b.$field {
+                    fields.push(stringify!($field).to_string());
+                }
+            )*
+        };
+    }
+    compare!(
+        mode,
+        channel_a,
+        channel_b,
+        channel_c,
+        channel_d,
+        power,
+        bias,
+        joined_channels,
+        map,
+        ramp,
+        warp
+    );
+    fields
+}
+
+/// Applies a complete `TwoBState` and verifies that the device reports it afterwards.
+pub struct Transaction {
+    target: TwoBState,
+    on_failure: OnFailure,
+}
+
+impl Transaction {
+    pub fn new(target: TwoBState, on_failure: OnFailure) -> Self {
+        Transaction { target, on_failure }
+    }
+
+    /// Runs the transaction against `two_b`. A failed command or a state that doesn't
+    /// match the target afterwards is returned as `TwoBError::TransactionFailed`.
+    pub fn apply<T: TwoB + ?Sized>(&self, two_b: &mut T) -> Result<TransactionReport, TwoBError> {
+        let mut run = Run::new(self, two_b.get_state());
+        let mut result = Ok(());
+        loop {
+            result = match run.next(&two_b.get_state(), result) {
+                Step::Execute(command) => two_b.execute(command),
+                Step::Refresh => two_b.refresh_state(),
+                Step::Done(report) => return report,
+            };
+        }
+    }
+
+    /// Same as `apply`, for an `AsyncTwoB`.
+    #[cfg(feature = "async")]
+    pub async fn apply_async<T: AsyncTwoB + ?Sized>(
+        &self,
+        two_b: &mut T,
+    ) -> Result<TransactionReport, TwoBError> {
+        let mut run = Run::new(self, two_b.get_state().await);
+        let mut result = Ok(());
+        loop {
+            result = match run.next(&two_b.get_state().await, result) {
+                Step::Execute(command) => two_b.execute(command).await,
+                Step::Refresh => two_b.refresh_state().await,
+                Step::Done(report) => return report,
+            };
+        }
+    }
+}
+
+fn failed(command: TwoBCommand, error: TwoBError) -> FailedCommand {
+    FailedCommand {
+        command,
+        error: format!("{:?}", error),
+    }
+}
+
+/// Levels are lowered first, then the settings are changed and the remaining levels
+/// set. Each step is planned against the state the device reported last.
+const STEPS: [fn(&TwoBState, &TwoBState) -> Vec<TwoBCommand>; 3] =
+    [lower_levels, settings, changed_levels];
+
+/// What `Run` needs done next.
+enum Step {
+    Execute(TwoBCommand),
+    Refresh,
+    Done(Result<TransactionReport, TwoBError>),
+}
+
+enum Phase {
+    /// Sending the commands of `STEPS[step]`, `queue` is planned when the step starts.
+    Apply {
+        step: usize,
+        queue: Option<VecDeque<TwoBCommand>>,
+        sent: Option<TwoBCommand>,
+    },
+    /// Waiting for the state after the last command.
+    Verify,
+}
+
+/// A transaction without the I/O, so `apply` and `apply_async` only carry out its
+/// steps. Every call to `next` gets the outcome of the previous step and the state the
+/// device reported afterwards.
+struct Run {
+    on_failure: OnFailure,
+    before: TwoBState,
+    /// The transaction's target, and the rollback target once rolling back.
+    target: TwoBState,
+    rolling_back: bool,
+    phase: Phase,
+    report: TransactionReport,
+}
+
+impl Run {
+    fn new(transaction: &Transaction, before: TwoBState) -> Self {
+        Run {
+            on_failure: transaction.on_failure,
+            before,
+            target: transaction.target.clone(),
+            rolling_back: false,
+            phase: Phase::Apply {
+                step: 0,
+                queue: None,
+                sent: None,
+            },
+            report: TransactionReport::default(),
+        }
+    }
+
+    fn next(&mut self, state: &TwoBState, result: Result<(), TwoBError>) -> Step {
+        let (step, queue, sent) = match &mut self.phase {
+            Phase::Apply { step, queue, sent } => (step, queue, sent),
+            Phase::Verify => return self.verify(state, result),
+        };
+        if let Some(command) = sent.take() {
+            match result {
+                Ok(()) if !self.rolling_back => self.report.applied.push(command),
+                Ok(()) => {}
+                Err(_) if self.rolling_back => return self.done(),
+                Err(e) => {
+                    self.report.failed = Some(failed(command, e));
+                    self.phase = Phase::Verify;
+                    return Step::Refresh;
+                }
+            }
+        }
+        while *step < STEPS.len() {
+            let target = &self.target;
+            let planned = queue.get_or_insert_with(|| STEPS[*step](state, target).into());
+            if let Some(command) = planned.pop_front() {
+                *sent = Some(command);
+                return Step::Execute(command);
+            }
+            *step += 1;
+            *queue = None;
+        }
+        self.phase = Phase::Verify;
+        Step::Refresh
+    }
+
+    fn verify(&mut self, state: &TwoBState, result: Result<(), TwoBError>) -> Step {
+        if self.rolling_back {
+            self.report.rolled_back =
+                result.is_ok() && differing_fields(state, &self.target).is_empty();
+            return self.done();
+        }
+        match result {
+            Ok(()) => self.report.mismatched = differing_fields(state, &self.target),
+            Err(e) => {
+                if self.report.failed.is_none() {
+                    self.report.failed = Some(failed(TwoBCommand::RefreshState, e));
+                }
+            }
+        }
+        if self.report.is_success() {
+            return Step::Done(Ok(mem::take(&mut self.report)));
+        }
+        if self.on_failure == OnFailure::Rollback {
+            self.target = rollback_target(&self.before, state);
+            self.rolling_back = true;
+            self.phase = Phase::Apply {
+                step: 0,
+                queue: None,
+                sent: None,
+            };
+            return self.next(state, Ok(()));
+        }
+        self.done()
+    }
+
+    fn done(&mut self) -> Step {
+        Step::Done(Err(TwoBError::TransactionFailed(mem::take(
+            &mut self.report,
+        ))))
+    }
+}
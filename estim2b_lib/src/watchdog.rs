@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::*;
+
+/// Identifies a lease registered with a `Watchdog`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct LeaseId(pub u64);
+
+/// What the watchdog does once the last lease expired.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ExpiryAction {
+    #[default]
+    Kill,
+    /// Lowers channel A and B to at most these levels, leaving everything else as is.
+    SafeLevels { a: u8, b: u8 },
+    /// Kills the device and latches the emergency stop of the handle.
+    EmergencyStop,
+}
+
+impl ExpiryAction {
+    /// The commands carrying out the action on a device in `state`.
+    pub fn commands(&self, state: &TwoBState) -> Vec<TwoBCommand> {
+        match self {
+            ExpiryAction::Kill | ExpiryAction::EmergencyStop => vec![TwoBCommand::Kill],
+            ExpiryAction::SafeLevels { a, b } => TwoBCommand::lower_levels(state, *a, *b),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    pub action: ExpiryAction,
+}
+
+/// A client that has to send a heartbeat at least every `timeout`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Lease {
+    pub id: LeaseId,
+    pub name: String,
+    pub timeout: Duration,
+    pub last_heartbeat: SystemTime,
+}
+
+struct Entry {
+    lease: Lease,
+    deadline: Instant,
+}
+
+/// Dead-man switch for remote controllers. Each client registers a lease and keeps it
+/// alive with heartbeats; once a lease runs out it is dropped, and once the last one
+/// runs out the device has to be brought into a safe state with `config().action`.
+///
+/// Without any lease the watchdog is disarmed.
+#[derive(Default)]
+pub struct Watchdog {
+    config: WatchdogConfig,
+    entries: Vec<Entry>,
+    next_id: u64,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Watchdog {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &WatchdogConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: WatchdogConfig) {
+        self.config = config;
+    }
+
+    pub fn register(&mut self, name: String, timeout: Duration, now: Instant) -> LeaseId {
+        let id = LeaseId(self.next_id);
+        self.next_id += 1;
+        self.entries.push(Entry {
+            lease: Lease {
+                id,
+                name,
+                timeout,
+                last_heartbeat: SystemTime::now(),
+            },
+            deadline: now + timeout,
+        });
+        id
+    }
+
+    /// Extends `lease` by its timeout. Returns whether the lease was still alive.
+    pub fn heartbeat(&mut self, lease: LeaseId, now: Instant) -> bool {
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.lease.id == lease)
+        {
+            Some(entry) => {
+                entry.lease.last_heartbeat = SystemTime::now();
+                entry.deadline = now + entry.lease.timeout;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drops `lease` without tripping the watchdog, even if it was the last one.
+    pub fn release(&mut self, lease: LeaseId) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.lease.id != lease);
+        self.entries.len() != before
+    }
+
+    pub fn leases(&self) -> Vec<Lease> {
+        self.entries
+            .iter()
+            .map(|entry| entry.lease.clone())
+            .collect()
+    }
+
+    /// Time until the next lease runs out, `None` without a lease.
+    pub fn wait_time(&self, now: Instant) -> Option<Duration> {
+        self.entries
+            .iter()
+            .map(|entry| entry.deadline.saturating_duration_since(now))
+            .min()
+    }
+
+    /// Drops and returns the leases that ran out by `now`, together with whether the
+    /// last lease was among them.
+    pub fn expire(&mut self, now: Instant) -> (Vec<Lease>, bool) {
+        let (expired, alive) = self
+            .entries
+            .drain(..)
+            .partition::<Vec<_>, _>(|entry| entry.deadline <= now);
+        self.entries = alive;
+        let tripped = !expired.is_empty() && self.entries.is_empty();
+        (
+            expired.into_iter().map(|entry| entry.lease).collect(),
+            tripped,
+        )
+    }
+}
@@ -0,0 +1,129 @@
+#![cfg(all(feature = "async", feature = "emulator"))]
+
+mod common;
+
+use common::{raw_bridge, rfc2217_bridge};
+use estim2b_lib::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+/// Opener serving a fresh emulator on every call, like a 2B that was power cycled.
+fn emulator_opener(current: Arc<Mutex<Option<JoinHandle<()>>>>) -> AsyncOpener {
+    Box::new(move || {
+        let current = current.clone();
+        Box::pin(async move {
+            let (client, mut server) = tokio::io::duplex(256);
+            let mut emulator = Emulator::new()?;
+            let task = tokio::spawn(async move {
+                let mut command = Vec::new();
+                let mut byte = [0u8; 1];
+                while let Ok(1) = server.read(&mut byte).await {
+                    if byte[0] != b'\r' {
+                        command.push(byte[0]);
+                        continue;
+                    }
+                    let reply = emulator.handle(&String::from_utf8_lossy(&command)) + "\n";
+                    command.clear();
+                    if server.write_all(reply.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            *current.lock().unwrap() = Some(task);
+            Ok(Box::new(client) as Box<dyn AsyncTransport>)
+        })
+    })
+}
+
+async fn cycle(twob: &mut dyn AsyncTwoB) -> Result<(), TwoBError> {
+    twob.set_mode(TwoBMode::Milk).await?;
+    twob.set_channel(TwoBChannel::A, 12).await?;
+    twob.increment_channel(TwoBChannel::A).await?;
+    twob.execute(TwoBCommand::SetPower(TwoBPower::HIGH)).await?;
+    twob.refresh_state().await?;
+    assert_eq!(twob.get_mode().await, TwoBMode::Milk);
+    assert_eq!(twob.get_channel(TwoBChannel::A).await, 13);
+    assert_eq!(twob.get_power().await, TwoBPower::HIGH);
+
+    let target = TwoBState {
+        channel_b: 40,
+        warp: TwoBWarp::X8,
+        ..twob.get_state().await
+    };
+    twob.set_state(target.clone()).await?;
+    assert_eq!(twob.get_state().await, target);
+    assert_eq!(twob.get_version().await, "2.122B");
+    Ok(())
+}
+
+#[tokio::test]
+async fn async_usb_two_b_over_tcp() -> Result<(), TwoBError> {
+    let mut twob = AsyncUSBTwoB::open(&raw_bridge()?).await?;
+    cycle(&mut twob).await
+}
+
+#[tokio::test]
+async fn async_usb_two_b_over_rfc2217() -> Result<(), TwoBError> {
+    let refused = Arc::new(Mutex::new(Vec::new()));
+    let address = rfc2217_bridge(Arc::new(Mutex::new(Vec::new())), refused.clone())?;
+    let mut twob = AsyncUSBTwoB::open(&address).await?;
+    cycle(&mut twob).await?;
+    assert_eq!(*refused.lock().unwrap(), vec![1]);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn async_usb_two_b_over_pty() -> Result<(), TwoBError> {
+    let pty = Emulator::new()?.spawn_pty()?;
+    let mut twob = AsyncUSBTwoB::open(pty.path()).await?;
+    cycle(&mut twob).await
+}
+
+#[tokio::test]
+async fn async_usb_two_b_reconnects() -> Result<(), TwoBError> {
+    let current = Arc::new(Mutex::new(None));
+    let mut twob =
+        AsyncUSBTwoB::connect(emulator_opener(current.clone()), LinkConfig::default()).await?;
+    twob.set_reconnect_config(ReconnectConfig {
+        delay: Duration::from_millis(10),
+        safe_level: 3,
+        ..ReconnectConfig::default()
+    });
+    twob.set_mode(TwoBMode::Milk).await?;
+    twob.set_channel(TwoBChannel::A, 40).await?;
+
+    // Unplug: the emulator behind the current stream goes away.
+    let unplugged = current.lock().unwrap().take();
+    unplugged.unwrap().abort();
+
+    twob.refresh_state().await?;
+    assert_eq!(twob.connection_state(), ConnectionState::Connected);
+    assert_eq!(twob.get_mode().await, TwoBMode::Milk);
+    assert_eq!(twob.get_channel(TwoBChannel::A).await, 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn blocking_adapter() -> Result<(), TwoBError> {
+    let mut twob = BlockingAdapter::new(VirtualTwoB::new()?);
+    cycle(&mut twob).await?;
+    let mut twob = BlockingAdapter::new(USBTwoB::try_from(raw_bridge()?.as_str())?);
+    cycle(&mut twob).await
+}
+
+#[tokio::test]
+async fn unsupported_commands_are_rejected() -> Result<(), TwoBError> {
+    let mut twob = AsyncUSBTwoB::open(&raw_bridge()?).await?;
+    assert!(matches!(
+        twob.set_channel(TwoBChannel::A, 101).await,
+        Err(TwoBError::Unsupported(_))
+    ));
+    assert!(matches!(
+        open_async_transport("udp://127.0.0.1:1").await,
+        Err(TwoBError::ConnectionError(_))
+    ));
+    Ok(())
+}
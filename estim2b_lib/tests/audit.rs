@@ -0,0 +1,250 @@
+#![cfg(feature = "virtual")]
+
+use estim2b_lib::*;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Audit log in the temporary directory, removed once dropped, also when a test fails.
+struct LogFile(PathBuf);
+
+impl LogFile {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("estim2b-audit-{}-{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        LogFile(path)
+    }
+}
+
+impl Deref for LogFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for LogFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for LogFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn write_records(path: &Path, count: u8) {
+    let mut log = AuditLog::open(path).unwrap();
+    let state = VirtualTwoB::new().unwrap().get_state();
+    for level in 0..count {
+        log.append(
+            Origin::Cli,
+            AuditedAction::Commands(vec![TwoBCommand::SetChannel(TwoBChannel::A, level)]),
+            state.clone(),
+            None,
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn the_chain_detects_edits() {
+    let path = LogFile::new("edits");
+    write_records(&path, 3);
+    // Reopening continues the chain.
+    write_records(&path, 1);
+    let records = AuditLog::verify(&path).unwrap();
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[3].prev_hash, records[2].hash);
+
+    let content = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    fs::write(&path, content.replacen("\"A\",1]", "\"A\",99]", 1)).unwrap();
+    assert!(AuditLog::verify(&path).is_err());
+    assert!(AuditLog::open(&path).is_err());
+    assert_eq!(AuditLog::read(&path).unwrap().len(), 4);
+
+    let removed = [lines[0], lines[2], lines[3]].join("\n") + "\n";
+    fs::write(&path, removed).unwrap();
+    assert!(AuditLog::verify(&path).is_err());
+}
+
+#[test]
+fn a_line_cut_off_is_dropped() {
+    let path = LogFile::new("cut");
+    write_records(&path, 2);
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"{\"seq\":2,\"timest").unwrap();
+    assert_eq!(AuditLog::verify(&path).unwrap().len(), 2);
+    write_records(&path, 1);
+    assert_eq!(AuditLog::verify(&path).unwrap().len(), 3);
+}
+
+#[test]
+fn handle_records_origins_and_errors() -> Result<(), TwoBError> {
+    let path = LogFile::new("handle");
+    let twob = TwoBHandle::new(VirtualTwoB::new()?);
+    twob.set_audit_log(Some(AuditLog::open(&path)?))?;
+    let script = twob.with_origin(Origin::Script("warmup".into()));
+    script.submit(vec![TwoBCommand::SetChannel(TwoBChannel::A, 30)])?;
+    assert_eq!(twob.audit_log_path(), Some(path.to_path_buf()));
+    twob.engage_emergency_stop(StopSource::Library)?;
+    assert!(script
+        .submit(vec![TwoBCommand::SetChannel(TwoBChannel::A, 10)])
+        .is_err());
+    twob.reset_emergency_stop("checked")?;
+    twob.submit(vec![TwoBCommand::SetChannel(TwoBChannel::A, 20)])?;
+    twob.set_watchdog_config(WatchdogConfig {
+        action: ExpiryAction::SafeLevels { a: 0, b: 0 },
+    })?;
+    twob.register_lease("remote", Duration::from_millis(10))?;
+    // The watchdog's levels are recorded once the worker has sent them.
+    for _ in 0..100 {
+        if AuditLog::read(&path)?.len() == 6 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let records = AuditLog::verify(&path)?;
+    let summary: Vec<_> = records
+        .iter()
+        .map(|record| (record.origin.clone(), record.error.is_some()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (Origin::Script("warmup".into()), false),
+            (Origin::Library, false),
+            (Origin::Script("warmup".into()), true),
+            (Origin::Library, false),
+            (Origin::Library, false),
+            (Origin::Automatic("watchdog".into()), false),
+        ]
+    );
+    assert_eq!(records[0].state.channel_a, 30);
+    assert_eq!(records[5].state.channel_a, 0);
+    assert_eq!(
+        records[3].action,
+        AuditedAction::ResetEmergencyStop {
+            reason: "checked".into()
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn every_command_sent_is_recorded_with_its_own_result() -> Result<(), TwoBError> {
+    use TwoBCommand::*;
+    let path = LogFile::new("sent");
+    let twob = TwoBHandle::new(VirtualTwoB::new()?);
+    twob.set_audit_log(Some(AuditLog::open(&path)?))?;
+    twob.set_queue_config(QueueConfig {
+        min_gap: Duration::from_millis(300),
+    })?;
+    twob.engage_emergency_stop(StopSource::Library)?;
+    let other = twob.with_origin(Origin::Script("other".into()));
+    let submitted = thread::spawn(move || {
+        other.submit(vec![
+            SetChannel(TwoBChannel::C, 60),
+            SetMode(TwoBMode::Milk),
+        ])
+    });
+    thread::sleep(Duration::from_millis(100));
+    // Merged with the mode of the other handle, which is not told about the refused raise.
+    assert!(twob
+        .submit(vec![
+            SetMode(TwoBMode::Pulse),
+            SetChannel(TwoBChannel::A, 10)
+        ])
+        .is_err());
+    assert!(submitted.join().unwrap().is_ok());
+    let probe = twob.with_origin(Origin::Script("probe".into()));
+    assert!(probe.call(|two_b| two_b.get_state()).is_err());
+    twob.reset_emergency_stop("checked")?;
+
+    twob.set_queue_config(QueueConfig::default())?;
+    twob.set_slew_config(SlewConfig {
+        max_rate: Some(100.0),
+    })?;
+    twob.submit(vec![SetChannel(TwoBChannel::A, 5)])?;
+    twob.set_poll_interval(Some(Duration::from_millis(20)))?;
+    let automatic = |source: &str| Origin::Automatic(source.into());
+    for _ in 0..100 {
+        let records = AuditLog::read(&path)?;
+        if [automatic("slew"), automatic("poller")]
+            .iter()
+            .all(|origin| records.iter().any(|record| &record.origin == origin))
+        {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let records = AuditLog::verify(&path)?;
+    let summary: Vec<_> = records
+        .iter()
+        .map(|record| {
+            (
+                record.origin.clone(),
+                record.action.clone(),
+                record.error.is_some(),
+            )
+        })
+        .collect();
+    let other = Origin::Script("other".into());
+    assert_eq!(
+        summary[..6],
+        [
+            (
+                Origin::Library,
+                AuditedAction::EmergencyStop(StopSource::Library),
+                false
+            ),
+            (
+                other.clone(),
+                AuditedAction::Commands(vec![SetChannel(TwoBChannel::C, 60)]),
+                false
+            ),
+            (
+                Origin::Coalesced(vec![other, Origin::Library]),
+                AuditedAction::Commands(vec![SetMode(TwoBMode::Pulse)]),
+                false
+            ),
+            (
+                Origin::Library,
+                AuditedAction::Commands(vec![SetChannel(TwoBChannel::A, 10)]),
+                true
+            ),
+            (Origin::Script("probe".into()), AuditedAction::Call, true),
+            (
+                Origin::Library,
+                AuditedAction::ResetEmergencyStop {
+                    reason: "checked".into()
+                },
+                false
+            ),
+        ]
+    );
+    // The first step of the ramp is sent for the submitter, the others by the handle.
+    assert_eq!(records[6].origin, Origin::Library);
+    assert!(matches!(
+        records[6].action,
+        AuditedAction::Commands(ref commands) if commands.len() == 1
+    ));
+    assert!(records
+        .iter()
+        .any(|record| record.origin == automatic("slew")));
+    assert!(records
+        .iter()
+        .any(|record| record.origin == automatic("poller")
+            && record.action == AuditedAction::Commands(vec![RefreshState])));
+    Ok(())
+}
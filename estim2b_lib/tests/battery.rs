@@ -0,0 +1,159 @@
+#![cfg(feature = "usb")]
+
+mod common;
+
+use common::{wait_for, MockTransport, STATUS_LINE};
+use estim2b_lib::*;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const EMPTY_LINE: &str = "300:10:12:120:116:15:L:0:0:0:0:0:2.120B\n";
+
+/// 0.02 V per count between 6.0 and 8.4 V, as measured on one box.
+fn model() -> BatteryModel {
+    BatteryModel {
+        supply: PowerSupply::BatteryPack,
+        volts_per_count: 0.02,
+        empty_volts: 6.0,
+        full_volts: 8.4,
+    }
+}
+
+fn config() -> BatteryConfig {
+    BatteryConfig {
+        sample_interval: Duration::ZERO,
+        ..BatteryConfig::new(model())
+    }
+}
+
+#[test]
+fn raw_values_are_converted() {
+    let model = model();
+    assert!((model.volts(344) - 6.88).abs() < 1e-9);
+    assert!((model.percentage(360).unwrap() - 50.0).abs() < 1e-9);
+    assert_eq!(model.percentage(0), Some(0.0));
+    assert_eq!(model.percentage(1000), Some(100.0));
+    let mains = BatteryModel {
+        supply: PowerSupply::Mains,
+        ..model.clone()
+    };
+    assert_eq!(mains.percentage(344), None);
+
+    let json = r#"{ "model": { "supply": "Mains", "volts_per_count": 0.02, "empty_volts": 6.0, "full_volts": 8.4 } }"#;
+    let config = BatteryConfig::from_json(json).unwrap();
+    assert_eq!(config.model, mains);
+    assert_eq!(config.thresholds, BatteryConfig::new(model).thresholds);
+}
+
+#[test]
+fn models_are_required_and_checked() {
+    assert!(BatteryConfig::from_json(r#"{ "model": { "supply": "Mains" } }"#).is_err());
+    let inverted =
+        r#"{ "model": { "volts_per_count": 0.02, "empty_volts": 8.4, "full_volts": 6.0 } }"#;
+    assert!(BatteryConfig::from_json(inverted).is_err());
+    let empty =
+        r#"{ "model": { "volts_per_count": 0.02, "empty_volts": 6.0, "full_volts": 6.0 } }"#;
+    assert!(BatteryConfig::from_json(empty).is_err());
+}
+
+#[test]
+fn thresholds_trigger_once() {
+    let mut monitor = BatteryMonitor::new(config());
+    let start = Instant::now();
+    let at = |minutes: u64| start + Duration::from_secs(minutes * 60);
+    // A count is 0.02 V or 1/120 of the range, 323 counts are just below 20 %.
+    assert!(monitor.record(360, at(0)).is_empty());
+    let crossed = monitor.record(323, at(1));
+    assert_eq!(crossed.len(), 1);
+    assert_eq!(crossed[0].action, BatteryAction::Warn);
+    assert!(monitor.record(322, at(2)).is_empty());
+    // Rearmed only once the charge rose more than the hysteresis above it.
+    assert!(monitor.record(328, at(3)).is_empty());
+    assert!(monitor.record(323, at(4)).is_empty());
+    assert!(monitor.record(340, at(5)).is_empty());
+    assert_eq!(monitor.record(323, at(6)).len(), 1);
+
+    let crossed = monitor.record(300, at(7));
+    assert_eq!(
+        crossed.iter().map(|t| t.action).collect::<Vec<_>>(),
+        vec![
+            BatteryAction::LowerLevels { max_level: 20 },
+            BatteryAction::Kill
+        ]
+    );
+}
+
+#[test]
+fn discharge_rate_is_estimated() {
+    let mut monitor = BatteryMonitor::new(BatteryConfig {
+        thresholds: Vec::new(),
+        ..config()
+    });
+    assert_eq!(monitor.status(), None);
+    let start = Instant::now();
+    // One count, 5/6 %, per 6 minutes.
+    for (i, raw) in [360, 359, 358, 357].into_iter().enumerate() {
+        monitor.record(raw, start + Duration::from_secs(360 * i as u64));
+    }
+    let status = monitor.status().unwrap();
+    assert_eq!(status.raw, 357);
+    assert!((status.percent.unwrap() - 47.5).abs() < 1e-9);
+    let rate = 100.0 / 12.0;
+    assert!((status.discharge_rate.unwrap() - rate).abs() < 1e-6);
+    let remaining = status.remaining.unwrap().as_secs_f64();
+    assert!((remaining - 47.5 / rate * 3600.0).abs() < 1.0);
+}
+
+#[test]
+fn steady_readings_have_no_remaining_time() {
+    let mut monitor = BatteryMonitor::new(config());
+    let start = Instant::now();
+    for i in 0..30 {
+        monitor.record(344, start + Duration::from_millis(1234 * i));
+    }
+    let status = monitor.status().unwrap();
+    assert!(status.discharge_rate.unwrap().abs() < 1e-9);
+    assert_eq!(status.remaining, None);
+
+    // A model without a range gives no percentage to divide.
+    let mut monitor = BatteryMonitor::new(BatteryConfig::new(BatteryModel {
+        full_volts: 6.0,
+        ..model()
+    }));
+    monitor.record(301, start);
+    monitor.record(300, start + Duration::from_secs(10));
+    assert_eq!(monitor.status().unwrap().remaining, None);
+}
+
+#[test]
+fn empty_battery_kills_the_handle() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let written = transport.written.clone();
+    let script = transport.script.clone();
+    let mut twob = TwoBHandle::new(USBTwoB::from_transport(transport)?);
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let recorded = changes.clone();
+    twob.subscribe(Box::new(move |event| {
+        recorded.lock().unwrap().push(event.change.clone())
+    }));
+    twob.set_battery_config(Some(config()))?;
+    twob.set_channel(TwoBChannel::A, 40)?;
+    assert!((twob.battery_status().unwrap().volts - 6.88).abs() < 1e-9);
+    assert!(!written.lock().unwrap().contains(&"K".to_string()));
+
+    script
+        .lock()
+        .unwrap()
+        .extend((0..10).map(|_| Some(EMPTY_LINE.to_string())));
+    twob.refresh_state()?;
+    assert!(written.lock().unwrap().contains(&"K".to_string()));
+    assert_eq!(twob.battery_status().unwrap().percent, Some(0.0));
+    assert!(wait_for(
+        &changes,
+        &TwoBChange::BatteryLow {
+            percent: 3,
+            action: BatteryAction::Kill
+        }
+    ));
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use estim2b_lib::*;
+use std::str::FromStr;
+
+fn all_commands() -> Vec<TwoBCommand> {
+    use TwoBCommand::*;
+    let mut commands = vec![
+        RefreshState,
+        Reset,
+        Kill,
+        SetJoinedChannels(false),
+        SetJoinedChannels(true),
+        SetPower(TwoBPower::HIGH),
+        SetPower(TwoBPower::LOW),
+        SetPower(TwoBPower::DYNAMIC),
+    ];
+    let end: u8 = TwoBMode::Training.into();
+    commands.extend((0..=end).map(|mode| SetMode(TwoBMode::try_from(mode).unwrap())));
+    commands.extend((0..3).map(|map| SetMap(TwoBMap::try_from(map).unwrap())));
+    commands.extend((0..4).map(|bias| SetBias(TwoBBias::try_from(bias).unwrap())));
+    commands.extend((0..4).map(|ramp| SetRamp(TwoBRamp::try_from(ramp).unwrap())));
+    commands.extend((0..6).map(|warp| SetWarp(TwoBWarp::try_from(warp).unwrap())));
+    for channel in ["A", "B", "C", "D"] {
+        let channel = TwoBChannel::from_str(channel).unwrap();
+        commands.push(IncrementChannel(channel));
+        commands.push(DecrementChannel(channel));
+        commands.extend((0..=TwoBCommand::MAX_LEVEL).map(|value| SetChannel(channel, value)));
+    }
+    commands
+}
+
+#[test]
+fn command_round_trip() -> Result<(), TwoBError> {
+    for command in all_commands() {
+        assert_eq!(TwoBCommand::decode(&command.encode())?, command);
+    }
+    Ok(())
+}
+
+#[test]
+fn encode_commands() {
+    assert_eq!(TwoBCommand::RefreshState.encode(), "V");
+    assert_eq!(TwoBCommand::SetMode(TwoBMode::Flo).encode(), "M3");
+    assert_eq!(TwoBCommand::SetBias(TwoBBias::MAX).encode(), "Q3");
+    assert_eq!(TwoBCommand::SetChannel(TwoBChannel::C, 42).encode(), "C42");
+    assert_eq!(TwoBCommand::DecrementChannel(TwoBChannel::D).encode(), "D-");
+}
+
+#[test]
+fn decode_invalid_commands() {
+    for command in [
+        "", "X", "V1", "J2", "M17", "M", "A+5", "A-1", "A101", "A256", "O3",
+    ] {
+        assert!(TwoBCommand::decode(command).is_err(), "{}", command);
+    }
+    assert_eq!(TwoBCommand::decode("K\r").unwrap(), TwoBCommand::Kill);
+}
+
+#[test]
+fn validate_levels() {
+    let level = |value| TwoBCommand::SetChannel(TwoBChannel::B, value).validate();
+    assert!(level(100).is_ok());
+    assert!(level(101).is_err());
+    assert!(TwoBCommand::SetMode(TwoBMode::Milk).validate().is_ok());
+}
+
+#[cfg(feature = "virtual")]
+#[test]
+fn execute_dispatches_commands() -> Result<(), TwoBError> {
+    let mut twob = VirtualTwoB::new()?;
+    twob.execute(TwoBCommand::SetMode(TwoBMode::Twist))?;
+    twob.execute(TwoBCommand::SetChannel(TwoBChannel::A, 7))?;
+    twob.execute(TwoBCommand::IncrementChannel(TwoBChannel::A))?;
+    assert_eq!(twob.get_mode(), TwoBMode::Twist);
+    assert_eq!(twob.get_channel(TwoBChannel::A), 8);
+    Ok(())
+}
@@ -0,0 +1,275 @@
+#![allow(dead_code)]
+
+use estim2b_lib::*;
+use std::collections::VecDeque;
+#[cfg(feature = "emulator")]
+use std::io::BufReader;
+use std::io::{self, Read, Write};
+#[cfg(feature = "emulator")]
+use std::net::{TcpListener, TcpStream};
+#[cfg(feature = "emulator")]
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "emulator")]
+use std::thread;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Waits up to a second until `changes` contains `change`. Handles call their listeners
+/// on a thread of their own, after the command that caused the change returned.
+pub fn wait_for(changes: &Arc<Mutex<Vec<TwoBChange>>>, change: &TwoBChange) -> bool {
+    wait_until(|| changes.lock().unwrap().contains(change))
+}
+
+/// Whether `result` was refused with a `PolicyViolation`.
+pub fn refused<T>(result: Result<T, TwoBError>) -> bool {
+    matches!(result, Err(TwoBError::PolicyViolation(_)))
+}
+
+/// Waits up to a second until `done` returns true.
+pub fn wait_until(done: impl Fn() -> bool) -> bool {
+    for _ in 0..100 {
+        if done() {
+            return true;
+        }
+        sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+/// Clock for `TwoBHandle::with_clock` that only moves on when the test advances it.
+pub struct ManualClock(Arc<Mutex<Instant>>);
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock(Arc::new(Mutex::new(Instant::now())))
+    }
+}
+
+impl ManualClock {
+    pub fn clock(&self) -> Clock {
+        let now = self.0.clone();
+        Arc::new(move || *now.lock().unwrap())
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+type Responder = Box<dyn FnMut(&str) -> Option<String> + Send>;
+
+pub const STATUS_LINE: &str = "344:10:12:120:116:15:L:0:0:0:0:0:2.120B";
+
+/// In-memory transport answering every command with a status line and recording
+/// everything written to it.
+///
+/// Replies can be scripted per command with `script`: `Some(bytes)` is sent instead of
+/// the status line, `None` leaves the command unanswered. Unscripted commands are
+/// passed to the responder if there is one.
+pub struct MockTransport {
+    pub written: Arc<Mutex<Vec<String>>>,
+    pub script: Arc<Mutex<VecDeque<Option<String>>>>,
+    pub fail_writes: Arc<Mutex<bool>>,
+    reply: String,
+    responder: Option<Responder>,
+    line: String,
+    pending: VecDeque<u8>,
+    timeout: Duration,
+}
+
+impl MockTransport {
+    pub fn new(reply: &str) -> Self {
+        MockTransport {
+            written: Arc::new(Mutex::new(Vec::new())),
+            script: Arc::new(Mutex::new(VecDeque::new())),
+            fail_writes: Arc::new(Mutex::new(false)),
+            reply: reply.into(),
+            responder: None,
+            line: String::new(),
+            pending: VecDeque::new(),
+            timeout: Duration::from_millis(100),
+        }
+    }
+
+    /// Answers every command with what `responder` returns for it, `None` leaves the
+    /// command unanswered.
+    pub fn responding<F>(responder: F) -> Self
+    where
+        F: FnMut(&str) -> Option<String> + Send + 'static,
+    {
+        MockTransport {
+            responder: Some(Box::new(responder)),
+            ..MockTransport::new(STATUS_LINE)
+        }
+    }
+
+    pub fn script(&self, replies: &[Option<&str>]) {
+        let mut script = self.script.lock().unwrap();
+        script.extend(replies.iter().map(|reply| reply.map(String::from)));
+    }
+
+    /// Bytes that are already waiting to be read before the next command is sent.
+    pub fn push_input(&mut self, bytes: &str) {
+        self.pending.extend(bytes.bytes());
+    }
+
+    fn respond(&mut self, line: &str) -> Option<String> {
+        match self.script.lock().unwrap().pop_front() {
+            Some(reply) => reply,
+            None => match &mut self.responder {
+                Some(responder) => responder(line).map(|reply| format!("{}\n", reply)),
+                None => Some(format!("{}\n", self.reply)),
+            },
+        }
+    }
+}
+
+impl Read for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            sleep(self.timeout.min(Duration::from_millis(5)));
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let n = buf.len().min(self.pending.len());
+        for (i, byte) in self.pending.drain(..n).enumerate() {
+            buf[i] = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if *self.fail_writes.lock().unwrap() {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        for &byte in buf {
+            if byte == b'\r' {
+                let reply = self.respond(&self.line.clone());
+                self.written
+                    .lock()
+                    .unwrap()
+                    .push(std::mem::take(&mut self.line));
+                if let Some(reply) = reply {
+                    self.pending.extend(reply.bytes());
+                }
+            } else {
+                self.line.push(byte as char);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "usb")]
+impl Transport for MockTransport {
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), TwoBError> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+/// Serves an emulator to the first client connecting to a loopback port.
+#[cfg(feature = "emulator")]
+pub fn raw_bridge() -> Result<String, TwoBError> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = format!("tcp://{}", listener.local_addr()?);
+    thread::spawn(move || -> Result<(), TwoBError> {
+        let (mut stream, _) = listener.accept()?;
+        Emulator::new()?.serve(&mut stream, &AtomicBool::new(true))
+    });
+    Ok(address)
+}
+
+pub const IAC: u8 = 255;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+pub const WILL: u8 = 251;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const COM_PORT_OPTION: u8 = 44;
+
+/// Minimal RFC 2217 server: acknowledges COM port settings, asks the client for an
+/// unsupported option and sends the emulator's replies through telnet framing.
+#[cfg(feature = "emulator")]
+pub fn rfc2217_bridge(
+    settings: Arc<Mutex<Vec<Vec<u8>>>>,
+    refused: Arc<Mutex<Vec<u8>>>,
+) -> Result<String, TwoBError> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = format!("rfc2217://{}", listener.local_addr()?);
+    thread::spawn(move || -> Result<(), TwoBError> {
+        let (mut stream, _) = listener.accept()?;
+        let mut emulator = Emulator::new()?;
+        // Echo (1) is never requested by the client and must be refused.
+        stream.write_all(&[IAC, DO, 1])?;
+
+        let mut bytes = BufReader::new(stream.try_clone()?).bytes();
+        let mut next = move || -> Result<u8, TwoBError> {
+            match bytes.next() {
+                Some(byte) => Ok(byte?),
+                None => Err(TwoBError::ConnectionError("Closed".into())),
+            }
+        };
+        let mut command = Vec::new();
+        loop {
+            match next()? {
+                IAC => match next()? {
+                    IAC => command.push(IAC),
+                    SB => {
+                        let mut subnegotiation = Vec::new();
+                        loop {
+                            match next()? {
+                                IAC if next()? == SE => break,
+                                byte => subnegotiation.push(byte),
+                            }
+                        }
+                        // Server replies carry the command code plus 100.
+                        let mut reply = vec![IAC, SB, COM_PORT_OPTION, subnegotiation[1] + 100];
+                        reply.extend_from_slice(&subnegotiation[2..]);
+                        reply.extend_from_slice(&[IAC, SE]);
+                        stream.write_all(&reply)?;
+                        settings.lock().unwrap().push(subnegotiation);
+                    }
+                    WILL => {
+                        if next()? == COM_PORT_OPTION {
+                            stream.write_all(&[IAC, DO, COM_PORT_OPTION])?;
+                        }
+                    }
+                    WONT => refused.lock().unwrap().push(next()?),
+                    _ => {
+                        next()?;
+                    }
+                },
+                b'\r' => {
+                    let reply = emulator.handle(&String::from_utf8_lossy(&command));
+                    command.clear();
+                    write_framed(&mut stream, &reply)?;
+                }
+                byte => command.push(byte),
+            }
+        }
+    });
+    Ok(address)
+}
+
+/// Sends a reply split by a telnet command, as a server may interleave notifications.
+#[cfg(feature = "emulator")]
+fn write_framed(stream: &mut TcpStream, reply: &str) -> Result<(), TwoBError> {
+    let (head, tail) = reply.split_at(reply.len() / 2);
+    let mut framed = head.as_bytes().to_vec();
+    framed.extend_from_slice(&[IAC, SB, COM_PORT_OPTION, 107, 0x30, IAC, SE]);
+    framed.extend_from_slice(tail.as_bytes());
+    framed.extend_from_slice(b"\r\0\n");
+    stream.write_all(&framed)?;
+    Ok(())
+}
@@ -0,0 +1,101 @@
+#![cfg(feature = "virtual")]
+
+use estim2b_lib::*;
+use std::time::{Duration, SystemTime};
+
+fn confirmations() -> Confirmations {
+    Confirmations::new(ConfirmationConfig {
+        max_level: Some(50),
+        max_delta: Some(10),
+        expiry: Duration::from_secs(60),
+        history: 2,
+    })
+}
+
+fn state(a: u8, b: u8) -> TwoBState {
+    let mut state = VirtualTwoB::new().unwrap().get_state();
+    state.channel_a = a;
+    state.channel_b = b;
+    state
+}
+
+fn commands(commands: &[TwoBCommand]) -> ProposedChange {
+    ProposedChange::Commands(commands.to_vec())
+}
+
+#[test]
+fn thresholds_decide_what_needs_confirmation() {
+    use TwoBCommand::*;
+    let confirmations = confirmations();
+    let current = state(30, 30);
+    let needs = |change: ProposedChange| confirmations.needs_confirmation(&change, &current);
+
+    assert!(!needs(commands(&[SetChannel(TwoBChannel::A, 40)])));
+    assert!(needs(commands(&[SetChannel(TwoBChannel::A, 41)])));
+    assert!(needs(ProposedChange::State(state(30, 45))));
+    assert!(!needs(ProposedChange::State(state(10, 10))));
+    // Lowering never needs confirmation, whatever the level.
+    assert!(!confirmations
+        .needs_confirmation(&commands(&[SetChannel(TwoBChannel::A, 70)]), &state(80, 0)));
+    // Steps add up, also after dropping to zero.
+    let steps = vec![
+        SetChannel(TwoBChannel::B, 38),
+        IncrementChannel(TwoBChannel::B),
+    ];
+    assert!(!needs(ProposedChange::Commands(steps.clone())));
+    assert!(needs(ProposedChange::Commands(
+        [steps, vec![IncrementChannel(TwoBChannel::B); 2]].concat()
+    )));
+    assert!(needs(commands(&[Kill, SetChannel(TwoBChannel::A, 20)])));
+    // Joining the channels raises B to A.
+    let apart = state(45, 10);
+    assert!(confirmations.needs_confirmation(&commands(&[SetJoinedChannels(true)]), &apart));
+    assert!(!confirmations.needs_confirmation(&commands(&[SetJoinedChannels(false)]), &apart));
+    let mut joined = apart.clone();
+    joined.joined_channels = true;
+    assert!(confirmations.needs_confirmation(&ProposedChange::State(joined), &apart));
+    assert!(!needs(commands(&[
+        SetMode(TwoBMode::Milk),
+        SetChannel(TwoBChannel::C, 99)
+    ])));
+}
+
+#[test]
+fn proposals_are_resolved_once() {
+    let mut confirmations = confirmations();
+    let now = SystemTime::now();
+    let change = commands(&[TwoBCommand::SetChannel(TwoBChannel::A, 90)]);
+    let first = confirmations.propose(change.clone(), "10.0.0.2", now);
+    let second = confirmations.propose(change.clone(), "10.0.0.2", now);
+    assert_ne!(first.id, second.id);
+    assert_eq!(first.expires_at, now + Duration::from_secs(60));
+    assert_eq!(confirmations.pending().len(), 2);
+
+    assert_eq!(
+        confirmations.confirm(first.id, "wearer", now),
+        Some(first.clone())
+    );
+    assert_eq!(confirmations.confirm(first.id, "wearer", now), None);
+    assert_eq!(confirmations.cancel(first.id, "10.0.0.2", now), None);
+
+    let later = now + Duration::from_secs(60);
+    assert_eq!(confirmations.confirm(second.id, "wearer", later), None);
+    assert!(confirmations.pending().is_empty());
+    let third = confirmations.propose(change, "10.0.0.3", later);
+    assert!(confirmations.cancel(third.id, "10.0.0.3", later).is_some());
+
+    // Only the last two outcomes are kept.
+    let history = confirmations.history();
+    assert_eq!(
+        history
+            .iter()
+            .map(|resolved| (resolved.proposal.id, resolved.outcome))
+            .collect::<Vec<_>>(),
+        vec![
+            (second.id, ProposalOutcome::Expired),
+            (third.id, ProposalOutcome::Cancelled)
+        ]
+    );
+    assert_eq!(history[0].resolved_by, None);
+    assert_eq!(history[1].resolved_by.as_deref(), Some("10.0.0.3"));
+}
@@ -0,0 +1,57 @@
+#![cfg(all(feature = "emulator", unix))]
+
+use estim2b_lib::*;
+use serialport::{SerialPortInfo, SerialPortType};
+
+fn port(name: &str) -> SerialPortInfo {
+    SerialPortInfo {
+        port_name: name.into(),
+        port_type: SerialPortType::Unknown,
+    }
+}
+
+#[test]
+fn discover_every_emulated_2b() -> Result<(), TwoBError> {
+    let first = Emulator::with_version("2.106")?.spawn_pty()?;
+    let second = Emulator::new()?.spawn_pty()?;
+    let ports = [
+        port(first.path()),
+        port("/dev/does-not-exist"),
+        port(second.path()),
+    ];
+
+    let found = discover_ports(&ports, &DiscoveryOptions::default());
+    assert_eq!(found.len(), 2);
+    assert_eq!(found[0].port, first.path());
+    assert_eq!(found[0].version, "2.106");
+    assert_eq!(found[1].port, second.path());
+    assert_eq!(found[1].version, "2.122B");
+    assert_eq!(found[1].battery, 1000);
+    assert!(found[1].usb.is_none());
+    Ok(())
+}
+
+#[test]
+fn skipped_ports_are_not_probed() -> Result<(), TwoBError> {
+    let pty = Emulator::new()?.spawn_pty()?;
+    let ports = [port(pty.path())];
+
+    let options = DiscoveryOptions {
+        skip_ports: vec![pty.path().into()],
+        ..DiscoveryOptions::default()
+    };
+    assert!(discover_ports(&ports, &options).is_empty());
+
+    let options = DiscoveryOptions {
+        usb_only: true,
+        ..DiscoveryOptions::default()
+    };
+    assert!(discover_ports(&ports, &options).is_empty());
+
+    let options = DiscoveryOptions {
+        serial_number: Some("A1B2C3".into()),
+        ..DiscoveryOptions::default()
+    };
+    assert!(discover_ports(&ports, &options).is_empty());
+    Ok(())
+}
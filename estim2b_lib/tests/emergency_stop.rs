@@ -0,0 +1,90 @@
+#![cfg(all(feature = "usb", feature = "virtual"))]
+
+mod common;
+
+use common::{refused, MockTransport, STATUS_LINE};
+use estim2b_lib::*;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn raising_levels_is_refused_until_reset() -> Result<(), TwoBError> {
+    let mut twob = TwoBHandle::new(VirtualTwoB::new()?);
+    twob.set_channel(TwoBChannel::A, 30)?;
+    twob.set_channel(TwoBChannel::B, 20)?;
+    twob.engage_emergency_stop(StopSource::Library)?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 0);
+    assert_eq!(
+        twob.cached_state().emergency_stop.unwrap().source,
+        StopSource::Library
+    );
+
+    assert!(refused(twob.set_channel(TwoBChannel::A, 10)));
+    assert!(refused(twob.increment_channel(TwoBChannel::B)));
+    assert!(refused(twob.set_state(TwoBState {
+        channel_b: 5,
+        ..twob.get_state()
+    })));
+    twob.set_mode(TwoBMode::Milk)?;
+    twob.set_channel(TwoBChannel::C, 80)?;
+    assert!(refused(twob.call(|two_b| two_b.get_state())));
+    // Engaging again keeps the first source.
+    twob.engage_emergency_stop(StopSource::Api)?;
+    assert_eq!(twob.emergency_stop().unwrap().source, StopSource::Library);
+
+    assert!(twob.reset_emergency_stop(" ").is_err());
+    assert!(twob.emergency_stop().is_some());
+    twob.reset_emergency_stop("Electrodes checked")?;
+    assert_eq!(twob.emergency_stop(), None);
+    twob.set_channel(TwoBChannel::A, 10)?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 10);
+    Ok(())
+}
+
+#[test]
+fn emergency_stop_preempts_the_queue() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let written = transport.written.clone();
+    let twob = TwoBHandle::with_config(
+        Box::new(USBTwoB::from_transport(transport)?),
+        QueueConfig {
+            min_gap: Duration::from_millis(40),
+        },
+    );
+
+    let submitter = twob.clone();
+    let settings = thread::spawn(move || {
+        submitter.submit(vec![
+            TwoBCommand::SetMode(TwoBMode::Milk),
+            TwoBCommand::SetPower(TwoBPower::HIGH),
+            TwoBCommand::SetChannel(TwoBChannel::A, 50),
+        ])
+    });
+    thread::sleep(Duration::from_millis(20));
+    twob.engage_emergency_stop(StopSource::Signal)?;
+    assert!(settings.join().unwrap().is_err());
+
+    // Nothing queued before the stop is sent after it.
+    let written = written.lock().unwrap();
+    let kill = written.iter().position(|line| line == "K").unwrap();
+    assert!(written[..kill].contains(&TwoBCommand::SetMode(TwoBMode::Milk).encode()));
+    assert!(!written[kill..]
+        .iter()
+        .any(|line| line == "H" || line == "A50"));
+    Ok(())
+}
+
+#[test]
+fn watchdog_engages_the_emergency_stop() -> Result<(), TwoBError> {
+    let twob = TwoBHandle::new(VirtualTwoB::new()?);
+    twob.set_watchdog_config(WatchdogConfig {
+        action: ExpiryAction::EmergencyStop,
+    })?;
+    twob.register_lease("remote", Duration::from_millis(20))?;
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        twob.emergency_stop().map(|stop| stop.source),
+        Some(StopSource::Watchdog)
+    );
+    Ok(())
+}
@@ -0,0 +1,129 @@
+#![cfg(feature = "emulator")]
+
+use estim2b_lib::*;
+use strum::VariantNames;
+
+const FIRMWARE_VERSIONS: [&str; 3] = ["2.106", "2.120B", "2.122B"];
+
+const POWERS: [(TwoBPower, &str, &str); 3] = [
+    (TwoBPower::HIGH, "H", "H"),
+    (TwoBPower::LOW, "L", "L"),
+    (TwoBPower::DYNAMIC, "Y", "D"),
+];
+
+const MODES: [(TwoBMode, &str, &str); 17] = [
+    (TwoBMode::Pulse, "M0", "0"),
+    (TwoBMode::Bounce, "M1", "1"),
+    (TwoBMode::Continuous, "M2", "2"),
+    (TwoBMode::Flo, "M3", "3"),
+    (TwoBMode::ASplit, "M4", "4"),
+    (TwoBMode::BSplit, "M5", "5"),
+    (TwoBMode::Wave, "M6", "6"),
+    (TwoBMode::Waterfall, "M7", "7"),
+    (TwoBMode::Squeeze, "M8", "8"),
+    (TwoBMode::Milk, "M9", "9"),
+    (TwoBMode::Throb, "M10", "10"),
+    (TwoBMode::Thrust, "M11", "11"),
+    (TwoBMode::Cycle, "M12", "12"),
+    (TwoBMode::Twist, "M13", "13"),
+    (TwoBMode::Random, "M14", "14"),
+    (TwoBMode::Step, "M15", "15"),
+    (TwoBMode::Training, "M16", "16"),
+];
+
+const BIASES: [(TwoBBias, &str, &str); 4] = [
+    (TwoBBias::A, "Q0", "0"),
+    (TwoBBias::B, "Q1", "1"),
+    (TwoBBias::AVERAGE, "Q2", "2"),
+    (TwoBBias::MAX, "Q3", "3"),
+];
+
+const MAPS: [(TwoBMap, &str, &str); 3] = [
+    (TwoBMap::A, "O0", "0"),
+    (TwoBMap::B, "O1", "1"),
+    (TwoBMap::C, "O2", "2"),
+];
+
+const RAMPS: [(TwoBRamp, &str, &str); 4] = [
+    (TwoBRamp::X1, "R0", "0"),
+    (TwoBRamp::X2, "R1", "1"),
+    (TwoBRamp::X3, "R2", "2"),
+    (TwoBRamp::X4, "R3", "3"),
+];
+
+const WARPS: [(TwoBWarp, &str, &str); 6] = [
+    (TwoBWarp::X1, "W0", "0"),
+    (TwoBWarp::X2, "W1", "1"),
+    (TwoBWarp::X4, "W2", "2"),
+    (TwoBWarp::X8, "W3", "3"),
+    (TwoBWarp::X16, "W4", "4"),
+    (TwoBWarp::X32, "W5", "5"),
+];
+
+/// Sends every entry of `table` to an emulator running `version`, checks the command
+/// encoding, the token in the reply and the value parsed back from it. Values the
+/// firmware doesn't support must leave the reported value untouched.
+fn check_table<T: Copy + PartialEq + std::fmt::Debug>(
+    version: &str,
+    table: &[(T, &str, &str)],
+    command: fn(T) -> TwoBCommand,
+    field: usize,
+    parsed: fn(&TwoBReply) -> T,
+) -> Result<(), TwoBError> {
+    let mut emulator = Emulator::with_version(version)?;
+    let capabilities = Capabilities::for_version(&version.parse()?);
+    for &(value, wire, token) in table {
+        assert_eq!(command(value).encode(), wire);
+        assert_eq!(TwoBCommand::decode(wire)?, command(value));
+        if capabilities.check(&command(value)).is_err() {
+            let before = emulator.handle("V");
+            assert_eq!(emulator.handle(wire), before, "{} {:?}", version, value);
+            continue;
+        }
+        let line = emulator.handle(wire);
+        assert_eq!(
+            line.split(':').nth(field),
+            Some(token),
+            "{} {:?}",
+            version,
+            value
+        );
+        let reply = TwoBReply::parse(&line)?;
+        assert_eq!(parsed(&reply), value, "{} {:?}", version, value);
+        assert_eq!(reply.version, version);
+        assert_eq!(reply.encode(), line);
+    }
+    Ok(())
+}
+
+#[test]
+fn tables_are_exhaustive() {
+    assert_eq!(POWERS.len(), TwoBPower::VARIANTS.len());
+    assert_eq!(MODES.len(), TwoBMode::VARIANTS.len());
+    assert_eq!(BIASES.len(), TwoBBias::VARIANTS.len());
+    assert_eq!(MAPS.len(), TwoBMap::VARIANTS.len());
+    assert_eq!(RAMPS.len(), TwoBRamp::VARIANTS.len());
+    assert_eq!(WARPS.len(), TwoBWarp::VARIANTS.len());
+}
+
+#[test]
+fn enums_round_trip_per_firmware() -> Result<(), TwoBError> {
+    for version in FIRMWARE_VERSIONS {
+        check_table(version, &POWERS, TwoBCommand::SetPower, 6, |r| r.power)?;
+        check_table(version, &MODES, TwoBCommand::SetMode, 5, |r| r.mode)?;
+        check_table(version, &BIASES, TwoBCommand::SetBias, 7, |r| r.bias)?;
+        check_table(version, &MAPS, TwoBCommand::SetMap, 9, |r| r.map)?;
+        check_table(version, &WARPS, TwoBCommand::SetWarp, 10, |r| r.warp)?;
+        check_table(version, &RAMPS, TwoBCommand::SetRamp, 11, |r| r.ramp)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn dynamic_power_is_not_reported_as_low() -> Result<(), TwoBError> {
+    let pty = Emulator::new()?.spawn_pty()?;
+    let mut twob = USBTwoB::try_from(pty.path())?;
+    twob.set_power(TwoBPower::DYNAMIC)?;
+    assert_eq!(twob.get_power(), TwoBPower::DYNAMIC);
+    Ok(())
+}
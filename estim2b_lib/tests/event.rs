@@ -0,0 +1,92 @@
+#![cfg(all(feature = "usb", feature = "virtual"))]
+
+mod common;
+
+use common::{wait_for, MockTransport, STATUS_LINE};
+use estim2b_lib::*;
+use std::sync::{Arc, Mutex};
+
+fn recorder(twob: &mut dyn TwoB) -> (SubscriptionId, Arc<Mutex<Vec<TwoBChange>>>) {
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let recorded = changes.clone();
+    let id = twob.subscribe(Box::new(move |event| {
+        recorded.lock().unwrap().push(event.change.clone())
+    }));
+    (id, changes)
+}
+
+#[test]
+fn changes_are_reported() -> Result<(), TwoBError> {
+    let mut twob = VirtualTwoB::new()?;
+    let (id, changes) = recorder(&mut twob);
+    twob.set_channel(TwoBChannel::A, 12)?;
+    twob.set_mode(TwoBMode::Milk)?;
+    twob.set_mode(TwoBMode::Milk)?;
+    assert!(twob.set_channel(TwoBChannel::B, 120).is_err());
+    assert_eq!(
+        changes.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![
+            TwoBChange::Level {
+                channel: TwoBChannel::A,
+                old: 0,
+                new: 12
+            },
+            TwoBChange::Mode {
+                old: TwoBMode::Pulse,
+                new: TwoBMode::Milk
+            },
+            TwoBChange::Error {
+                command: Some(TwoBCommand::SetChannel(TwoBChannel::B, 120)),
+                message: "Unsupported(\"Level 120 for channel B is outside 0 to 100\")".into()
+            },
+        ]
+    );
+
+    assert!(twob.unsubscribe(id));
+    assert!(!twob.unsubscribe(id));
+    twob.kill()?;
+    assert!(changes.lock().unwrap().is_empty());
+    Ok(())
+}
+
+#[test]
+fn refresh_reports_changes_made_on_the_device() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let script = transport.script.clone();
+    let mut twob = USBTwoB::from_transport(transport)?;
+    let (_, changes) = recorder(&mut twob);
+    script
+        .lock()
+        .unwrap()
+        .push_back(Some("300:10:12:120:116:15:H:0:0:0:0:0:2.120B\n".into()));
+    twob.refresh_state()?;
+    assert_eq!(
+        *changes.lock().unwrap(),
+        vec![
+            TwoBChange::Power {
+                old: TwoBPower::LOW,
+                new: TwoBPower::HIGH
+            },
+            TwoBChange::Battery { old: 344, new: 300 },
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn handle_forwards_events() -> Result<(), TwoBError> {
+    let mut twob = TwoBHandle::new(VirtualTwoB::new()?);
+    let (_, changes) = recorder(&mut twob);
+    let mut other = twob.clone();
+    other.set_state(TwoBState {
+        warp: TwoBWarp::X4,
+        ..other.get_state()
+    })?;
+    let warp = TwoBChange::Warp {
+        old: TwoBWarp::X1,
+        new: TwoBWarp::X4,
+    };
+    assert!(wait_for(&changes, &warp));
+    assert_eq!(*changes.lock().unwrap(), vec![warp]);
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use estim2b_lib::*;
+
+#[test]
+fn parse_firmware_version() -> Result<(), TwoBError> {
+    assert_eq!(
+        "2.122B".parse::<FirmwareVersion>()?,
+        FirmwareVersion::new(2, 122, Some('B'))
+    );
+    assert_eq!(
+        "2.106".parse::<FirmwareVersion>()?,
+        FirmwareVersion::new(2, 106, None)
+    );
+    assert_eq!(
+        FirmwareVersion::new(2, 120, Some('B')).to_string(),
+        "2.120B"
+    );
+    for invalid in ["", "2", "2.", ".120", "B", "2.x1B", "x.120"] {
+        assert!(invalid.parse::<FirmwareVersion>().is_err(), "{}", invalid);
+    }
+    assert!(FirmwareVersion::new(2, 106, None) < FirmwareVersion::new(2, 120, Some('B')));
+    Ok(())
+}
+
+#[test]
+fn capability_matrix() {
+    let old = Capabilities::for_version(&FirmwareVersion::new(2, 106, None));
+    assert!(!old.power_modes.contains(&TwoBPower::DYNAMIC));
+    assert!(!old.audio_modes && !old.user_modes);
+    assert!(old
+        .check(&TwoBCommand::SetPower(TwoBPower::DYNAMIC))
+        .is_err());
+
+    let new = Capabilities::for_version(&FirmwareVersion::new(2, 122, Some('B')));
+    assert_eq!(new, Capabilities::default());
+    assert!(new.power_modes.contains(&TwoBPower::DYNAMIC));
+    assert!(new.audio_modes && new.user_modes);
+    assert_eq!(new.modes.len(), 17);
+    assert_eq!(new.levels, 0..=100);
+    assert_eq!(
+        Capabilities::for_version(&FirmwareVersion::new(2, 120, Some('B'))),
+        new
+    );
+    assert_eq!(
+        Capabilities::for_version(&FirmwareVersion::new(1, 50, None)),
+        old
+    );
+
+    assert!(new
+        .check(&TwoBCommand::SetChannel(TwoBChannel::A, 100))
+        .is_ok());
+    assert!(new
+        .check(&TwoBCommand::SetChannel(TwoBChannel::A, 101))
+        .is_err());
+}
+
+#[cfg(feature = "virtual")]
+#[test]
+fn devices_reject_unsupported_requests() -> Result<(), TwoBError> {
+    let mut twob = VirtualTwoB::with_version("2.106")?;
+    assert_eq!(
+        twob.get_firmware_version()?,
+        FirmwareVersion::new(2, 106, None)
+    );
+    assert!(matches!(
+        twob.set_power(TwoBPower::DYNAMIC),
+        Err(TwoBError::Unsupported(_))
+    ));
+    assert!(matches!(
+        twob.set_channel(TwoBChannel::A, 150),
+        Err(TwoBError::Unsupported(_))
+    ));
+    assert_eq!(twob.get_power(), TwoBPower::LOW);
+    assert_eq!(twob.get_channel(TwoBChannel::A), 0);
+    Ok(())
+}
+
+#[cfg(all(feature = "emulator", unix))]
+#[test]
+fn usb_rejects_unsupported_requests() -> Result<(), TwoBError> {
+    let pty = Emulator::with_version("2.106")?.spawn_pty()?;
+    let mut twob = USBTwoB::try_from(pty.path())?;
+    assert!(!twob.get_capabilities().audio_modes);
+    assert!(matches!(
+        twob.set_power(TwoBPower::DYNAMIC),
+        Err(TwoBError::Unsupported(_))
+    ));
+    assert!(matches!(
+        twob.set_channel(TwoBChannel::B, 150),
+        Err(TwoBError::Unsupported(_))
+    ));
+    twob.set_power(TwoBPower::HIGH)?;
+    assert_eq!(twob.get_power(), TwoBPower::HIGH);
+    twob.set_channel(TwoBChannel::B, 100)?;
+    assert_eq!(twob.get_channel(TwoBChannel::B), 100);
+    Ok(())
+}
@@ -0,0 +1,126 @@
+#![cfg(all(feature = "usb", feature = "virtual"))]
+
+mod common;
+
+use common::{wait_for, MockTransport, STATUS_LINE};
+use estim2b_lib::*;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn clones_share_one_device() -> Result<(), TwoBError> {
+    let twob = TwoBHandle::new(VirtualTwoB::new()?);
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let mut twob = twob.clone();
+            thread::spawn(move || -> Result<(), TwoBError> {
+                for _ in 0..10 {
+                    twob.increment_channel(TwoBChannel::A)?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap()?;
+    }
+    assert_eq!(twob.get_channel(TwoBChannel::A), 40);
+
+    let mut other = twob.clone();
+    let target = TwoBState {
+        mode: TwoBMode::Milk,
+        channel_b: 30,
+        ..twob.get_state()
+    };
+    other.set_state(target.clone())?;
+    assert_eq!(twob.get_state(), target);
+    assert_eq!(twob.get_version(), "2.122B");
+    Ok(())
+}
+
+#[test]
+fn kill_jumps_the_queue() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let written = transport.written.clone();
+    let twob = TwoBHandle::with_config(
+        Box::new(USBTwoB::from_transport(transport)?),
+        QueueConfig {
+            min_gap: Duration::from_millis(40),
+        },
+    );
+
+    let submitter = twob.clone();
+    let settings = thread::spawn(move || {
+        submitter.submit(vec![
+            TwoBCommand::SetMode(TwoBMode::Milk),
+            TwoBCommand::SetPower(TwoBPower::HIGH),
+            TwoBCommand::SetMap(TwoBMap::B),
+            TwoBCommand::SetRamp(TwoBRamp::X2),
+        ])
+    });
+    thread::sleep(Duration::from_millis(20));
+    twob.submit(vec![TwoBCommand::Kill])?;
+    settings.join().unwrap()?;
+
+    let written = written.lock().unwrap();
+    let kill = written.iter().position(|line| line == "K").unwrap();
+    let ramp = written.iter().position(|line| line == "R1").unwrap();
+    assert!(kill < ramp);
+    assert!(twob.queue_metrics().dispatched >= 5);
+    Ok(())
+}
+
+#[test]
+fn idle_device_is_polled() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let script = transport.script.clone();
+    let mut twob = TwoBHandle::new(USBTwoB::from_transport(transport)?);
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let recorded = changes.clone();
+    twob.subscribe(Box::new(move |event| {
+        recorded.lock().unwrap().push(event.change.clone())
+    }));
+    let before = twob.cached_state().as_of;
+
+    // The power is changed on the device itself.
+    script
+        .lock()
+        .unwrap()
+        .extend((0..50).map(|_| Some("344:10:12:120:116:15:H:0:0:0:0:0:2.120B\n".to_string())));
+    twob.set_poll_interval(Some(Duration::from_millis(20)))?;
+
+    // Waits for the worker to poll instead of guessing how long that takes.
+    let cached = (0..100)
+        .map(|_| {
+            thread::sleep(Duration::from_millis(10));
+            twob.cached_state()
+        })
+        .find(|cached| cached.state.power == TwoBPower::HIGH)
+        .expect("The idle device was never polled");
+    assert!(cached.as_of > before);
+    assert!(wait_for(
+        &changes,
+        &TwoBChange::Power {
+            old: TwoBPower::LOW,
+            new: TwoBPower::HIGH
+        }
+    ));
+    Ok(())
+}
+
+#[test]
+fn listeners_can_use_the_handle() -> Result<(), TwoBError> {
+    let mut twob = TwoBHandle::new(VirtualTwoB::new()?);
+    let mut listener = twob.clone();
+    let (killed, result) = mpsc::channel();
+    twob.subscribe(Box::new(move |event| {
+        if let TwoBChange::Level { new: 40, .. } = event.change {
+            let _ = killed.send(listener.kill());
+        }
+    }));
+    twob.set_channel(TwoBChannel::A, 40)?;
+    result.recv_timeout(Duration::from_secs(1)).unwrap()?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 0);
+    Ok(())
+}
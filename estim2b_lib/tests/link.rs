@@ -0,0 +1,110 @@
+#![cfg(feature = "usb")]
+
+mod common;
+
+use common::{MockTransport, STATUS_LINE};
+use estim2b_lib::*;
+use std::time::Duration;
+
+fn config() -> LinkConfig {
+    LinkConfig {
+        timeout: Duration::from_millis(30),
+        retries: 2,
+        backoff: Duration::from_millis(1),
+        ..LinkConfig::default()
+    }
+}
+
+#[test]
+fn resync_on_garbled_line() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    transport.script(&[Some(
+        "4:10:1\x00\n\n344:10:12:120:116:15:H:0:0:0:0:0:2.120B\n",
+    )]);
+    let twob = USBTwoB::with_config(transport, config())?;
+    assert_eq!(twob.get_power(), TwoBPower::HIGH);
+    Ok(())
+}
+
+#[test]
+fn stale_input_is_discarded() -> Result<(), TwoBError> {
+    let mut transport = MockTransport::new(STATUS_LINE);
+    transport.push_input("344:10:12:120:116:15:H:0:0:0:0:0:2.120B\n");
+    let twob = USBTwoB::with_config(transport, config())?;
+    assert_eq!(twob.get_power(), TwoBPower::LOW);
+    Ok(())
+}
+
+#[test]
+fn timeouts_are_retried() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let written = transport.written.clone();
+    transport.script(&[None, None]);
+    USBTwoB::with_config(transport, config())?;
+    // Dropping it sends `Kill` once.
+    assert_eq!(*written.lock().unwrap(), vec!["V", "V", "V", "K"]);
+    Ok(())
+}
+
+#[test]
+fn timeout_error_after_retries() {
+    let transport = MockTransport::new(STATUS_LINE);
+    let written = transport.written.clone();
+    transport.script(&[None, None, None]);
+    let result = USBTwoB::with_config(transport, config());
+    assert!(matches!(result, Err(TwoBError::Timeout(_))));
+    assert_eq!(written.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn parser_error_after_retries() {
+    let transport = MockTransport::new(STATUS_LINE);
+    transport.script(&[Some("garbage\n"), Some("garbage\n"), Some("garbage\n")]);
+    let result = USBTwoB::with_config(transport, config());
+    assert!(matches!(result, Err(TwoBError::ParserError(_))));
+}
+
+#[test]
+fn increments_are_not_retried() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let written = transport.written.clone();
+    let script = transport.script.clone();
+    let mut twob = USBTwoB::with_config(transport, config())?;
+    script.lock().unwrap().push_back(None);
+    assert!(matches!(
+        twob.increment_channel(TwoBChannel::A),
+        Err(TwoBError::Timeout(_))
+    ));
+    assert_eq!(*written.lock().unwrap(), vec!["V", "A+"]);
+    Ok(())
+}
+
+#[test]
+fn write_failure_is_an_error() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let fail_writes = transport.fail_writes.clone();
+    let mut twob = USBTwoB::with_config(transport, config())?;
+    *fail_writes.lock().unwrap() = true;
+    assert!(matches!(twob.kill(), Err(TwoBError::ConnectionError(_))));
+    Ok(())
+}
+
+#[test]
+fn per_command_timeouts() {
+    let config = LinkConfig {
+        timeout: Duration::from_millis(10),
+        command_timeouts: vec![(
+            TwoBCommand::SetMode(TwoBMode::Pulse),
+            Duration::from_secs(1),
+        )],
+        ..LinkConfig::default()
+    };
+    assert_eq!(
+        config.timeout_for(&TwoBCommand::SetMode(TwoBMode::Milk)),
+        Duration::from_secs(1)
+    );
+    assert_eq!(
+        config.timeout_for(&TwoBCommand::Kill),
+        Duration::from_millis(10)
+    );
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use common::{raw_bridge, rfc2217_bridge, COM_PORT_OPTION};
+use estim2b_lib::*;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn raw_tcp_bridge() -> Result<(), TwoBError> {
+    let mut twob = USBTwoB::try_from(raw_bridge()?.as_str())?;
+    twob.set_mode(TwoBMode::Milk)?;
+    twob.set_channel(TwoBChannel::A, 12)?;
+    twob.refresh_state()?;
+    assert_eq!(twob.get_mode(), TwoBMode::Milk);
+    assert_eq!(twob.get_channel(TwoBChannel::A), 12);
+    Ok(())
+}
+
+#[test]
+fn rfc2217_bridge_negotiates_line_settings() -> Result<(), TwoBError> {
+    let settings = Arc::new(Mutex::new(Vec::new()));
+    let refused = Arc::new(Mutex::new(Vec::new()));
+    let address = rfc2217_bridge(settings.clone(), refused.clone())?;
+
+    let mut twob = USBTwoB::try_from(address.as_str())?;
+    twob.set_power(TwoBPower::HIGH)?;
+    twob.set_channel(TwoBChannel::B, 30)?;
+    twob.refresh_state()?;
+    assert_eq!(twob.get_power(), TwoBPower::HIGH);
+    assert_eq!(twob.get_channel(TwoBChannel::B), 30);
+
+    let settings = settings.lock().unwrap();
+    assert!(settings.contains(&vec![COM_PORT_OPTION, 1, 0, 0, 0x25, 0x80]));
+    assert!(settings.contains(&vec![COM_PORT_OPTION, 2, 8]));
+    assert!(settings.contains(&vec![COM_PORT_OPTION, 3, 1]));
+    assert_eq!(*refused.lock().unwrap(), vec![1]);
+    Ok(())
+}
+
+#[test]
+fn unknown_scheme_is_rejected() {
+    assert!(matches!(
+        open_transport("udp://127.0.0.1:1"),
+        Err(TwoBError::ConnectionError(_))
+    ));
+}
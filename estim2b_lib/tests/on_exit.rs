@@ -0,0 +1,122 @@
+#![cfg(feature = "usb")]
+
+mod common;
+
+use common::{MockTransport, STATUS_LINE};
+use estim2b_lib::*;
+use std::env;
+use std::panic;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+fn device(on_exit: OnExit) -> (USBTwoB, Arc<Mutex<Vec<String>>>) {
+    let transport = MockTransport::new(STATUS_LINE);
+    let written = transport.written.clone();
+    let mut two_b = USBTwoB::from_transport(transport).unwrap();
+    two_b.set_on_exit(on_exit);
+    (two_b, written)
+}
+
+fn last(written: &Arc<Mutex<Vec<String>>>) -> Option<String> {
+    written.lock().unwrap().last().cloned()
+}
+
+#[test]
+fn drop_sends_the_on_exit_command() {
+    for (on_exit, command) in [(OnExit::Kill, "K"), (OnExit::Reset, "E")] {
+        let (mut two_b, written) = device(on_exit);
+        two_b.set_channel(TwoBChannel::A, 40).unwrap();
+        drop(two_b);
+        assert_eq!(last(&written).as_deref(), Some(command));
+    }
+
+    let (mut two_b, written) = device(OnExit::Leave);
+    two_b.set_channel(TwoBChannel::A, 40).unwrap();
+    drop(two_b);
+    assert_eq!(last(&written).as_deref(), Some("A40"));
+}
+
+#[test]
+fn drop_does_not_retry() {
+    let transport = MockTransport::new(STATUS_LINE);
+    let written = transport.written.clone();
+    let script = transport.script.clone();
+    let two_b = USBTwoB::from_transport(transport).unwrap();
+    // Neither `K` nor a retry of it would be answered.
+    script.lock().unwrap().extend([None, None, None]);
+    drop(two_b);
+    assert_eq!(*written.lock().unwrap(), vec!["V", "K"]);
+}
+
+#[test]
+fn panicking_owner_kills() {
+    let (two_b, written) = device(OnExit::default());
+    let owner = thread::spawn(move || {
+        let mut two_b = two_b;
+        two_b.set_channel(TwoBChannel::A, 40).unwrap();
+        panic!("Controller crashed");
+    });
+    assert!(owner.join().is_err());
+    assert_eq!(last(&written).as_deref(), Some("K"));
+}
+
+#[test]
+fn dropping_the_last_handle_kills() -> Result<(), TwoBError> {
+    let (two_b, written) = device(OnExit::Kill);
+    let mut handle = TwoBHandle::new(two_b);
+    let other = handle.clone();
+    handle.set_channel(TwoBChannel::A, 40)?;
+    drop(other);
+    assert_eq!(last(&written).as_deref(), Some("A40"));
+    // Returns only once the worker dropped the device.
+    drop(handle);
+    assert_eq!(last(&written).as_deref(), Some("K"));
+    Ok(())
+}
+
+#[test]
+fn shutdown_kills() -> Result<(), TwoBError> {
+    let (two_b, written) = device(OnExit::Kill);
+    let mut handle = TwoBHandle::new(two_b);
+    handle.set_channel(TwoBChannel::A, 40)?;
+    handle.shutdown()?;
+    assert_eq!(last(&written).as_deref(), Some("K"));
+    assert!(handle.set_channel(TwoBChannel::A, 40).is_err());
+    Ok(())
+}
+
+/// Runs in a child process, as the panic hook is installed for every thread.
+#[test]
+fn panic_hook_kills_and_keeps_the_device() {
+    if env::var_os("ESTIM2B_PANIC_HOOK").is_some() {
+        let (two_b, written) = device(OnExit::Kill);
+        let mut handle = TwoBHandle::new(two_b);
+        handle.set_channel(TwoBChannel::A, 40).unwrap();
+        panic::set_hook(Box::new(|_| {}));
+        handle.exit_on_panic();
+        assert!(thread::spawn(|| panic!("Request failed")).join().is_err());
+        // The panic was caught, so the device goes on.
+        handle.set_channel(TwoBChannel::A, 10).unwrap();
+        // The hook doesn't keep the worker running.
+        drop(handle);
+        println!("Written: {:?}", written.lock().unwrap());
+        return;
+    }
+    let output = Command::new(env::current_exe().unwrap())
+        .args([
+            "panic_hook_kills_and_keeps_the_device",
+            "--exact",
+            "--nocapture",
+        ])
+        .env("ESTIM2B_PANIC_HOOK", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(r#"Written: ["V", "A40", "K", "A10", "K"]"#),
+        "{}",
+        stdout
+    );
+}
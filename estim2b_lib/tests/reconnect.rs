@@ -0,0 +1,133 @@
+#![cfg(all(feature = "emulator", unix))]
+
+mod common;
+
+use common::{MockTransport, STATUS_LINE};
+use estim2b_lib::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Opener serving a fresh emulator on every call, like a 2B that was power cycled.
+fn emulator_opener(current: Arc<Mutex<Option<EmulatorPty>>>) -> Opener {
+    Box::new(move || {
+        let pty = Emulator::new()?.spawn_pty()?;
+        let io = open_serial(pty.path())?;
+        *current.lock().unwrap() = Some(pty);
+        Ok(io)
+    })
+}
+
+#[test]
+fn reconnect_restores_state_with_safe_levels() -> Result<(), TwoBError> {
+    let current = Arc::new(Mutex::new(None));
+    let mut twob = USBTwoB::connect(emulator_opener(current.clone()), LinkConfig::default())?;
+    twob.set_reconnect_config(ReconnectConfig {
+        delay: Duration::from_millis(10),
+        safe_level: 3,
+        ..ReconnectConfig::default()
+    });
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    {
+        let changes = changes.clone();
+        twob.on_connection_change(Box::new(move |state| changes.lock().unwrap().push(state)));
+    }
+
+    twob.set_mode(TwoBMode::Milk)?;
+    twob.set_power(TwoBPower::HIGH)?;
+    twob.set_channel(TwoBChannel::A, 40)?;
+    twob.set_channel(TwoBChannel::B, 2)?;
+    twob.set_channel(TwoBChannel::C, 30)?;
+
+    // Unplug: the emulator behind the current pty goes away.
+    current.lock().unwrap().take();
+
+    twob.refresh_state()?;
+    assert_eq!(twob.connection_state(), ConnectionState::Connected);
+    let state = twob.get_state();
+    assert_eq!(state.mode, TwoBMode::Milk);
+    assert_eq!(state.power, TwoBPower::HIGH);
+    assert_eq!(state.channel_a, 3);
+    assert_eq!(state.channel_b, 2);
+    assert_eq!(state.channel_c, 30);
+    assert_eq!(
+        *changes.lock().unwrap(),
+        vec![
+            ConnectionState::Disconnected,
+            ConnectionState::Reconnecting,
+            ConnectionState::Connected
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn level_changes_are_not_repeated_after_reconnect() -> Result<(), TwoBError> {
+    let current = Arc::new(Mutex::new(None));
+    let mut twob = USBTwoB::connect(emulator_opener(current.clone()), LinkConfig::default())?;
+    twob.set_reconnect_config(ReconnectConfig {
+        restore_state: false,
+        ..ReconnectConfig::default()
+    });
+    current.lock().unwrap().take();
+
+    assert!(twob.increment_channel(TwoBChannel::A).is_err());
+    assert_eq!(twob.connection_state(), ConnectionState::Connected);
+    assert_eq!(twob.get_channel(TwoBChannel::A), 0);
+    twob.increment_channel(TwoBChannel::A)?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 1);
+    Ok(())
+}
+
+#[test]
+fn failed_reconnect_reports_disconnected() -> Result<(), TwoBError> {
+    let current = Arc::new(Mutex::new(None));
+    let mut opener = emulator_opener(current.clone());
+    let io = opener()?;
+    let attempts = Arc::new(Mutex::new(0));
+    let opener: Opener = {
+        let attempts = attempts.clone();
+        let mut io = Some(io);
+        Box::new(move || {
+            *attempts.lock().unwrap() += 1;
+            io.take()
+                .ok_or_else(|| TwoBError::ConnectionError("2B is gone".into()))
+        })
+    };
+    let mut twob = USBTwoB::connect(opener, LinkConfig::default())?;
+    twob.set_reconnect_config(ReconnectConfig {
+        attempts: 2,
+        delay: Duration::from_millis(1),
+        ..ReconnectConfig::default()
+    });
+    current.lock().unwrap().take();
+
+    assert!(matches!(twob.kill(), Err(TwoBError::ConnectionError(_))));
+    assert_eq!(twob.connection_state(), ConnectionState::Disconnected);
+    assert_eq!(*attempts.lock().unwrap(), 3);
+    Ok(())
+}
+
+#[test]
+fn timeouts_keep_the_connection() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let script = transport.script.clone();
+    let mut io = Some(transport);
+    let opens = Arc::new(Mutex::new(0));
+    let opener: Opener = {
+        let opens = opens.clone();
+        Box::new(move || {
+            *opens.lock().unwrap() += 1;
+            io.take()
+                .map(|io| Box::new(io) as Box<dyn Transport>)
+                .ok_or_else(|| TwoBError::ConnectionError("Reopened".into()))
+        })
+    };
+    let mut twob = USBTwoB::connect(opener, LinkConfig::default())?;
+    twob.set_channel(TwoBChannel::A, 10)?;
+    script.lock().unwrap().extend([None, None, None]);
+
+    assert!(matches!(twob.refresh_state(), Err(TwoBError::Timeout(_))));
+    assert_eq!(*opens.lock().unwrap(), 1);
+    assert_eq!(twob.connection_state(), ConnectionState::Connected);
+    Ok(())
+}
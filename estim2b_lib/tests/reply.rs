@@ -0,0 +1,94 @@
+use estim2b_lib::*;
+use proptest::prelude::*;
+
+fn reply() -> impl Strategy<Value = TwoBReply> {
+    let end: u8 = TwoBMode::Training.into();
+    (
+        any::<u16>(),
+        [0u16..=510, 0u16..=510, 0u16..=510, 0u16..=510],
+        0..=end,
+        prop_oneof![
+            Just(TwoBPower::HIGH),
+            Just(TwoBPower::LOW),
+            Just(TwoBPower::DYNAMIC)
+        ],
+        0u8..4,
+        any::<bool>(),
+        0u8..3,
+        0u8..6,
+        0u8..4,
+        "[0-9]\\.[0-9]{3}[A-Z]?",
+    )
+        .prop_map(
+            |(
+                battery,
+                raw_channels,
+                mode,
+                power,
+                bias,
+                joined_channels,
+                map,
+                warp,
+                ramp,
+                version,
+            )| {
+                TwoBReply {
+                    battery,
+                    raw_channels,
+                    mode: TwoBMode::try_from(mode).unwrap(),
+                    power,
+                    bias: TwoBBias::try_from(bias).unwrap(),
+                    joined_channels,
+                    map: TwoBMap::try_from(map).unwrap(),
+                    warp: TwoBWarp::try_from(warp).unwrap(),
+                    ramp: TwoBRamp::try_from(ramp).unwrap(),
+                    version,
+                }
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn reply_round_trip(reply in reply()) {
+        prop_assert_eq!(TwoBReply::parse(&reply.encode()).unwrap(), reply);
+    }
+
+    #[test]
+    fn state_round_trip(reply in reply()) {
+        let state = reply.state();
+        let encoded = TwoBReply::from_state(&state, &reply.version).encode();
+        prop_assert_eq!(TwoBReply::parse(&encoded).unwrap().state(), state);
+    }
+}
+
+#[test]
+fn parse_reply() -> Result<(), ReplyError> {
+    let reply = TwoBReply::parse("344:11:12:120:116:15:L:0:1:2:5:3:2.120B\r\n")?;
+    assert_eq!(reply.raw_channels, [11, 12, 120, 116]);
+    assert_eq!(reply.version, "2.120B");
+    assert_eq!(reply.state().channel_a, 5);
+    assert!(reply.state().joined_channels);
+    assert_eq!(reply.encode(), "344:11:12:120:116:15:L:0:1:2:5:3:2.120B");
+    Ok(())
+}
+
+#[test]
+fn reply_errors_name_field_and_position() {
+    let error = |line: &str| TwoBReply::parse(line).unwrap_err();
+
+    let e = error("344:10:12:120:116:17:L:0:0:0:0:0:2.120B");
+    assert_eq!((e.field.as_str(), e.position), ("mode", 18));
+    let e = error("344:10:1x:120:116:15:L:0:0:0:0:0:2.120B");
+    assert_eq!((e.field.as_str(), e.position), ("channel_b", 7));
+    let e = error("344:10:12:120:116:15:X:0:0:0:0:0:2.120B");
+    assert_eq!((e.field.as_str(), e.position), ("power", 21));
+    let e = error("344:10:12:120:116:15:L:0:2:0:0:0:2.120B");
+    assert_eq!((e.field.as_str(), e.position), ("joined_channels", 25));
+    let e = error("344:10:12:120:116:15:L:0:0:0:0:0");
+    assert_eq!((e.field.as_str(), e.position), ("version", 32));
+    let e = error("344:10:12:120:116:15:L:0:0:0:0:0:2.120B:1");
+    assert_eq!((e.field.as_str(), e.position), ("reply", 40));
+    let e = error("");
+    assert_eq!(e.field, "battery");
+}
@@ -0,0 +1,124 @@
+#![cfg(feature = "virtual")]
+
+use estim2b_lib::*;
+
+fn policy(enforcement: Enforcement) -> SafetyPolicy {
+    SafetyPolicy {
+        enforcement,
+        max_levels: ChannelLimits {
+            a: Some(40),
+            b: Some(60),
+            ..ChannelLimits::default()
+        },
+        mode_limits: vec![ModeLimit {
+            mode: TwoBMode::Milk,
+            max_level: 20,
+        }],
+        allowed_modes: vec![TwoBMode::Pulse, TwoBMode::Milk, TwoBMode::Throb],
+        forbidden_powers: vec![TwoBPower::HIGH],
+    }
+}
+
+fn violation<T>(result: Result<T, TwoBError>) -> bool {
+    matches!(result, Err(TwoBError::PolicyViolation(_)))
+}
+
+#[test]
+fn violations_are_rejected() -> Result<(), TwoBError> {
+    let mut twob = SafeTwoB::new(VirtualTwoB::new()?, policy(Enforcement::Reject));
+    assert!(violation(twob.set_channel(TwoBChannel::A, 41)));
+    assert!(violation(twob.set_mode(TwoBMode::Wave)));
+    assert!(violation(twob.set_power(TwoBPower::HIGH)));
+    assert!(violation(twob.set_state(TwoBState {
+        channel_b: 99,
+        ..twob.get_state()
+    })));
+    assert_eq!(twob.get_state(), VirtualTwoB::new()?.get_state());
+
+    twob.set_channel(TwoBChannel::A, 40)?;
+    assert!(violation(twob.increment_channel(TwoBChannel::A)));
+    // Milk allows at most 20 on channel A and B.
+    assert!(violation(twob.set_mode(TwoBMode::Milk)));
+    twob.set_channel(TwoBChannel::A, 20)?;
+    twob.set_mode(TwoBMode::Milk)?;
+    assert!(violation(twob.set_channel(TwoBChannel::B, 21)));
+    twob.set_power(TwoBPower::DYNAMIC)?;
+    Ok(())
+}
+
+#[test]
+fn violations_are_clamped() -> Result<(), TwoBError> {
+    let mut twob = SafeTwoB::new(VirtualTwoB::new()?, policy(Enforcement::Clamp));
+    twob.set_channel(TwoBChannel::A, 99)?;
+    twob.increment_channel(TwoBChannel::A)?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 40);
+
+    twob.set_channel(TwoBChannel::B, 50)?;
+    twob.set_joined_channels(true)?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 40);
+    twob.set_joined_channels(false)?;
+    twob.set_mode(TwoBMode::Milk)?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 20);
+    assert_eq!(twob.get_channel(TwoBChannel::B), 20);
+
+    twob.set_state(TwoBState {
+        mode: TwoBMode::Throb,
+        channel_a: 80,
+        channel_b: 80,
+        ..twob.get_state()
+    })?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 40);
+    assert_eq!(twob.get_channel(TwoBChannel::B), 60);
+    // Modes and power settings can't be clamped.
+    assert!(violation(twob.set_mode(TwoBMode::Wave)));
+    assert!(violation(twob.set_power(TwoBPower::HIGH)));
+    Ok(())
+}
+
+#[test]
+fn policy_from_json() -> Result<(), TwoBError> {
+    let policy = SafetyPolicy::from_json(
+        r#"{
+            "enforcement": "Clamp",
+            "max_levels": { "a": 40, "b": 60 },
+            "mode_limits": [{ "mode": "Milk", "max_level": 20 }],
+            "allowed_modes": ["Pulse", "Milk", "Throb"],
+            "forbidden_powers": ["HIGH"]
+        }"#,
+    )?;
+    assert_eq!(policy, self::policy(Enforcement::Clamp));
+    assert_eq!(SafetyPolicy::from_json("{}")?, SafetyPolicy::default());
+    assert!(SafetyPolicy::from_json(r#"{ "max_levels": { "a": -1 } }"#).is_err());
+
+    let mut device = VirtualTwoB::new()?;
+    device.set_channel(TwoBChannel::A, 70)?;
+    let mut twob = SafeTwoB::new(device, policy);
+    twob.enforce()?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 40);
+    Ok(())
+}
+
+#[test]
+fn reset_is_brought_within_the_policy() -> Result<(), TwoBError> {
+    let policy = SafetyPolicy {
+        max_levels: ChannelLimits {
+            c: Some(30),
+            ..ChannelLimits::default()
+        },
+        allowed_modes: vec![TwoBMode::Milk, TwoBMode::Throb],
+        forbidden_powers: vec![TwoBPower::LOW],
+        ..policy(Enforcement::Reject)
+    };
+    let mut twob = SafeTwoB::new(VirtualTwoB::new()?, policy);
+    twob.enforce()?;
+    let enforced = twob.get_state();
+    assert_eq!(enforced.mode, TwoBMode::Milk);
+    assert_eq!(enforced.power, TwoBPower::HIGH);
+    assert_eq!(enforced.channel_c, 30);
+
+    twob.set_mode(TwoBMode::Throb)?;
+    // The defaults a reset restores are Pulse, LOW and 50 on channel C.
+    twob.reset()?;
+    assert_eq!(twob.get_state(), enforced);
+    Ok(())
+}
@@ -0,0 +1,142 @@
+#![cfg(feature = "virtual")]
+
+use estim2b_lib::*;
+use std::time::{Duration, Instant};
+
+fn drain(queue: &mut CommandQueue, twob: &VirtualTwoB) -> Vec<TwoBCommand> {
+    let mut commands = Vec::new();
+    while let Some(command) = queue.pop(&twob.get_state(), &twob.get_capabilities()) {
+        commands.push(command);
+    }
+    commands
+}
+
+#[test]
+fn increments_are_coalesced() -> Result<(), TwoBError> {
+    let mut twob = VirtualTwoB::new()?;
+    twob.set_channel(TwoBChannel::A, 5)?;
+    let mut queue = CommandQueue::new(QueueConfig::default());
+    for _ in 0..10 {
+        queue.push(TwoBCommand::IncrementChannel(TwoBChannel::A));
+    }
+    queue.push(TwoBCommand::IncrementChannel(TwoBChannel::B));
+    assert_eq!(queue.len(), 2);
+    assert_eq!(
+        drain(&mut queue, &twob),
+        vec![
+            TwoBCommand::SetChannel(TwoBChannel::A, 15),
+            TwoBCommand::IncrementChannel(TwoBChannel::B),
+        ]
+    );
+
+    queue.push(TwoBCommand::SetChannel(TwoBChannel::C, 90));
+    for _ in 0..20 {
+        queue.push(TwoBCommand::IncrementChannel(TwoBChannel::C));
+    }
+    queue.push(TwoBCommand::IncrementChannel(TwoBChannel::D));
+    queue.push(TwoBCommand::DecrementChannel(TwoBChannel::D));
+    assert_eq!(
+        drain(&mut queue, &twob),
+        vec![TwoBCommand::SetChannel(TwoBChannel::C, 100)]
+    );
+    Ok(())
+}
+
+#[test]
+fn latest_setting_wins() -> Result<(), TwoBError> {
+    let twob = VirtualTwoB::new()?;
+    let mut queue = CommandQueue::new(QueueConfig::default());
+    queue.push(TwoBCommand::SetMode(TwoBMode::Milk));
+    queue.push(TwoBCommand::SetPower(TwoBPower::HIGH));
+    queue.push(TwoBCommand::SetMode(TwoBMode::Throb));
+    queue.push(TwoBCommand::SetChannel(TwoBChannel::A, 20));
+    queue.push(TwoBCommand::SetChannel(TwoBChannel::A, 10));
+    assert_eq!(
+        drain(&mut queue, &twob),
+        vec![
+            TwoBCommand::SetPower(TwoBPower::HIGH),
+            TwoBCommand::SetMode(TwoBMode::Throb),
+            TwoBCommand::SetChannel(TwoBChannel::A, 10),
+        ]
+    );
+    let metrics = queue.metrics();
+    assert_eq!(metrics.depth, 0);
+    assert_eq!(metrics.submitted, 5);
+    assert_eq!(metrics.coalesced, 2);
+    assert_eq!(metrics.dispatched, 3);
+    Ok(())
+}
+
+#[test]
+fn kill_jumps_the_queue() -> Result<(), TwoBError> {
+    let twob = VirtualTwoB::new()?;
+    let mut queue = CommandQueue::new(QueueConfig::default());
+    queue.push(TwoBCommand::SetMode(TwoBMode::Milk));
+    queue.push(TwoBCommand::IncrementChannel(TwoBChannel::A));
+    queue.push(TwoBCommand::SetChannel(TwoBChannel::C, 30));
+    queue.push(TwoBCommand::Kill);
+    assert_eq!(
+        drain(&mut queue, &twob),
+        vec![
+            TwoBCommand::Kill,
+            TwoBCommand::SetMode(TwoBMode::Milk),
+            TwoBCommand::SetChannel(TwoBChannel::C, 30),
+        ]
+    );
+
+    queue.push(TwoBCommand::SetMode(TwoBMode::Milk));
+    queue.push(TwoBCommand::Reset);
+    queue.push(TwoBCommand::SetMode(TwoBMode::Flo));
+    assert_eq!(
+        drain(&mut queue, &twob),
+        vec![TwoBCommand::Reset, TwoBCommand::SetMode(TwoBMode::Flo)]
+    );
+    Ok(())
+}
+
+#[test]
+fn pushed_front_commands_overtake_the_queue() -> Result<(), TwoBError> {
+    let twob = VirtualTwoB::new()?;
+    let mut queue = CommandQueue::new(QueueConfig::default());
+    queue.push(TwoBCommand::SetMode(TwoBMode::Milk));
+    queue.push(TwoBCommand::SetChannel(TwoBChannel::A, 30));
+    queue.push(TwoBCommand::SetChannel(TwoBChannel::C, 30));
+    queue.push_front(&[
+        TwoBCommand::SetChannel(TwoBChannel::A, 0),
+        TwoBCommand::SetChannel(TwoBChannel::B, 0),
+    ]);
+    assert_eq!(
+        drain(&mut queue, &twob),
+        vec![
+            TwoBCommand::SetChannel(TwoBChannel::A, 0),
+            TwoBCommand::SetChannel(TwoBChannel::B, 0),
+            TwoBCommand::SetMode(TwoBMode::Milk),
+            TwoBCommand::SetChannel(TwoBChannel::C, 30),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn commands_are_paced() -> Result<(), TwoBError> {
+    let mut twob = VirtualTwoB::new()?;
+    let min_gap = Duration::from_millis(30);
+    let mut queue = CommandQueue::new(QueueConfig { min_gap });
+    queue.push(TwoBCommand::SetMode(TwoBMode::Milk));
+    queue.push(TwoBCommand::SetChannel(TwoBChannel::A, 3));
+    queue.push(TwoBCommand::SetPower(TwoBPower::HIGH));
+
+    let start = Instant::now();
+    queue.run(&mut twob)?;
+    assert!(start.elapsed() >= min_gap * 2);
+    assert_eq!(twob.get_mode(), TwoBMode::Milk);
+    assert_eq!(twob.get_channel(TwoBChannel::A), 3);
+    assert_eq!(twob.get_power(), TwoBPower::HIGH);
+
+    let metrics = queue.metrics();
+    assert_eq!(metrics.dispatched, 3);
+    assert!(metrics.max_latency >= min_gap * 2);
+    assert!(metrics.mean_latency <= metrics.max_latency);
+    assert!(queue.wait_time() <= min_gap);
+    Ok(())
+}
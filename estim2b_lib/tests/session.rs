@@ -0,0 +1,205 @@
+#![cfg(feature = "virtual")]
+
+mod common;
+
+use common::{refused, wait_for, ManualClock};
+use estim2b_lib::*;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+fn levels(a: u8, b: u8) -> TwoBState {
+    let mut state = VirtualTwoB::new().unwrap().get_state();
+    state.channel_a = a;
+    state.channel_b = b;
+    state
+}
+
+fn config() -> SessionConfig {
+    SessionConfig {
+        max_active: secs(100),
+        cooldown: secs(60),
+        warnings: vec![secs(30)],
+        ramp_down: secs(10),
+    }
+}
+
+#[test]
+fn limit_ramps_down_kills_and_cools_down() {
+    let mut governor = SessionGovernor::new(config());
+    let start = Instant::now();
+    let at = |s: u64| start + secs(s);
+    assert_eq!(governor.update(&levels(0, 0), at(0)), (vec![], vec![]));
+    assert_eq!(governor.wait_time(at(0)), None);
+
+    governor.update(&levels(40, 20), at(0));
+    assert_eq!(governor.wait_time(at(0)), Some(secs(70)));
+    let (changes, _) = governor.update(&levels(40, 20), at(70));
+    assert_eq!(
+        changes,
+        vec![TwoBChange::SessionWarning {
+            remaining: secs(30)
+        }]
+    );
+    assert!(governor
+        .check(
+            &TwoBCommand::SetChannel(TwoBChannel::A, 99),
+            &levels(40, 20)
+        )
+        .is_ok());
+
+    // Half way through the ramp down, levels are capped at half.
+    governor.update(&levels(40, 20), at(90));
+    let (_, commands) = governor.update(&levels(40, 20), at(95));
+    assert_eq!(
+        commands,
+        vec![
+            TwoBCommand::SetChannel(TwoBChannel::A, 20),
+            TwoBCommand::SetChannel(TwoBChannel::B, 10)
+        ]
+    );
+    assert!(governor
+        .check(
+            &TwoBCommand::SetChannel(TwoBChannel::A, 30),
+            &levels(20, 10)
+        )
+        .is_err());
+
+    let (changes, commands) = governor.update(&levels(20, 10), at(100));
+    assert_eq!(
+        changes,
+        vec![TwoBChange::SessionLimitReached { cooldown: secs(60) }]
+    );
+    assert_eq!(commands, vec![TwoBCommand::Kill]);
+    governor.update(&levels(0, 0), at(100));
+    let status = governor.status(at(130));
+    assert_eq!(status.remaining, Duration::ZERO);
+    assert_eq!(status.cooldown_remaining, Some(secs(30)));
+    assert!(refused(governor.check(
+        &TwoBCommand::IncrementChannel(TwoBChannel::B),
+        &levels(0, 0)
+    )));
+    assert!(governor.check_state(&levels(0, 0), &levels(0, 0)).is_ok());
+
+    let (changes, _) = governor.update(&levels(0, 0), at(160));
+    assert_eq!(changes, vec![TwoBChange::CooldownEnded]);
+    assert_eq!(governor.status(at(160)).remaining, secs(100));
+    assert!(governor
+        .check(&TwoBCommand::SetChannel(TwoBChannel::A, 99), &levels(0, 0))
+        .is_ok());
+}
+
+#[test]
+fn warnings_report_the_time_left() {
+    let mut governor = SessionGovernor::new(config());
+    let start = Instant::now();
+    governor.update(&levels(40, 20), start);
+    let (changes, _) = governor.update(&levels(40, 20), start + secs(75));
+    assert_eq!(
+        changes,
+        vec![TwoBChange::SessionWarning {
+            remaining: secs(25)
+        }]
+    );
+}
+
+#[test]
+fn joining_and_maximum_levels_are_checked() {
+    let mut governor = SessionGovernor::new(config());
+    let start = Instant::now();
+    governor.update(&levels(40, 20), start);
+    governor.update(&levels(40, 20), start + secs(95));
+    // Capped at 20 and 10 by now, joining would take B to 40.
+    assert!(refused(
+        governor.check(&TwoBCommand::SetJoinedChannels(true), &levels(20, 10))
+    ));
+    assert!(governor
+        .check(&TwoBCommand::SetJoinedChannels(true), &levels(10, 10))
+        .is_ok());
+    let joined = TwoBState {
+        joined_channels: true,
+        ..levels(20, 0)
+    };
+    assert!(refused(governor.check_state(&joined, &levels(20, 10))));
+    assert!(governor
+        .check(
+            &TwoBCommand::IncrementChannel(TwoBChannel::A),
+            &levels(u8::MAX, 0)
+        )
+        .is_ok());
+}
+
+#[test]
+fn pauses_as_long_as_the_cooldown_start_a_new_session() {
+    let mut governor = SessionGovernor::new(config());
+    let start = Instant::now();
+    let at = |s: u64| start + secs(s);
+    governor.update(&levels(10, 0), at(0));
+    governor.update(&levels(0, 0), at(40));
+    // A short pause keeps the time counted so far.
+    governor.update(&levels(0, 10), at(50));
+    assert_eq!(governor.status(at(60)).active_time, secs(50));
+    governor.update(&levels(0, 0), at(60));
+    assert_eq!(governor.wait_time(at(60)), Some(secs(60)));
+    governor.update(&levels(0, 0), at(120));
+    assert_eq!(governor.status(at(120)).active_time, Duration::ZERO);
+}
+
+#[test]
+fn handle_enforces_the_limit() -> Result<(), TwoBError> {
+    let mut twob = TwoBHandle::new(VirtualTwoB::new()?);
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let recorded = changes.clone();
+    twob.subscribe(Box::new(move |event| {
+        recorded.lock().unwrap().push(event.change.clone())
+    }));
+    twob.set_session_config(Some(SessionConfig {
+        max_active: Duration::from_millis(100),
+        cooldown: Duration::from_millis(300),
+        warnings: Vec::new(),
+        ramp_down: Duration::from_millis(50),
+    }))?;
+    twob.set_channel(TwoBChannel::A, 40)?;
+    assert!(twob.cached_state().session.unwrap().active);
+
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(twob.get_channel(TwoBChannel::A), 0);
+    assert!(wait_for(
+        &changes,
+        &TwoBChange::SessionLimitReached {
+            cooldown: Duration::from_millis(300)
+        }
+    ));
+    assert!(twob.session_status().unwrap().cooldown_remaining.is_some());
+    assert!(refused(twob.set_channel(TwoBChannel::A, 10)));
+    twob.set_channel(TwoBChannel::C, 80)?;
+
+    thread::sleep(Duration::from_millis(300));
+    assert!(wait_for(&changes, &TwoBChange::CooldownEnded));
+    twob.set_channel(TwoBChannel::A, 10)?;
+    assert_eq!(twob.get_channel(TwoBChannel::A), 10);
+    Ok(())
+}
+
+#[test]
+fn status_follows_the_clock_of_the_handle() -> Result<(), TwoBError> {
+    let clock = ManualClock::default();
+    let mut twob = TwoBHandle::with_clock(
+        Box::new(VirtualTwoB::new()?),
+        QueueConfig::default(),
+        clock.clock(),
+    );
+    twob.set_session_config(Some(config()))?;
+    twob.set_channel(TwoBChannel::A, 40)?;
+    assert_eq!(twob.session_status().unwrap().remaining, secs(100));
+
+    clock.advance(secs(20));
+    let status = twob.session_status().unwrap();
+    assert_eq!(status.active_time, secs(20));
+    assert_eq!(status.remaining, secs(80));
+    Ok(())
+}
@@ -0,0 +1,147 @@
+#![cfg(feature = "virtual")]
+
+mod common;
+
+use common::{wait_until, ManualClock};
+use estim2b_lib::*;
+use std::time::{Duration, Instant};
+
+fn limiter() -> SlewLimiter {
+    SlewLimiter::new(SlewConfig {
+        max_rate: Some(2.0),
+    })
+}
+
+#[test]
+fn rises_are_ramped() {
+    let mut slew = limiter();
+    let mut state = VirtualTwoB::new().unwrap().get_state();
+    state.channel_a = 10;
+    let start = Instant::now();
+
+    assert_eq!(
+        slew.plan(TwoBCommand::SetChannel(TwoBChannel::A, 12), &state),
+        None
+    );
+    assert_eq!(slew.wait_time(start), Some(Duration::ZERO));
+    assert_eq!(
+        slew.next(&state, start),
+        Some(TwoBCommand::SetChannel(TwoBChannel::A, 11))
+    );
+    state.channel_a = 11;
+    assert_eq!(slew.wait_time(start), Some(Duration::from_millis(500)));
+    assert_eq!(slew.next(&state, start), None);
+    assert_eq!(
+        slew.ramps(&state),
+        vec![Ramp {
+            channel: TwoBChannel::A,
+            target: 12,
+            level: 11
+        }]
+    );
+    let later = start + Duration::from_millis(500);
+    assert_eq!(
+        slew.next(&state, later),
+        Some(TwoBCommand::SetChannel(TwoBChannel::A, 12))
+    );
+    state.channel_a = 12;
+    assert_eq!(slew.next(&state, later + Duration::from_secs(1)), None);
+    assert_eq!(slew.wait_time(later), None);
+
+    // Decreases are immediate and other channels are left alone.
+    for command in [
+        TwoBCommand::SetChannel(TwoBChannel::A, 5),
+        TwoBCommand::DecrementChannel(TwoBChannel::B),
+        TwoBCommand::SetChannel(TwoBChannel::C, 90),
+    ] {
+        assert_eq!(slew.plan(command, &state), Some(command));
+    }
+    assert_eq!(
+        slew.plan(TwoBCommand::IncrementChannel(TwoBChannel::B), &state),
+        None
+    );
+    assert_eq!(
+        slew.plan(TwoBCommand::Kill, &state),
+        Some(TwoBCommand::Kill)
+    );
+    assert!(!slew.is_ramping());
+}
+
+#[test]
+fn set_state_is_ramped() {
+    let mut slew = limiter();
+    let current = VirtualTwoB::new().unwrap().get_state();
+    let target = TwoBState {
+        mode: TwoBMode::Milk,
+        channel_a: 30,
+        channel_c: 20,
+        ..current.clone()
+    };
+    let planned = slew.plan_state(target, &current);
+    assert_eq!(planned.mode, TwoBMode::Milk);
+    assert_eq!(planned.channel_a, current.channel_a);
+    assert_eq!(planned.channel_c, 20);
+    assert_eq!(slew.ramps(&planned).len(), 1);
+}
+
+#[test]
+fn handle_ramps_until_killed() -> Result<(), TwoBError> {
+    let clock = ManualClock::default();
+    let mut twob = TwoBHandle::with_clock(
+        Box::new(VirtualTwoB::new()?),
+        QueueConfig::default(),
+        clock.clock(),
+    );
+    twob.set_slew_config(SlewConfig {
+        max_rate: Some(50.0),
+    })?;
+    twob.set_channel(TwoBChannel::A, 3)?;
+    // Every further step waits for the clock to move on by 20 ms.
+    for level in 1..=3 {
+        assert!(wait_until(|| twob.get_channel(TwoBChannel::A) == level));
+        clock.advance(Duration::from_millis(20));
+    }
+    assert!(wait_until(|| twob.ramps().is_empty()));
+
+    twob.set_state(TwoBState {
+        channel_b: 80,
+        ..twob.get_state()
+    })?;
+    assert!(wait_until(|| twob.get_channel(TwoBChannel::B) == 1));
+    assert_eq!(
+        twob.ramps(),
+        vec![Ramp {
+            channel: TwoBChannel::B,
+            target: 80,
+            level: 1
+        }]
+    );
+
+    twob.kill()?;
+    assert!(twob.ramps().is_empty());
+    assert_eq!(twob.get_channel(TwoBChannel::B), 0);
+    Ok(())
+}
+
+#[test]
+fn increments_at_the_maximum_stay_a_no_op() {
+    let mut slew = limiter();
+    let mut state = VirtualTwoB::new().unwrap().get_state();
+    state.channel_a = TwoBCommand::MAX_LEVEL;
+    let increment = TwoBCommand::IncrementChannel(TwoBChannel::A);
+    assert_eq!(slew.plan(increment, &state), Some(increment));
+    assert!(!slew.is_ramping());
+
+    // Once a ramp is on its way to the maximum, the increment is dropped.
+    state.channel_a = 99;
+    assert_eq!(slew.plan(increment, &state), None);
+    assert_eq!(slew.plan(increment, &state), None);
+    assert_eq!(
+        slew.ramps(&state),
+        vec![Ramp {
+            channel: TwoBChannel::A,
+            target: TwoBCommand::MAX_LEVEL,
+            level: 99
+        }]
+    );
+}
@@ -31,6 +31,13 @@ fn usb_cycle_modes() -> Result<(), TwoBError> {
     cycle_modes(USBTwoB::new()?)
 }
 
+#[cfg(all(feature = "emulator", unix))]
+#[test]
+fn emulator_cycle_modes() -> Result<(), TwoBError> {
+    let pty = Emulator::new()?.spawn_pty()?;
+    cycle_modes(USBTwoB::try_from(pty.path())?)
+}
+
 #[cfg(feature = "virtual")]
 #[test]
 fn virtual_cycle_modes() -> Result<(), TwoBError> {
@@ -55,6 +62,13 @@ fn usb_state_changing() -> Result<(), TwoBError> {
     state_changing(USBTwoB::new()?)
 }
 
+#[cfg(all(feature = "emulator", unix))]
+#[test]
+fn emulator_state_changing() -> Result<(), TwoBError> {
+    let pty = Emulator::new()?.spawn_pty()?;
+    state_changing(USBTwoB::try_from(pty.path())?)
+}
+
 #[cfg(feature = "virtual")]
 #[test]
 fn virtual_state_changing() -> Result<(), TwoBError> {
@@ -0,0 +1,185 @@
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use common::MockTransport;
+use estim2b_lib::*;
+use std::time::Duration;
+
+enum Fault {
+    /// The command is lost on the way to the device.
+    Drop,
+    /// The device answers but doesn't apply the command.
+    Ignore,
+}
+
+/// Emulator that misbehaves on selected commands.
+fn connect(faults: Vec<(TwoBCommand, Fault)>) -> Result<USBTwoB, TwoBError> {
+    let mut emulator = Emulator::new()?;
+    let io = MockTransport::responding(move |line| {
+        let fault = faults
+            .iter()
+            .find(|(command, _)| command.encode() == line)
+            .map(|(_, fault)| fault);
+        match fault {
+            Some(Fault::Drop) => None,
+            Some(Fault::Ignore) => Some(emulator.handle("")),
+            None => Some(emulator.handle(line)),
+        }
+    });
+    let config = LinkConfig {
+        timeout: Duration::from_millis(20),
+        retries: 0,
+        ..LinkConfig::default()
+    };
+    USBTwoB::with_config(io, config)
+}
+
+fn initial_state() -> Result<TwoBState, TwoBError> {
+    Ok(VirtualTwoB::new()?.get_state())
+}
+
+#[test]
+fn plan_lowers_levels_first_and_raises_them_last() -> Result<(), TwoBError> {
+    let from = TwoBState {
+        channel_a: 40,
+        channel_b: 10,
+        ..initial_state()?
+    };
+    let to = TwoBState {
+        channel_a: 20,
+        channel_b: 60,
+        mode: TwoBMode::Milk,
+        power: TwoBPower::HIGH,
+        ..from.clone()
+    };
+    assert_eq!(
+        plan(&from, &to),
+        vec![
+            TwoBCommand::SetChannel(TwoBChannel::A, 20),
+            TwoBCommand::SetMode(TwoBMode::Milk),
+            TwoBCommand::SetPower(TwoBPower::HIGH),
+            TwoBCommand::SetChannel(TwoBChannel::B, 60),
+        ]
+    );
+    assert!(plan(&from, &from).is_empty());
+    assert_eq!(
+        differing_fields(&from, &to),
+        vec!["mode", "channel_a", "channel_b", "power"]
+    );
+    Ok(())
+}
+
+#[test]
+fn set_state_is_verified() -> Result<(), TwoBError> {
+    let mut twob = connect(Vec::new())?;
+    let target = TwoBState {
+        channel_a: 12,
+        channel_d: 70,
+        mode: TwoBMode::Throb,
+        warp: TwoBWarp::X4,
+        ..twob.get_state()
+    };
+    twob.set_state(target.clone())?;
+    assert_eq!(twob.get_state(), target);
+    Ok(())
+}
+
+#[test]
+fn failed_command_is_rolled_back() -> Result<(), TwoBError> {
+    let mut twob = connect(vec![(
+        TwoBCommand::SetChannel(TwoBChannel::B, 30),
+        Fault::Drop,
+    )])?;
+    twob.set_channel(TwoBChannel::A, 20)?;
+    let before = twob.get_state();
+
+    let target = TwoBState {
+        channel_a: 25,
+        channel_b: 30,
+        mode: TwoBMode::Milk,
+        ..before.clone()
+    };
+    let report = match twob.set_state(target) {
+        Err(TwoBError::TransactionFailed(report)) => report,
+        other => panic!("Unexpected result {:?}", other),
+    };
+    assert_eq!(
+        report.applied,
+        vec![
+            TwoBCommand::SetMode(TwoBMode::Milk),
+            TwoBCommand::SetChannel(TwoBChannel::A, 25)
+        ]
+    );
+    assert_eq!(
+        report.failed.map(|failed| failed.command),
+        Some(TwoBCommand::SetChannel(TwoBChannel::B, 30))
+    );
+    assert!(report.rolled_back);
+    assert_eq!(twob.get_state(), before);
+    Ok(())
+}
+
+#[test]
+fn rollback_never_raises_levels() -> Result<(), TwoBError> {
+    let mut twob = connect(vec![(TwoBCommand::SetMode(TwoBMode::Milk), Fault::Drop)])?;
+    twob.set_channel(TwoBChannel::A, 20)?;
+    let before = twob.get_state();
+
+    let target = TwoBState {
+        channel_a: 5,
+        channel_b: 30,
+        mode: TwoBMode::Milk,
+        ..before.clone()
+    };
+    let report = match twob.set_state(target) {
+        Err(TwoBError::TransactionFailed(report)) => report,
+        other => panic!("Unexpected result {:?}", other),
+    };
+    assert_eq!(
+        report.applied,
+        vec![TwoBCommand::SetChannel(TwoBChannel::A, 5)]
+    );
+    assert!(report.rolled_back);
+    // A stays at the level the transaction lowered it to.
+    assert_eq!(
+        twob.get_state(),
+        TwoBState {
+            channel_a: 5,
+            ..before
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn mismatch_is_reported_without_rollback() -> Result<(), TwoBError> {
+    let mut twob = connect(vec![(
+        TwoBCommand::SetPower(TwoBPower::HIGH),
+        Fault::Ignore,
+    )])?;
+    twob.set_on_failure(OnFailure::Report);
+
+    let target = TwoBState {
+        channel_a: 8,
+        power: TwoBPower::HIGH,
+        ..twob.get_state()
+    };
+    let report = match twob.set_state(target) {
+        Err(TwoBError::TransactionFailed(report)) => report,
+        other => panic!("Unexpected result {:?}", other),
+    };
+    assert_eq!(
+        report.applied,
+        vec![
+            TwoBCommand::SetPower(TwoBPower::HIGH),
+            TwoBCommand::SetChannel(TwoBChannel::A, 8),
+        ]
+    );
+    assert_eq!(report.failed, None);
+    assert_eq!(report.mismatched, vec!["power"]);
+    assert!(!report.rolled_back);
+    assert_eq!(twob.get_channel(TwoBChannel::A), 8);
+    assert_eq!(twob.get_power(), TwoBPower::LOW);
+    Ok(())
+}
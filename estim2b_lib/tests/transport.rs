@@ -0,0 +1,28 @@
+#![cfg(feature = "usb")]
+
+mod common;
+
+use common::{MockTransport, STATUS_LINE};
+use estim2b_lib::*;
+
+#[test]
+fn usb_over_mock_transport() -> Result<(), TwoBError> {
+    let transport = MockTransport::new(STATUS_LINE);
+    let written = transport.written.clone();
+    let mut twob = USBTwoB::from_transport(transport)?;
+
+    assert_eq!(twob.get_version(), "2.120B");
+    assert_eq!(twob.get_channel(TwoBChannel::A), 5);
+
+    twob.set_mode(TwoBMode::Flo)?;
+    twob.set_channel(TwoBChannel::B, 42)?;
+    twob.increment_channel(TwoBChannel::A)?;
+    twob.set_joined_channels(true)?;
+    twob.kill()?;
+
+    assert_eq!(
+        *written.lock().unwrap(),
+        vec!["V", "M3", "B42", "A+", "J1", "K"]
+    );
+    Ok(())
+}
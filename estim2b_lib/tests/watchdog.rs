@@ -0,0 +1,156 @@
+#![cfg(feature = "virtual")]
+
+mod common;
+
+use common::{wait_for, wait_until, ManualClock};
+use estim2b_lib::*;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn last_expired_lease_trips() {
+    let mut watchdog = Watchdog::default();
+    let start = Instant::now();
+    assert_eq!(watchdog.wait_time(start), None);
+    let short = watchdog.register("short".into(), Duration::from_millis(10), start);
+    let long = watchdog.register("long".into(), Duration::from_secs(60), start);
+
+    let later = start + Duration::from_millis(20);
+    let (expired, tripped) = watchdog.expire(later);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].id, short);
+    assert!(!tripped);
+    assert!(!watchdog.heartbeat(short, later));
+    assert!(watchdog.heartbeat(long, later));
+
+    let (expired, tripped) = watchdog.expire(later + Duration::from_secs(60));
+    assert_eq!(expired[0].id, long);
+    assert!(tripped);
+    assert!(watchdog.leases().is_empty());
+
+    // Released leases never trip the watchdog.
+    let lease = watchdog.register("released".into(), Duration::ZERO, later);
+    assert!(watchdog.release(lease));
+    assert_eq!(watchdog.expire(later), (Vec::new(), false));
+}
+
+#[test]
+fn expiry_actions() {
+    let mut state = VirtualTwoB::new().unwrap().get_state();
+    state.channel_a = 50;
+    state.channel_b = 5;
+    assert_eq!(ExpiryAction::Kill.commands(&state), vec![TwoBCommand::Kill]);
+    assert_eq!(
+        ExpiryAction::SafeLevels { a: 10, b: 10 }.commands(&state),
+        vec![
+            TwoBCommand::SetChannel(TwoBChannel::A, 10),
+            TwoBCommand::SetChannel(TwoBChannel::B, 5)
+        ]
+    );
+}
+
+#[test]
+fn handle_is_killed_without_heartbeats() -> Result<(), TwoBError> {
+    let mut twob = TwoBHandle::new(VirtualTwoB::new()?);
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let recorded = changes.clone();
+    twob.subscribe(Box::new(move |event| {
+        recorded.lock().unwrap().push(event.change.clone())
+    }));
+    twob.set_channel(TwoBChannel::A, 40)?;
+    let lease = twob.register_lease("remote", Duration::from_millis(100))?;
+    assert_eq!(twob.leases()[0].name, "remote");
+
+    for _ in 0..5 {
+        thread::sleep(Duration::from_millis(40));
+        assert!(twob.heartbeat(lease)?);
+    }
+    assert_eq!(twob.get_channel(TwoBChannel::A), 40);
+
+    thread::sleep(Duration::from_millis(200));
+    assert!(!twob.heartbeat(lease)?);
+    assert!(twob.leases().is_empty());
+    assert_eq!(twob.get_channel(TwoBChannel::A), 0);
+    assert!(wait_for(
+        &changes,
+        &TwoBChange::LeaseExpired {
+            lease,
+            name: "remote".into()
+        }
+    ));
+    assert!(wait_for(
+        &changes,
+        &TwoBChange::WatchdogTripped {
+            action: ExpiryAction::Kill
+        }
+    ));
+    Ok(())
+}
+
+#[test]
+fn handle_is_lowered_to_safe_levels() -> Result<(), TwoBError> {
+    let mut twob = TwoBHandle::new(VirtualTwoB::new()?);
+    twob.set_watchdog_config(WatchdogConfig {
+        action: ExpiryAction::SafeLevels { a: 20, b: 20 },
+    })?;
+    twob.set_channel(TwoBChannel::A, 40)?;
+    twob.set_channel(TwoBChannel::B, 10)?;
+    twob.set_mode(TwoBMode::Milk)?;
+    twob.register_lease("remote", Duration::from_millis(20))?;
+    thread::sleep(Duration::from_millis(150));
+    let state = twob.get_state();
+    assert_eq!(state.channel_a, 20);
+    assert_eq!(state.channel_b, 10);
+    assert_eq!(state.mode, TwoBMode::Milk);
+    Ok(())
+}
+
+#[test]
+fn expiry_overtakes_pending_commands() -> Result<(), TwoBError> {
+    let clock = ManualClock::default();
+    let mut twob = TwoBHandle::with_clock(
+        Box::new(VirtualTwoB::new()?),
+        QueueConfig {
+            min_gap: Duration::from_millis(100),
+        },
+        clock.clock(),
+    );
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let recorded = changes.clone();
+    twob.subscribe(Box::new(move |event| {
+        recorded.lock().unwrap().push(event.change.clone())
+    }));
+    twob.set_watchdog_config(WatchdogConfig {
+        action: ExpiryAction::SafeLevels { a: 0, b: 0 },
+    })?;
+    twob.set_channel(TwoBChannel::A, 40)?;
+    twob.register_lease("remote", Duration::from_secs(1))?;
+
+    let submitter = twob.clone();
+    let pending = thread::spawn(move || {
+        submitter.submit(vec![
+            TwoBCommand::SetChannel(TwoBChannel::C, 10),
+            TwoBCommand::SetChannel(TwoBChannel::D, 10),
+            TwoBCommand::SetMode(TwoBMode::Milk),
+        ])
+    });
+    assert!(wait_until(|| twob.queue_metrics().submitted >= 4));
+    clock.advance(Duration::from_secs(2));
+    pending.join().unwrap()?;
+
+    let lowered = TwoBChange::Level {
+        channel: TwoBChannel::A,
+        old: 40,
+        new: 0,
+    };
+    let milk = TwoBChange::Mode {
+        old: TwoBMode::Pulse,
+        new: TwoBMode::Milk,
+    };
+    assert!(wait_for(&changes, &milk));
+    let changes = changes.lock().unwrap();
+    let position = |change| changes.iter().position(|c| c == change).unwrap();
+    assert!(position(&lowered) < position(&milk));
+    Ok(())
+}
@@ -1,30 +1,159 @@
 use estim2b_lib::*;
-use rocket::{get, post, launch, routes, serde::json::Json, State};
+use rocket::fairing::AdHoc;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError, Sender};
+use rocket::{get, launch, post, routes, serde::json::Json, Build, Rocket, Shutdown, State};
+use std::ops::Deref;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// The 2B, with commands audited as coming from the client of the request: its address
+/// and the fingerprint of the `X-Client-Token` header it sent, if any.
+struct Caller(TwoBHandle);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Caller {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let two_b = request.rocket().state::<TwoBHandle>().unwrap();
+        request::Outcome::Success(Caller(
+            two_b.with_origin(Origin::Http {
+                address: request.client_ip().map(|ip| ip.to_string()),
+                token: request
+                    .headers()
+                    .get_one("X-Client-Token")
+                    .map(token_fingerprint),
+            }),
+        ))
+    }
+}
+
+impl Deref for Caller {
+    type Target = TwoBHandle;
+
+    fn deref(&self) -> &TwoBHandle {
+        &self.0
+    }
+}
+
+/// The `X-Wearer-Token` header of the request, if it sent one.
+struct WearerToken(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WearerToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(WearerToken(
+            request
+                .headers()
+                .get_one("X-Wearer-Token")
+                .map(String::from),
+        ))
+    }
+}
+
+/// Changes waiting for the wearer, who confirms them with `--wearer-token`.
+struct Proposals {
+    confirmations: Mutex<Confirmations>,
+    wearer_token: Option<String>,
+}
+
+impl Default for Proposals {
+    fn default() -> Self {
+        Proposals {
+            confirmations: Mutex::new(Confirmations::new(ConfirmationConfig::default())),
+            wearer_token: None,
+        }
+    }
+}
+
+impl Proposals {
+    fn lock(&self) -> MutexGuard<'_, Confirmations> {
+        self.confirmations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Holds `change` as a proposal if it raises levels beyond the thresholds.
+    fn hold(
+        &self,
+        two_b: &TwoBHandle,
+        change: ProposedChange,
+    ) -> Result<ProposedChange, TwoBError> {
+        let mut confirmations = self.lock();
+        if !confirmations.needs_confirmation(&change, &two_b.state()) {
+            return Ok(change);
+        }
+        let proposal = confirmations.propose(change, &client_name(two_b), SystemTime::now());
+        Err(TwoBError::ConfirmationRequired(proposal))
+    }
+}
+
+/// The client of `two_b` as recorded in proposals: its address and the fingerprint of
+/// its `X-Client-Token`, if it sent one.
+fn client_name(two_b: &TwoBHandle) -> String {
+    match two_b.origin() {
+        Origin::Http { address, token } => {
+            let address = address.as_deref().unwrap_or("unknown");
+            match token {
+                Some(token) => format!("{} ({})", address, token),
+                None => address.into(),
+            }
+        }
+        origin => format!("{:?}", origin),
+    }
+}
+
+async fn apply(two_b: &TwoBHandle, change: ProposedChange) -> Result<(), TwoBError> {
+    match change {
+        ProposedChange::Commands(commands) => two_b.submit_async(commands).await,
+        ProposedChange::State(state) => two_b.set_state_async(state).await,
+    }
+}
+
+/// Applies `change` unless the wearer has to confirm it first.
+async fn submit_change(
+    two_b: &TwoBHandle,
+    proposals: &Proposals,
+    change: ProposedChange,
+) -> Result<(), TwoBError> {
+    let change = proposals.hold(two_b, change)?;
+    apply(two_b, change).await
+}
 
 #[get("/refresh_state")]
-fn refresh_state(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<Result<(), TwoBError>> {
-    two_b.lock().unwrap().refresh_state().into()
+async fn refresh_state(two_b: Caller) -> Json<Result<(), TwoBError>> {
+    two_b
+        .submit_async(vec![TwoBCommand::RefreshState])
+        .await
+        .into()
 }
 
 #[get("/reset")]
-fn reset(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<Result<(), TwoBError>> {
-    two_b.lock().unwrap().reset().into()
+async fn reset(two_b: Caller) -> Json<Result<(), TwoBError>> {
+    two_b.submit_async(vec![TwoBCommand::Reset]).await.into()
 }
 
 #[get("/kill")]
-fn kill(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<Result<(), TwoBError>> {
-    two_b.lock().unwrap().kill().into()
+async fn kill(two_b: Caller) -> Json<Result<(), TwoBError>> {
+    two_b.submit_async(vec![TwoBCommand::Kill]).await.into()
 }
 
 #[get("/set_joined_channels?<enable>")]
-fn set_joined_channels(
-    two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>,
+async fn set_joined_channels(
+    two_b: Caller,
+    proposals: &State<Proposals>,
     enable: &str,
 ) -> Json<Result<(), TwoBError>> {
     if let Ok(enable) = bool::from_str(enable) {
-        two_b.lock().unwrap().set_joined_channels(enable).into()
+        let change = ProposedChange::Commands(vec![TwoBCommand::SetJoinedChannels(enable)]);
+        submit_change(&two_b, proposals, change).await.into()
     } else {
         Json(Err(TwoBError::ParserError(
             "'enable' has to be true or false!".into(),
@@ -33,189 +162,510 @@ fn set_joined_channels(
 }
 
 #[get("/set_mode?<mode>")]
-fn set_mode(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>, mode: &str) -> Json<Result<(), TwoBError>> {
+async fn set_mode(two_b: Caller, mode: &str) -> Json<Result<(), TwoBError>> {
     if let Ok(mode) = TwoBMode::from_str(mode) {
-        two_b.lock().unwrap().set_mode(mode).into()
+        two_b
+            .submit_async(vec![TwoBCommand::SetMode(mode)])
+            .await
+            .into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid mode!".into())))
     }
 }
 
 #[get("/set_power?<power>")]
-fn set_power(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>, power: &str) -> Json<Result<(), TwoBError>> {
+async fn set_power(two_b: Caller, power: &str) -> Json<Result<(), TwoBError>> {
     if let Ok(power) = TwoBPower::from_str(power) {
-        two_b.lock().unwrap().set_power(power).into()
+        two_b
+            .submit_async(vec![TwoBCommand::SetPower(power)])
+            .await
+            .into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid power!".into())))
     }
 }
 
 #[get("/set_map?<map>")]
-fn set_map(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>, map: &str) -> Json<Result<(), TwoBError>> {
+async fn set_map(two_b: Caller, map: &str) -> Json<Result<(), TwoBError>> {
     if let Ok(map) = TwoBMap::from_str(map) {
-        two_b.lock().unwrap().set_map(map).into()
+        two_b
+            .submit_async(vec![TwoBCommand::SetMap(map)])
+            .await
+            .into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid map!".into())))
     }
 }
 
 #[get("/set_bias?<bias>")]
-fn set_bias(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>, bias: &str) -> Json<Result<(), TwoBError>> {
+async fn set_bias(two_b: Caller, bias: &str) -> Json<Result<(), TwoBError>> {
     if let Ok(bias) = TwoBBias::from_str(bias) {
-        two_b.lock().unwrap().set_bias(bias).into()
+        two_b
+            .submit_async(vec![TwoBCommand::SetBias(bias)])
+            .await
+            .into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid bias!".into())))
     }
 }
 
 #[get("/set_ramp?<ramp>")]
-fn set_ramp(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>, ramp: &str) -> Json<Result<(), TwoBError>> {
+async fn set_ramp(two_b: Caller, ramp: &str) -> Json<Result<(), TwoBError>> {
     if let Ok(ramp) = TwoBRamp::from_str(ramp) {
-        two_b.lock().unwrap().set_ramp(ramp).into()
+        two_b
+            .submit_async(vec![TwoBCommand::SetRamp(ramp)])
+            .await
+            .into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid ramp!".into())))
     }
 }
 
 #[get("/set_warp?<warp>")]
-fn set_warp(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>, warp: &str) -> Json<Result<(), TwoBError>> {
+async fn set_warp(two_b: Caller, warp: &str) -> Json<Result<(), TwoBError>> {
     if let Ok(warp) = TwoBWarp::from_str(warp) {
-        two_b.lock().unwrap().set_warp(warp).into()
+        two_b
+            .submit_async(vec![TwoBCommand::SetWarp(warp)])
+            .await
+            .into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid warp!".into())))
     }
 }
 
 #[get("/increment_channel?<id>")]
-fn increment_channel(
-    two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>,
+async fn increment_channel(
+    two_b: Caller,
+    proposals: &State<Proposals>,
     id: &str,
 ) -> Json<Result<(), TwoBError>> {
     if let Ok(channel) = TwoBChannel::from_str(id) {
-        two_b.lock().unwrap().increment_channel(channel).into()
+        let change = ProposedChange::Commands(vec![TwoBCommand::IncrementChannel(channel)]);
+        submit_change(&two_b, proposals, change).await.into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid Channel ID!".into())))
     }
 }
 
 #[get("/decrement_channel?<id>")]
-fn decrement_channel(
-    two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>,
-    id: &str,
-) -> Json<Result<(), TwoBError>> {
+async fn decrement_channel(two_b: Caller, id: &str) -> Json<Result<(), TwoBError>> {
     if let Ok(channel) = TwoBChannel::from_str(id) {
-        two_b.lock().unwrap().decrement_channel(channel).into()
+        two_b
+            .submit_async(vec![TwoBCommand::DecrementChannel(channel)])
+            .await
+            .into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid Channel ID!".into())))
     }
 }
 
 #[get("/set_channel?<id>&<value>")]
-fn set_channel(
-    two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>,
+async fn set_channel(
+    two_b: Caller,
+    proposals: &State<Proposals>,
     id: &str,
     value: u8,
 ) -> Json<Result<(), TwoBError>> {
     if let Ok(channel) = TwoBChannel::from_str(id) {
-        two_b.lock().unwrap().set_channel(channel, value).into()
+        let change = ProposedChange::Commands(vec![TwoBCommand::SetChannel(channel, value)]);
+        submit_change(&two_b, proposals, change).await.into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid Channel ID!".into())))
     }
 }
 
 #[post("/set_state", data = "<state>")]
-fn set_state(
-    two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>,
+async fn set_state(
+    two_b: Caller,
+    proposals: &State<Proposals>,
     state: Json<TwoBState>,
 ) -> Json<Result<(), TwoBError>> {
-    two_b.lock().unwrap().set_state(state.into_inner()).into()
+    let change = ProposedChange::State(state.into_inner());
+    submit_change(&two_b, proposals, change).await.into()
+}
+
+#[post("/execute", data = "<commands>")]
+async fn execute(
+    two_b: Caller,
+    proposals: &State<Proposals>,
+    commands: Json<Vec<TwoBCommand>>,
+) -> Json<Result<(), TwoBError>> {
+    let commands = commands.into_inner();
+    let capabilities = two_b.capabilities();
+    if let Err(e) = commands
+        .iter()
+        .try_for_each(|command| capabilities.check(command))
+    {
+        return Json(Err(e));
+    }
+    submit_change(&two_b, proposals, ProposedChange::Commands(commands))
+        .await
+        .into()
+}
+
+#[get("/get_queue_metrics")]
+async fn get_queue_metrics(two_b: &State<TwoBHandle>) -> Json<QueueMetrics> {
+    two_b.queue_metrics().into()
+}
+
+/// The state of the 2B together with the emergency stop, if it is engaged, and the
+/// session, if it is limited.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ReportedState {
+    #[serde(flatten)]
+    state: TwoBState,
+    emergency_stop: Option<EmergencyStop>,
+    session: Option<SessionStatus>,
 }
 
 #[get("/")]
-fn get_state(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<TwoBState> {
-    two_b.lock().unwrap().get_state().into()
+async fn get_state(two_b: &State<TwoBHandle>) -> Json<ReportedState> {
+    ReportedState {
+        state: two_b.state(),
+        emergency_stop: two_b.emergency_stop(),
+        session: two_b.session_status(),
+    }
+    .into()
+}
+
+/// The state with the time the 2B last confirmed it.
+#[get("/get_cached_state")]
+async fn get_cached_state(two_b: &State<TwoBHandle>) -> Json<CachedState> {
+    two_b.cached_state().into()
+}
+
+/// Level rises of channel A and B the 2B is still ramping up.
+#[get("/get_ramps")]
+async fn get_ramps(two_b: &State<TwoBHandle>) -> Json<Vec<Ramp>> {
+    two_b.ramps().into()
+}
+
+/// Registers a watchdog lease that has to be kept alive with `/api/heartbeat`.
+#[get("/register_lease?<name>&<timeout_ms>")]
+async fn register_lease(
+    two_b: &State<TwoBHandle>,
+    name: &str,
+    timeout_ms: u64,
+) -> Json<Result<LeaseId, TwoBError>> {
+    two_b
+        .register_lease(name, Duration::from_millis(timeout_ms))
+        .into()
+}
+
+/// Returns whether the lease was still alive.
+#[get("/heartbeat?<lease>")]
+async fn heartbeat(two_b: &State<TwoBHandle>, lease: u64) -> Json<Result<bool, TwoBError>> {
+    two_b.heartbeat(LeaseId(lease)).into()
+}
+
+#[get("/release_lease?<lease>")]
+async fn release_lease(two_b: &State<TwoBHandle>, lease: u64) -> Json<Result<bool, TwoBError>> {
+    two_b.release_lease(LeaseId(lease)).into()
+}
+
+#[get("/get_leases")]
+async fn get_leases(two_b: &State<TwoBHandle>) -> Json<Vec<Lease>> {
+    two_b.leases().into()
+}
+
+/// Changes waiting for the wearer to confirm them.
+#[get("/get_proposals")]
+async fn get_proposals(proposals: &State<Proposals>) -> Json<Vec<Proposal>> {
+    let mut confirmations = proposals.lock();
+    confirmations.expire(SystemTime::now());
+    confirmations.pending().into()
+}
+
+/// Confirmed, cancelled and expired proposals, oldest first.
+#[get("/get_proposal_history")]
+async fn get_proposal_history(proposals: &State<Proposals>) -> Json<Vec<ResolvedProposal>> {
+    let mut confirmations = proposals.lock();
+    confirmations.expire(SystemTime::now());
+    confirmations.history().into()
+}
+
+/// Applies a pending proposal; the `X-Wearer-Token` header has to be the
+/// `--wearer-token`.
+#[post("/confirm_proposal?<id>")]
+async fn confirm_proposal(
+    two_b: Caller,
+    proposals: &State<Proposals>,
+    id: u64,
+    token: WearerToken,
+) -> Json<Result<(), TwoBError>> {
+    if token.0.is_none() || proposals.wearer_token != token.0 {
+        return Json(Err(TwoBError::PolicyViolation(
+            "Only the wearer can confirm a proposal".into(),
+        )));
+    }
+    let proposal =
+        proposals
+            .lock()
+            .confirm(ProposalId(id), &client_name(&two_b), SystemTime::now());
+    match proposal {
+        Some(proposal) => apply(&two_b, proposal.change).await.into(),
+        None => Json(Err(TwoBError::PolicyViolation(format!(
+            "Proposal {} is not pending",
+            id
+        )))),
+    }
+}
+
+/// Withdraws a pending proposal, recording the client that did. Returns whether it was
+/// still pending.
+#[post("/cancel_proposal?<id>")]
+async fn cancel_proposal(two_b: Caller, proposals: &State<Proposals>, id: u64) -> Json<bool> {
+    proposals
+        .lock()
+        .cancel(ProposalId(id), &client_name(&two_b), SystemTime::now())
+        .is_some()
+        .into()
+}
+
+/// Kills the 2B ahead of everything queued and refuses raising levels until
+/// `/api/reset_emergency_stop` is called.
+#[get("/emergency_stop")]
+async fn emergency_stop(two_b: Caller) -> Json<Result<(), TwoBError>> {
+    two_b
+        .engage_emergency_stop_async(StopSource::Api)
+        .await
+        .into()
+}
+
+#[get("/reset_emergency_stop?<reason>")]
+async fn reset_emergency_stop(two_b: Caller, reason: &str) -> Json<Result<(), TwoBError>> {
+    two_b.reset_emergency_stop(reason).into()
+}
+
+#[get("/get_emergency_stop")]
+async fn get_emergency_stop(two_b: &State<TwoBHandle>) -> Json<Option<EmergencyStop>> {
+    two_b.emergency_stop().into()
+}
+
+/// Volts, estimated charge and discharge rate, `null` without `--battery`.
+#[get("/get_battery_status")]
+async fn get_battery_status(two_b: &State<TwoBHandle>) -> Json<Option<BatteryStatus>> {
+    two_b.battery_status().into()
+}
+
+/// Time counted in the current session, what is left of it and of the cooldown,
+/// `null` without `--session-limit`.
+#[get("/get_session")]
+async fn get_session(two_b: &State<TwoBHandle>) -> Json<Option<SessionStatus>> {
+    two_b.session_status().into()
+}
+
+fn no_audit_log() -> TwoBError {
+    TwoBError::Unsupported("The server runs without --audit-log".into())
+}
+
+/// Audit records from sequence number `since` on, at most `limit`, 100 by default.
+#[get("/get_audit_log?<since>&<limit>")]
+async fn get_audit_log(
+    two_b: &State<TwoBHandle>,
+    since: Option<u64>,
+    limit: Option<usize>,
+) -> Json<Result<Vec<AuditRecord>, TwoBError>> {
+    let path = match two_b.audit_log_path() {
+        Some(path) => path,
+        None => return Json(Err(no_audit_log())),
+    };
+    AuditLog::read(path)
+        .map(|records| {
+            records
+                .into_iter()
+                .filter(|record| record.seq >= since.unwrap_or(0))
+                .take(limit.unwrap_or(100))
+                .collect()
+        })
+        .into()
+}
+
+/// Checks the hash chain of the audit log and returns the number of records.
+#[get("/verify_audit_log")]
+async fn verify_audit_log(two_b: &State<TwoBHandle>) -> Json<Result<usize, TwoBError>> {
+    match two_b.audit_log_path() {
+        Some(path) => AuditLog::verify(path).map(|records| records.len()).into(),
+        None => Json(Err(no_audit_log())),
+    }
 }
 
 #[get("/get_mode")]
-fn get_mode(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<TwoBMode> {
-    two_b.lock().unwrap().get_mode().into()
+async fn get_mode(two_b: &State<TwoBHandle>) -> Json<TwoBMode> {
+    two_b.state().mode.into()
 }
 
 #[get("/get_power")]
-fn get_power(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<TwoBPower> {
-    two_b.lock().unwrap().get_power().into()
+async fn get_power(two_b: &State<TwoBHandle>) -> Json<TwoBPower> {
+    two_b.state().power.into()
 }
 
 #[get("/get_bias")]
-fn get_bias(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<TwoBBias> {
-    two_b.lock().unwrap().get_bias().into()
+async fn get_bias(two_b: &State<TwoBHandle>) -> Json<TwoBBias> {
+    two_b.state().bias.into()
 }
 
 #[get("/get_joined_channels")]
-fn get_joined_channels(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<bool> {
-    two_b.lock().unwrap().get_joined_channels().into()
+async fn get_joined_channels(two_b: &State<TwoBHandle>) -> Json<bool> {
+    two_b.state().joined_channels.into()
 }
 
 #[get("/get_map")]
-fn get_map(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<TwoBMap> {
-    two_b.lock().unwrap().get_map().into()
+async fn get_map(two_b: &State<TwoBHandle>) -> Json<TwoBMap> {
+    two_b.state().map.into()
 }
 
 #[get("/get_ramp")]
-fn get_ramp(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<TwoBRamp> {
-    two_b.lock().unwrap().get_ramp().into()
+async fn get_ramp(two_b: &State<TwoBHandle>) -> Json<TwoBRamp> {
+    two_b.state().ramp.into()
 }
 
 #[get("/get_warp")]
-fn get_warp(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<TwoBWarp> {
-    two_b.lock().unwrap().get_warp().into()
+async fn get_warp(two_b: &State<TwoBHandle>) -> Json<TwoBWarp> {
+    two_b.state().warp.into()
 }
 
 #[get("/get_battery")]
-fn get_battery(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<u16> {
-    two_b.lock().unwrap().get_battery().into()
+async fn get_battery(two_b: &State<TwoBHandle>) -> Json<u16> {
+    two_b.state().battery.into()
 }
 
 #[get("/get_channel?<id>")]
-fn get_channel(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>, id: &str) -> Json<Result<u8, TwoBError>> {
+async fn get_channel(two_b: &State<TwoBHandle>, id: &str) -> Json<Result<u8, TwoBError>> {
     if let Ok(channel) = TwoBChannel::from_str(id) {
-        Ok(two_b.lock().unwrap().get_channel(channel)).into()
+        Ok(TwoB::get_channel(two_b.inner(), channel)).into()
     } else {
         Json(Err(TwoBError::ParserError("Invalid Channel ID!".into())))
     }
 }
 
 #[get("/get_version")]
-fn get_version(two_b: &State<Arc<Mutex<Box<dyn TwoB>>>>) -> Json<String> {
-    two_b.lock().unwrap().get_version().into()
+async fn get_version(two_b: &State<TwoBHandle>) -> Json<String> {
+    two_b.version().into()
+}
+
+#[get("/get_firmware_version")]
+async fn get_firmware_version(
+    two_b: &State<TwoBHandle>,
+) -> Json<Result<FirmwareVersion, TwoBError>> {
+    two_b.version().parse().into()
+}
+
+#[get("/get_capabilities")]
+async fn get_capabilities(two_b: &State<TwoBHandle>) -> Json<Capabilities> {
+    two_b.capabilities().into()
+}
+
+/// Streams every `TwoBEvent` as a server-sent event.
+#[get("/events")]
+fn events(events: &State<Sender<TwoBEvent>>, mut shutdown: Shutdown) -> EventStream![] {
+    let mut receiver = events.subscribe();
+    EventStream! {
+        loop {
+            let event = select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+            yield Event::json(&event);
+        }
+    }
 }
 
 use clap::Parser;
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Serial port of 2B
+    /// Serial port of 2B, "tcp://host:port" for a raw TCP serial bridge or
+    /// "rfc2217://host:port" for a telnet COM port server
     #[clap(short, long)]
     serial_port: Option<String>,
+
+    /// USB serial number of the 2B to use when detecting it
+    #[clap(long)]
+    serial_number: Option<String>,
+
+    /// List every detected 2B and exit
+    #[clap(long)]
+    list: bool,
+
+    /// JSON file with the safety policy every command is checked against
+    #[clap(long)]
+    policy: Option<String>,
+
+    /// Seconds the 2B has to be idle before its state is refreshed. The state isn't
+    /// polled without it
+    #[clap(long)]
+    poll_interval: Option<u64>,
+
+    /// Levels per second channel A and B rise at most, 0 lets levels jump
+    #[clap(long, default_value = "0")]
+    max_rate: f64,
+
+    /// Level channel A and B are lowered to when the last watchdog lease runs out,
+    /// instead of engaging the emergency stop
+    #[clap(long)]
+    safe_level: Option<u8>,
+
+    /// What the 2B is left with when the server stops or panics: Kill, Reset or Leave
+    #[clap(long, default_value = "Kill")]
+    on_exit: OnExit,
+
+    /// JSON file with the battery model and low-battery thresholds to monitor the
+    /// battery with
+    #[clap(long)]
+    battery: Option<String>,
+
+    /// Minutes channel A and B may be above zero before the 2B is lowered and killed,
+    /// 0 disables the session limit
+    #[clap(long, default_value = "0")]
+    session_limit: u64,
+
+    /// Minutes after the session limit before levels can be raised again
+    #[clap(long, default_value = "10")]
+    cooldown: u64,
+
+    /// Level above which raising channel A or B has to be confirmed by the wearer
+    #[clap(long, requires = "wearer-token")]
+    confirm_above: Option<u8>,
+
+    /// Rise of channel A or B in one request above which it has to be confirmed by the
+    /// wearer
+    #[clap(long, requires = "wearer-token")]
+    confirm_delta: Option<u8>,
+
+    /// Seconds a proposal waits for the wearer to confirm it
+    #[clap(long, default_value = "60")]
+    proposal_timeout: u64,
+
+    /// Token the wearer confirms proposals with
+    #[clap(long)]
+    wearer_token: Option<String>,
+
+    /// File every command is appended to with where it came from, hash-chained so
+    /// edits are detectable
+    #[clap(long)]
+    audit_log: Option<String>,
+
+    /// Check the hash chain of an audit log, list its records and exit
+    #[clap(long)]
+    verify_audit_log: Option<String>,
 }
 
-#[launch]
-fn rocket() -> _ {
-    let two_b: Box<dyn TwoB>;
-    let args = Args::parse();
-    if let Some(path) = args.serial_port {
-        if path == "virtual" {
-            two_b = Box::new(VirtualTwoB::new().unwrap());
-        } else {
-            two_b = Box::<USBTwoB>::new(
-                USBTwoB::try_from(path.as_str()).unwrap_or_else(|_| panic!("No 2B found on serialport: {}", path))
-            );
-        }
-    } else {
-        two_b = Box::new(USBTwoB::new().expect("No 2B found"));
-    }
+fn build(mut two_b: TwoBHandle, proposals: Proposals) -> Rocket<Build> {
+    let (events, _) = broadcast::channel(1024);
+    let sender = events.clone();
+    two_b.subscribe(Box::new(move |event| {
+        let _ = sender.send(event.clone());
+    }));
     rocket::build()
-        .manage(Arc::new(Mutex::new(two_b)))
+        .manage(two_b)
+        .manage(events)
+        .manage(proposals)
         .mount(
             "/api",
             routes![
@@ -233,7 +683,21 @@ fn rocket() -> _ {
                 decrement_channel,
                 set_channel,
                 set_state,
+                execute,
                 get_state,
+                get_cached_state,
+                get_ramps,
+                register_lease,
+                heartbeat,
+                release_lease,
+                get_leases,
+                get_proposals,
+                get_proposal_history,
+                confirm_proposal,
+                cancel_proposal,
+                emergency_stop,
+                reset_emergency_stop,
+                get_emergency_stop,
                 get_mode,
                 get_power,
                 get_bias,
@@ -242,9 +706,529 @@ fn rocket() -> _ {
                 get_ramp,
                 get_warp,
                 get_battery,
+                get_battery_status,
+                get_session,
+                get_audit_log,
+                verify_audit_log,
                 get_channel,
-                get_version
+                get_version,
+                get_firmware_version,
+                get_capabilities,
+                get_queue_metrics,
+                events
             ],
         )
         .mount("/api/get_state", routes![get_state])
 }
+
+/// Drops the 2B once Rocket shut down gracefully, e.g. on SIGINT or SIGTERM, so it
+/// is left as `--on-exit` says.
+fn shutdown_on_exit() -> AdHoc {
+    AdHoc::on_shutdown("Stop the 2B", |rocket| {
+        Box::pin(async move {
+            let two_b = rocket.state::<TwoBHandle>().unwrap().clone();
+            let _ = rocket::tokio::task::spawn_blocking(move || two_b.shutdown()).await;
+        })
+    })
+}
+
+/// Engages the emergency stop whenever the server receives SIGUSR1.
+#[cfg(unix)]
+fn emergency_stop_on_signal() -> AdHoc {
+    use rocket::tokio::signal::unix::{signal, SignalKind};
+    AdHoc::on_liftoff("Emergency stop on SIGUSR1", |rocket| {
+        Box::pin(async move {
+            let two_b = rocket
+                .state::<TwoBHandle>()
+                .unwrap()
+                .with_origin(Origin::Signal);
+            let mut signals =
+                signal(SignalKind::user_defined1()).expect("Cannot listen for SIGUSR1");
+            rocket::tokio::spawn(async move {
+                while signals.recv().await.is_some() {
+                    if let Err(e) = two_b.engage_emergency_stop_async(StopSource::Signal).await {
+                        eprintln!("Emergency stop failed: {:?}", e);
+                    }
+                }
+            });
+        })
+    })
+}
+
+/// Carries out a line entered on the terminal of the server: `stop` engages the
+/// emergency stop, `reset <reason>` releases it.
+fn console_command(two_b: &TwoBHandle, line: &str) -> Result<(), TwoBError> {
+    match line.trim().split_once(' ') {
+        None if line.trim() == "stop" => two_b.engage_emergency_stop(StopSource::Cli),
+        Some(("reset", reason)) => two_b.reset_emergency_stop(reason),
+        _ => Err(TwoBError::ParserError(format!(
+            "Unknown command {:?}, expected \"stop\" or \"reset <reason>\"",
+            line.trim()
+        ))),
+    }
+}
+
+/// Takes `console_command`s from the terminal the server runs in.
+fn emergency_stop_on_input() -> AdHoc {
+    AdHoc::on_liftoff("Emergency stop from the terminal", |rocket| {
+        Box::pin(async move {
+            let two_b = rocket
+                .state::<TwoBHandle>()
+                .unwrap()
+                .with_origin(Origin::Cli);
+            std::thread::Builder::new()
+                .name("estim2b-console".into())
+                .spawn(move || {
+                    for line in std::io::stdin().lines().map_while(Result::ok) {
+                        match console_command(&two_b, &line) {
+                            Ok(()) => println!("Emergency stop: {:?}", two_b.emergency_stop()),
+                            Err(e) => eprintln!("{:?}", e),
+                        }
+                    }
+                })
+                .expect("Cannot read commands from the terminal");
+        })
+    })
+}
+
+#[launch]
+fn rocket() -> _ {
+    let mut two_b: Box<dyn TwoB>;
+    let args = Args::parse();
+    if args.list {
+        for found in discover(&DiscoveryOptions::default()).expect("Cannot list serial ports") {
+            println!(
+                "{}\t{}\tbattery {}\t{} ms\t{}",
+                found.port,
+                found.version,
+                found.battery,
+                found.latency.as_millis(),
+                found
+                    .usb
+                    .and_then(|usb| usb.serial_number)
+                    .unwrap_or_default()
+            );
+        }
+        std::process::exit(0);
+    }
+    if let Some(path) = args.verify_audit_log {
+        match AuditLog::verify(&path) {
+            Ok(records) => {
+                for record in &records {
+                    println!(
+                        "{}\t{}\t{:?}\t{:?}\t{:?}",
+                        record.seq,
+                        record
+                            .timestamp
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs(),
+                        record.origin,
+                        record.action,
+                        record.error
+                    );
+                }
+                println!("{} records verified", records.len());
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("{}: {:?}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = args.serial_port {
+        if path == "virtual" {
+            two_b = Box::new(VirtualTwoB::new().unwrap());
+        } else {
+            let mut usb = USBTwoB::try_from(path.as_str())
+                .unwrap_or_else(|_| panic!("No 2B found on serialport: {}", path));
+            usb.set_on_exit(args.on_exit);
+            two_b = Box::new(usb);
+        }
+    } else {
+        let options = DiscoveryOptions {
+            serial_number: args.serial_number,
+            ..DiscoveryOptions::default()
+        };
+        let mut usb = USBTwoB::discover(&options).expect("No 2B found");
+        usb.set_on_exit(args.on_exit);
+        two_b = Box::new(usb);
+    }
+    if let Some(path) = args.policy {
+        let policy = SafetyPolicy::from_file(&path)
+            .unwrap_or_else(|e| panic!("Cannot read policy {}: {:?}", path, e));
+        let mut safe = SafeTwoB::from_box(two_b, policy);
+        safe.enforce()
+            .unwrap_or_else(|e| panic!("Cannot apply policy {}: {:?}", path, e));
+        two_b = Box::new(safe);
+    }
+    let two_b = TwoBHandle::from_box(two_b);
+    two_b.exit_on_panic();
+    if let Some(path) = args.audit_log {
+        let log = AuditLog::open(&path)
+            .unwrap_or_else(|e| panic!("Cannot open audit log {}: {:?}", path, e));
+        two_b
+            .set_audit_log(Some(log))
+            .expect("Cannot configure the audit log");
+    }
+    if let Some(seconds) = args.poll_interval.filter(|&seconds| seconds > 0) {
+        two_b
+            .set_poll_interval(Some(Duration::from_secs(seconds)))
+            .expect("Cannot configure polling");
+    }
+    if args.max_rate > 0.0 {
+        two_b
+            .set_slew_config(SlewConfig {
+                max_rate: Some(args.max_rate),
+            })
+            .expect("Cannot configure the slew limiter");
+    }
+    if let Some(path) = args.battery {
+        let config = BatteryConfig::from_file(&path)
+            .unwrap_or_else(|e| panic!("Cannot read battery config {}: {:?}", path, e));
+        two_b
+            .set_battery_config(Some(config))
+            .expect("Cannot configure the battery monitor");
+    }
+    if args.session_limit > 0 {
+        two_b
+            .set_session_config(Some(SessionConfig {
+                max_active: Duration::from_secs(args.session_limit * 60),
+                cooldown: Duration::from_secs(args.cooldown * 60),
+                ..SessionConfig::default()
+            }))
+            .expect("Cannot configure the session governor");
+    }
+    let action = match args.safe_level {
+        Some(level) => ExpiryAction::SafeLevels { a: level, b: level },
+        None => ExpiryAction::EmergencyStop,
+    };
+    two_b
+        .set_watchdog_config(WatchdogConfig { action })
+        .expect("Cannot configure the watchdog");
+    let proposals = Proposals {
+        confirmations: Mutex::new(Confirmations::new(ConfirmationConfig {
+            max_level: args.confirm_above,
+            max_delta: args.confirm_delta,
+            expiry: Duration::from_secs(args.proposal_timeout),
+            ..ConfirmationConfig::default()
+        })),
+        wearer_token: args.wearer_token,
+    };
+    let rocket = build(two_b, proposals)
+        .attach(shutdown_on_exit())
+        .attach(emergency_stop_on_input());
+    #[cfg(unix)]
+    let rocket = rocket.attach(emergency_stop_on_signal());
+    rocket
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    #[test]
+    fn console_engages_and_resets_the_emergency_stop() {
+        let two_b = TwoBHandle::new(VirtualTwoB::new().unwrap()).with_origin(Origin::Cli);
+        assert!(console_command(&two_b, "halt").is_err());
+        assert!(console_command(&two_b, "stop\n").is_ok());
+        assert_eq!(two_b.emergency_stop().unwrap().source, StopSource::Cli);
+        assert!(console_command(&two_b, "reset").is_err());
+        assert!(console_command(&two_b, "reset electrodes checked").is_ok());
+        assert_eq!(two_b.emergency_stop(), None);
+    }
+
+    #[test]
+    fn emergency_stop_latches() {
+        let two_b = TwoBHandle::new(VirtualTwoB::new().unwrap());
+        let client = Client::tracked(build(two_b, Proposals::default())).unwrap();
+        let call = |path: &str| {
+            client
+                .get(path)
+                .dispatch()
+                .into_json::<Result<(), TwoBError>>()
+                .unwrap()
+        };
+
+        assert!(call("/api/set_channel?id=A&value=30").is_ok());
+        assert!(call("/api/emergency_stop").is_ok());
+        let stop = client
+            .get("/api/get_emergency_stop")
+            .dispatch()
+            .into_json::<Option<EmergencyStop>>()
+            .unwrap();
+        assert_eq!(stop.unwrap().source, StopSource::Api);
+        assert!(matches!(
+            call("/api/set_channel?id=A&value=10"),
+            Err(TwoBError::PolicyViolation(_))
+        ));
+        assert!(call("/api/set_mode?mode=Milk").is_ok());
+        let state = client
+            .get("/api/get_state")
+            .dispatch()
+            .into_json::<ReportedState>()
+            .unwrap();
+        assert_eq!(state.state.channel_a, 0);
+        assert_eq!(state.emergency_stop.unwrap().source, StopSource::Api);
+        assert!(call("/api/reset_emergency_stop?reason=").is_err());
+        assert!(call("/api/reset_emergency_stop?reason=checked%20electrodes").is_ok());
+        assert!(call("/api/set_channel?id=A&value=10").is_ok());
+    }
+
+    #[test]
+    fn session_in_cached_and_reported_state() {
+        let two_b = TwoBHandle::new(VirtualTwoB::new().unwrap());
+        two_b
+            .set_session_config(Some(SessionConfig::default()))
+            .unwrap();
+        let client = Client::tracked(build(two_b, Proposals::default())).unwrap();
+        client.get("/api/set_channel?id=A&value=30").dispatch();
+
+        let cached = client
+            .get("/api/get_cached_state")
+            .dispatch()
+            .into_json::<CachedState>()
+            .unwrap();
+        let session = cached.session.unwrap();
+        assert!(session.active);
+        assert!(session.remaining <= SessionConfig::default().max_active);
+        assert_eq!(session.cooldown_remaining, None);
+        let session = client
+            .get("/api/get_session")
+            .dispatch()
+            .into_json::<Option<SessionStatus>>()
+            .unwrap();
+        assert!(session.unwrap().active);
+        let state = client
+            .get("/api/get_state")
+            .dispatch()
+            .into_json::<ReportedState>()
+            .unwrap();
+        assert_eq!(state.state.channel_a, 30);
+        assert!(state.session.unwrap().active);
+    }
+
+    #[test]
+    fn raises_above_the_threshold_wait_for_the_wearer() {
+        let two_b = TwoBHandle::new(VirtualTwoB::new().unwrap());
+        let proposals = Proposals {
+            confirmations: Mutex::new(Confirmations::new(ConfirmationConfig {
+                max_level: Some(50),
+                ..ConfirmationConfig::default()
+            })),
+            wearer_token: Some("wearer".into()),
+        };
+        let client = Client::tracked(build(two_b, proposals)).unwrap();
+        let call = |path: &str| {
+            client
+                .get(path)
+                .dispatch()
+                .into_json::<Result<(), TwoBError>>()
+                .unwrap()
+        };
+
+        assert!(call("/api/set_channel?id=A&value=40").is_ok());
+        let proposal = match call("/api/set_channel?id=A&value=90") {
+            Err(TwoBError::ConfirmationRequired(proposal)) => proposal,
+            other => panic!("Not held: {:?}", other),
+        };
+        let pending = client
+            .get("/api/get_proposals")
+            .dispatch()
+            .into_json::<Vec<Proposal>>()
+            .unwrap();
+        assert_eq!(pending, vec![proposal.clone()]);
+        let channel = || {
+            client
+                .get("/api/get_channel?id=A")
+                .dispatch()
+                .into_json::<Result<u8, TwoBError>>()
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(channel(), 40);
+
+        let confirm = |id: u64, token: Option<&str>| {
+            let mut request = client.post(format!("/api/confirm_proposal?id={}", id));
+            if let Some(token) = token {
+                request = request.header(Header::new("X-Wearer-Token", token.to_string()));
+            }
+            request
+                .dispatch()
+                .into_json::<Result<(), TwoBError>>()
+                .unwrap()
+        };
+        let id = proposal.id.0;
+        assert!(matches!(
+            confirm(id, Some("guess")),
+            Err(TwoBError::PolicyViolation(_))
+        ));
+        assert!(matches!(
+            confirm(id, None),
+            Err(TwoBError::PolicyViolation(_))
+        ));
+        assert!(confirm(id, Some("wearer")).is_ok());
+        assert_eq!(channel(), 90);
+        assert!(matches!(
+            confirm(id, Some("wearer")),
+            Err(TwoBError::PolicyViolation(_))
+        ));
+        assert!(matches!(
+            confirm(id + 100, Some("wearer")),
+            Err(TwoBError::PolicyViolation(_))
+        ));
+
+        // Joining the channels raises B to A.
+        match call("/api/set_joined_channels?enable=true") {
+            Err(TwoBError::ConfirmationRequired(_)) => {}
+            other => panic!("Not held: {:?}", other),
+        }
+
+        let second = match call("/api/set_channel?id=B&value=60") {
+            Err(TwoBError::ConfirmationRequired(proposal)) => proposal,
+            other => panic!("Not held: {:?}", other),
+        };
+        let cancel = || {
+            client
+                .post(format!("/api/cancel_proposal?id={}", second.id.0))
+                .header(Header::new("X-Client-Token", "tablet"))
+                .remote("127.0.0.1:9000".parse().unwrap())
+                .dispatch()
+                .into_json::<bool>()
+                .unwrap()
+        };
+        assert!(cancel());
+        assert!(!cancel());
+        let history = client
+            .get("/api/get_proposal_history")
+            .dispatch()
+            .into_json::<Vec<ResolvedProposal>>()
+            .unwrap();
+        assert_eq!(
+            history.iter().map(|r| r.outcome).collect::<Vec<_>>(),
+            vec![ProposalOutcome::Confirmed, ProposalOutcome::Cancelled]
+        );
+        assert_eq!(
+            history[1].resolved_by,
+            Some(format!("127.0.0.1 ({})", token_fingerprint("tablet")))
+        );
+    }
+
+    /// Removes the file once dropped, also when a test fails.
+    struct RemoveOnDrop(std::path::PathBuf);
+
+    impl Drop for RemoveOnDrop {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn commands_are_audited_with_their_client() {
+        let path = std::env::temp_dir().join(format!("estim2b-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _log = RemoveOnDrop(path.clone());
+        let two_b = TwoBHandle::new(VirtualTwoB::new().unwrap());
+        two_b
+            .set_audit_log(Some(AuditLog::open(&path).unwrap()))
+            .unwrap();
+        let client = Client::tracked(build(two_b, Proposals::default())).unwrap();
+
+        client
+            .get("/api/set_channel?id=A&value=30")
+            .header(Header::new("X-Client-Token", "tablet"))
+            .remote("127.0.0.1:9000".parse().unwrap())
+            .dispatch();
+        client.get("/api/emergency_stop").dispatch();
+        let records = client
+            .get("/api/get_audit_log?since=1")
+            .dispatch()
+            .into_json::<Result<Vec<AuditRecord>, TwoBError>>()
+            .unwrap()
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].action,
+            AuditedAction::EmergencyStop(StopSource::Api)
+        );
+        let first = &AuditLog::read(&path).unwrap()[0];
+        assert_eq!(
+            first.origin,
+            Origin::Http {
+                address: Some("127.0.0.1".into()),
+                token: Some(token_fingerprint("tablet")),
+            }
+        );
+        assert_eq!(first.state.channel_a, 30);
+        let verified = client
+            .get("/api/verify_audit_log")
+            .dispatch()
+            .into_json::<Result<usize, TwoBError>>()
+            .unwrap();
+        assert_eq!(verified.unwrap(), 2);
+    }
+
+    #[test]
+    fn watchdog_leases() {
+        let two_b = TwoBHandle::new(VirtualTwoB::new().unwrap());
+        let client = Client::tracked(build(two_b, Proposals::default())).unwrap();
+
+        let lease = client
+            .get("/api/register_lease?name=browser&timeout_ms=60000")
+            .dispatch()
+            .into_json::<Result<LeaseId, TwoBError>>()
+            .unwrap()
+            .unwrap();
+        let leases = client
+            .get("/api/get_leases")
+            .dispatch()
+            .into_json::<Vec<Lease>>()
+            .unwrap();
+        assert_eq!(leases[0].id, lease);
+        assert_eq!(leases[0].timeout, Duration::from_secs(60));
+        for (path, alive) in [("heartbeat", true), ("release_lease", true), ("heartbeat", false)] {
+            let response = client
+                .get(format!("/api/{}?lease={}", path, lease.0))
+                .dispatch()
+                .into_json::<Result<bool, TwoBError>>()
+                .unwrap();
+            assert_eq!(response.unwrap(), alive);
+        }
+    }
+
+    #[test]
+    fn api_against_emulated_2b() {
+        let pty = Emulator::new().unwrap().spawn_pty().unwrap();
+        let two_b = TwoBHandle::new(USBTwoB::try_from(pty.path()).unwrap());
+        let client = Client::tracked(build(two_b, Proposals::default())).unwrap();
+
+        let response = client.get("/api/set_mode?mode=Flo").dispatch();
+        assert!(response.into_json::<Result<(), TwoBError>>().unwrap().is_ok());
+        let response = client.get("/api/set_channel?id=A&value=10").dispatch();
+        assert!(response.into_json::<Result<(), TwoBError>>().unwrap().is_ok());
+        let response = client.get("/api/refresh_state").dispatch();
+        assert!(response.into_json::<Result<(), TwoBError>>().unwrap().is_ok());
+
+        let state = client.get("/api/").dispatch().into_json::<TwoBState>().unwrap();
+        assert_eq!(state.mode, TwoBMode::Flo);
+        assert_eq!(state.channel_a, 10);
+
+        let commands = vec![
+            TwoBCommand::SetChannel(TwoBChannel::B, 20),
+            TwoBCommand::IncrementChannel(TwoBChannel::B),
+            TwoBCommand::SetMode(TwoBMode::Milk),
+        ];
+        let response = client.post("/api/execute").json(&commands).dispatch();
+        assert!(response.into_json::<Result<(), TwoBError>>().unwrap().is_ok());
+        let state = client.get("/api/").dispatch().into_json::<TwoBState>().unwrap();
+        assert_eq!(state.mode, TwoBMode::Milk);
+        assert_eq!(state.channel_b, 21);
+        assert_eq!(
+            client.get("/api/get_version").dispatch().into_json::<String>().unwrap(),
+            "2.122B"
+        );
+    }
+}